@@ -0,0 +1,60 @@
+// Fixed-point amount helpers
+//
+// Token amounts are passed to the contracts as integers scaled by the
+// token's decimals (7 for Stellar assets), so 12.5 XLM is 125_000_000.
+// These helpers convert between that form and a (whole, fractional) pair.
+
+// Decimals used by Stellar Classic and Soroban token amounts
+pub const STELLAR_DECIMALS: u32 = 7;
+
+// Largest amount considered sane: 10^12 whole tokens. Anything larger is
+// almost certainly a double-scaled input.
+pub const MAX_SANE_AMOUNT: i128 = to_scaled(1_000_000_000_000, 0, STELLAR_DECIMALS);
+
+// Build a scaled amount from its whole part and its fractional digits,
+// e.g. to_scaled(12, 5_000_000, 7) == 125_000_000. `frac` must be below
+// 10^decimals, and decimals is limited to 9 so the fraction fits a u32.
+pub const fn to_scaled(whole: i128, frac: u32, decimals: u32) -> i128 {
+    let scale = scale(decimals);
+    if frac as i128 >= scale {
+        panic!("Fraction exceeds precision");
+    }
+
+    if whole < 0 {
+        whole * scale - frac as i128
+    } else {
+        whole * scale + frac as i128
+    }
+}
+
+// Split a scaled amount into its whole part and fractional digits; the
+// inverse of `to_scaled`
+pub const fn from_scaled(value: i128, decimals: u32) -> (i128, u32) {
+    let scale = scale(decimals);
+    let whole = value / scale;
+    let frac = (value % scale).unsigned_abs() as u32;
+    (whole, frac)
+}
+
+// Rescale an oracle price from `from_decimals` to `to_decimals`, so prices
+// from feeds reporting different precision can be compared. Scaling up
+// saturates; scaling down truncates towards zero.
+pub const fn normalize_price(price: i128, from_decimals: u32, to_decimals: u32) -> i128 {
+    if from_decimals <= to_decimals {
+        price.saturating_mul(10i128.pow(to_decimals - from_decimals))
+    } else {
+        price / 10i128.pow(from_decimals - to_decimals)
+    }
+}
+
+// Whether an amount is positive and below MAX_SANE_AMOUNT
+pub const fn is_sane_amount(amount: i128) -> bool {
+    amount > 0 && amount <= MAX_SANE_AMOUNT
+}
+
+const fn scale(decimals: u32) -> i128 {
+    if decimals > 9 {
+        panic!("Too many decimals");
+    }
+    10i128.pow(decimals)
+}
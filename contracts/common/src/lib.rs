@@ -0,0 +1,9 @@
+#![no_std]
+
+// Shared helpers used across the StellarGuard contracts
+pub mod decimal;
+pub mod ownable;
+pub mod pausable;
+pub mod positions;
+pub mod upgradeable;
+mod test;
@@ -0,0 +1,82 @@
+use soroban_sdk::{contractevent, contracttype, Address, Env};
+
+// Admin ownership with a two-step transfer: the current admin nominates a
+// successor, who must accept before the change takes effect. A mistyped
+// address therefore never locks the contract.
+
+#[contracttype]
+enum OwnableKey {
+    Admin,
+    PendingAdmin,
+}
+
+#[contractevent(topics = ["admin", "proposed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminProposed {
+    pub current_admin: Address,
+    pub new_admin: Address,
+}
+
+#[contractevent(topics = ["admin", "accepted"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminAccepted {
+    pub previous_admin: Address,
+    pub new_admin: Address,
+}
+
+pub fn has_admin(env: &Env) -> bool {
+    env.storage().instance().has(&OwnableKey::Admin)
+}
+
+pub fn get_admin(env: &Env) -> Address {
+    env.storage()
+        .instance()
+        .get(&OwnableKey::Admin)
+        .unwrap_or_else(|| panic!("Not initialized"))
+}
+
+// False before initialization rather than panicking, so UIs can call it
+// unconditionally
+pub fn is_admin(env: &Env, who: &Address) -> bool {
+    env.storage().instance().get::<_, Address>(&OwnableKey::Admin).as_ref() == Some(who)
+}
+
+// Set the admin directly. Intended for contract initialization only.
+pub fn set_admin(env: &Env, admin: &Address) {
+    env.storage().instance().set(&OwnableKey::Admin, admin);
+}
+
+// Require the stored admin's authorization and return it
+pub fn require_admin(env: &Env) -> Address {
+    let admin = get_admin(env);
+    admin.require_auth();
+    admin
+}
+
+pub fn get_pending_admin(env: &Env) -> Option<Address> {
+    env.storage().instance().get(&OwnableKey::PendingAdmin)
+}
+
+// Nominate a new admin. Replaces any earlier nomination.
+pub fn propose_admin(env: &Env, current_admin: &Address, new_admin: &Address) {
+    if require_admin(env) != *current_admin {
+        panic!("Unauthorized");
+    }
+    env.storage().instance().set(&OwnableKey::PendingAdmin, new_admin);
+    AdminProposed { current_admin: current_admin.clone(), new_admin: new_admin.clone() }.publish(env);
+}
+
+// Complete a transfer; must be called and authorized by the nominated admin
+pub fn accept_admin(env: &Env, new_admin: &Address) {
+    match get_pending_admin(env) {
+        None => panic!("No pending admin"),
+        Some(pending) if pending != *new_admin => panic!("Not the pending admin"),
+        Some(_) => {}
+    }
+    new_admin.require_auth();
+    
+    let previous_admin = get_admin(env);
+    set_admin(env, new_admin);
+    env.storage().instance().remove(&OwnableKey::PendingAdmin);
+    AdminAccepted { previous_admin, new_admin: new_admin.clone() }.publish(env);
+}
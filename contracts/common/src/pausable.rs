@@ -0,0 +1,34 @@
+use soroban_sdk::{contractevent, contracttype, Address, Env};
+
+use crate::ownable;
+
+#[contracttype]
+enum PausableKey {
+    Paused,
+}
+
+#[contractevent(topics = ["contract", "paused"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractPaused {
+    pub paused: bool,
+}
+
+pub fn is_paused(env: &Env) -> bool {
+    env.storage().instance().get(&PausableKey::Paused).unwrap_or(false)
+}
+
+// Guard for entry points that must not run while paused
+pub fn when_not_paused(env: &Env) {
+    if is_paused(env) {
+        panic!("Contract paused");
+    }
+}
+
+// Admin-only pause toggle; `admin` must be the stored admin
+pub fn set_paused(env: &Env, admin: &Address, paused: bool) {
+    if ownable::require_admin(env) != *admin {
+        panic!("Unauthorized");
+    }
+    env.storage().instance().set(&PausableKey::Paused, &paused);
+    ContractPaused { paused }.publish(env);
+}
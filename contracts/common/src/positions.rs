@@ -0,0 +1,26 @@
+use soroban_sdk::{contracttype, vec, Address, Env, Error, IntoVal, Symbol, Vec};
+
+// A user's order and loan ids across the stop-loss and liquidation contracts
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UserPositions {
+    pub order_ids: Vec<u64>,
+    pub loan_ids: Vec<u64>,
+}
+
+// Collect `user`'s positions from both contracts. A contract that is
+// missing, uninitialized or fails the call contributes no ids.
+pub fn get_user_positions(env: &Env, stop_loss: &Address, liquidation: &Address, user: &Address) -> UserPositions {
+    UserPositions {
+        order_ids: user_ids(env, stop_loss, "get_user_orders", user),
+        loan_ids: user_ids(env, liquidation, "get_user_loans", user),
+    }
+}
+
+fn user_ids(env: &Env, contract: &Address, fn_name: &str, user: &Address) -> Vec<u64> {
+    let args = vec![env, user.into_val(env)];
+    match env.try_invoke_contract::<Vec<u64>, Error>(contract, &Symbol::new(env, fn_name), args) {
+        Ok(Ok(ids)) => ids,
+        _ => Vec::new(env),
+    }
+}
@@ -0,0 +1,263 @@
+#![cfg(test)]
+
+use crate::decimal::*;
+use crate::positions::{get_user_positions, UserPositions};
+use crate::{ownable, pausable, upgradeable};
+use soroban_sdk::{
+    contract, contractimpl, testutils::{Address as _, Events}, vec, Address, BytesN, Env, IntoVal, Map, Symbol, Val, Vec
+};
+
+#[contract]
+struct TestContract;
+
+#[contract]
+struct MockStopLoss;
+
+#[contractimpl]
+impl MockStopLoss {
+    pub fn get_user_orders(env: Env, _user: Address) -> Vec<u64> {
+        vec![&env, 3, 7]
+    }
+}
+
+#[contract]
+struct MockLiquidation;
+
+#[contractimpl]
+impl MockLiquidation {
+    pub fn get_user_loans(env: Env, _user: Address) -> Vec<u64> {
+        vec![&env, 2]
+    }
+}
+
+// Stands in for a sub-contract that was deployed but never initialized
+#[contract]
+struct UninitializedLiquidation;
+
+#[contractimpl]
+impl UninitializedLiquidation {
+    pub fn get_user_loans(_env: Env, _user: Address) -> Vec<u64> {
+        panic!("Not initialized");
+    }
+}
+
+fn with_admin(env: &Env) -> (Address, Address) {
+    let contract_id = env.register(TestContract, ());
+    let admin = Address::generate(env);
+    env.as_contract(&contract_id, || ownable::set_admin(env, &admin));
+    (contract_id, admin)
+}
+
+#[test]
+fn test_to_scaled() {
+    assert_eq!(to_scaled(12, 5_000_000, 7), 125_000_000);
+    assert_eq!(to_scaled(0, 1_000_000, 7), 1_000_000);
+    assert_eq!(to_scaled(100, 0, 7), 1_000_000_000);
+    assert_eq!(to_scaled(3, 25, 2), 325);
+    assert_eq!(to_scaled(-1, 5, 1), -15);
+}
+
+#[test]
+fn test_round_trip() {
+    let cases: [(i128, u32, u32); 6] = [
+        (0, 0, 7),
+        (1, 1, 7),
+        (12, 5_000_000, 7),
+        (1_000_000_000_000, 9_999_999, 7),
+        (42, 0, 0),
+        (-7, 123_456_789, 9),
+    ];
+
+    for (whole, frac, decimals) in cases {
+        let scaled = to_scaled(whole, frac, decimals);
+        assert_eq!(from_scaled(scaled, decimals), (whole, frac));
+    }
+}
+
+#[test]
+fn test_is_sane_amount() {
+    assert!(is_sane_amount(1));
+    assert!(is_sane_amount(MAX_SANE_AMOUNT));
+    assert!(!is_sane_amount(0));
+    assert!(!is_sane_amount(-1));
+    assert!(!is_sane_amount(MAX_SANE_AMOUNT + 1));
+}
+
+#[test]
+#[should_panic(expected = "Fraction exceeds precision")]
+fn test_fraction_exceeds_precision() {
+    to_scaled(1, 10_000_000, 7);
+}
+
+#[test]
+fn test_normalize_price() {
+    assert_eq!(normalize_price(1_234_567, 7, 7), 1_234_567);
+    assert_eq!(normalize_price(1_234_567, 7, 14), 12_345_670_000_000);
+    assert_eq!(normalize_price(12_345_678_901_234, 14, 7), 1_234_567);
+    assert_eq!(normalize_price(-12_345_678_901_234, 14, 7), -1_234_567);
+    assert_eq!(normalize_price(i128::MAX / 10, 0, 2), i128::MAX);
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, admin) = with_admin(&env);
+    let new_admin = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        ownable::propose_admin(&env, &admin, &new_admin);
+    });
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(&env, "admin"), Symbol::new(&env, "proposed")).into_val(&env));
+    let data: Map<Symbol, Val> = data.into_val(&env);
+    let proposed: Address = data.get(Symbol::new(&env, "new_admin")).unwrap().into_val(&env);
+    assert_eq!(proposed, new_admin);
+
+    env.as_contract(&contract_id, || {
+        // Nothing changes until the nominee accepts
+        assert_eq!(ownable::get_admin(&env), admin);
+        assert_eq!(ownable::get_pending_admin(&env), Some(new_admin.clone()));
+
+        ownable::accept_admin(&env, &new_admin);
+        assert_eq!(ownable::get_admin(&env), new_admin);
+        assert_eq!(ownable::get_pending_admin(&env), None);
+    });
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(&env, "admin"), Symbol::new(&env, "accepted")).into_val(&env));
+}
+
+#[test]
+fn test_is_admin() {
+    let env = Env::default();
+    let contract_id = env.register(TestContract, ());
+    let admin = Address::generate(&env);
+    let other = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        assert!(!ownable::is_admin(&env, &admin));
+        ownable::set_admin(&env, &admin);
+        assert!(ownable::is_admin(&env, &admin));
+        assert!(!ownable::is_admin(&env, &other));
+    });
+}
+
+#[test]
+#[should_panic(expected = "No pending admin")]
+fn test_accept_admin_without_nomination() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, _) = with_admin(&env);
+
+    env.as_contract(&contract_id, || {
+        ownable::accept_admin(&env, &Address::generate(&env));
+    });
+}
+
+#[test]
+#[should_panic(expected = "Not the pending admin")]
+fn test_accept_admin_by_other_address() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, admin) = with_admin(&env);
+    let new_admin = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        ownable::propose_admin(&env, &admin, &new_admin);
+        ownable::accept_admin(&env, &Address::generate(&env));
+    });
+}
+
+#[test]
+#[should_panic]
+fn test_propose_admin_requires_admin_auth() {
+    let env = Env::default();
+    let (contract_id, admin) = with_admin(&env);
+    let new_admin = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        ownable::propose_admin(&env, &admin, &new_admin);
+    });
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_propose_admin_rejects_non_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, _) = with_admin(&env);
+    let new_admin = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        ownable::propose_admin(&env, &new_admin, &new_admin);
+    });
+}
+
+#[test]
+fn test_pause_guard() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, admin) = with_admin(&env);
+
+    env.as_contract(&contract_id, || {
+        assert!(!pausable::is_paused(&env));
+        pausable::when_not_paused(&env);
+        pausable::set_paused(&env, &admin, true);
+        assert!(pausable::is_paused(&env));
+    });
+
+    env.as_contract(&contract_id, || {
+        pausable::set_paused(&env, &admin, false);
+        pausable::when_not_paused(&env);
+    });
+}
+
+#[test]
+#[should_panic(expected = "Contract paused")]
+fn test_pause_guard_blocks_when_paused() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, admin) = with_admin(&env);
+
+    env.as_contract(&contract_id, || {
+        pausable::set_paused(&env, &admin, true);
+        pausable::when_not_paused(&env);
+    });
+}
+
+#[test]
+fn test_get_user_positions() {
+    let env = Env::default();
+    let user = Address::generate(&env);
+    let stop_loss = env.register(MockStopLoss, ());
+    let liquidation = env.register(MockLiquidation, ());
+
+    assert_eq!(
+        get_user_positions(&env, &stop_loss, &liquidation, &user),
+        UserPositions { order_ids: vec![&env, 3, 7], loan_ids: vec![&env, 2] }
+    );
+
+    // Failing sub-contracts contribute nothing
+    let uninitialized = env.register(UninitializedLiquidation, ());
+    let positions = get_user_positions(&env, &stop_loss, &uninitialized, &user);
+    assert_eq!(positions.order_ids, vec![&env, 3, 7]);
+    assert_eq!(positions.loan_ids.len(), 0);
+
+    let missing = Address::generate(&env);
+    let positions = get_user_positions(&env, &missing, &liquidation, &user);
+    assert_eq!(positions.order_ids.len(), 0);
+    assert_eq!(positions.loan_ids, vec![&env, 2]);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_upgrade_requires_stored_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (contract_id, _) = with_admin(&env);
+    let other = Address::generate(&env);
+
+    env.as_contract(&contract_id, || {
+        upgradeable::upgrade(&env, &other, 1, BytesN::from_array(&env, &[0; 32]));
+    });
+}
@@ -0,0 +1,21 @@
+use soroban_sdk::{contractevent, Address, BytesN, Env};
+
+use crate::ownable;
+
+#[contractevent(topics = ["contract", "upgraded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractUpgraded {
+    pub from_version: u32,
+    pub new_wasm_hash: BytesN<32>,
+}
+
+// Replace the calling contract's code with already-uploaded WASM, keeping
+// its id and storage. `admin` must be the stored admin and authorize it.
+pub fn upgrade(env: &Env, admin: &Address, from_version: u32, new_wasm_hash: BytesN<32>) {
+    if ownable::require_admin(env) != *admin {
+        panic!("Unauthorized");
+    }
+    
+    env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+    ContractUpgraded { from_version, new_wasm_hash }.publish(env);
+}
@@ -0,0 +1,117 @@
+// Loan monitoring events, published for keepers and monitoring tools
+use soroban_sdk::{contractevent, Address, Vec};
+
+use crate::{AssetType, RewardAsset};
+
+// Summary of a check_health_batch call
+#[contractevent(topics = ["loan", "health_report"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchHealthReport {
+    pub healthy: u32,
+    pub at_risk: u32,
+    pub liquidatable: u32,
+    pub liquidatable_ids: Vec<u64>,
+}
+
+#[contractevent(topics = ["loan", "created"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanCreated {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub collateral_asset: AssetType,
+    pub collateral_amount: i128,
+    pub borrowed_asset: AssetType,
+    pub borrowed_amount: i128,
+}
+
+// Prices and collateralization ratio are those the liquidation was
+// decided on
+#[contractevent(topics = ["loan", "liquidated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanLiquidated {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub liquidator: Address,
+    pub reward: i128,
+    pub reward_asset: RewardAsset,
+    pub collateral_price: i128,
+    pub borrowed_price: i128,
+    pub collateral_ratio: i128,
+}
+
+#[contractevent(topics = ["loan", "borrowed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanBorrowed {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub amount: i128,
+    pub borrowed_amount: i128,
+}
+
+#[contractevent(topics = ["loan", "repaid"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanRepaid {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub amount: i128,
+    pub remaining: i128,
+}
+
+// Published when a loan's debt is paid off and its collateral returned
+#[contractevent(topics = ["loan", "closed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanClosed {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub collateral_returned: i128,
+}
+
+// A liquidated loan's collateral was moved out of the contract
+#[contractevent(topics = ["loan", "collateral_swept"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CollateralSwept {
+    #[topic]
+    pub loan_id: u64,
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent(topics = ["loan", "collateral_added"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CollateralAdded {
+    #[topic]
+    pub loan_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub amount: i128,
+    pub collateral_amount: i128,
+}
+
+// New loans, borrowing and liquidations on `asset` halted or resumed
+#[contractevent(topics = ["asset", "paused"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetPauseChanged {
+    #[topic]
+    pub asset: AssetType,
+    pub paused: bool,
+}
+
+// Collateral pulled from a loan's pre-authorized top-up source
+#[contractevent(topics = ["loan", "auto_topup"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AutoTopUpExecuted {
+    #[topic]
+    pub loan_id: u64,
+    pub source: Address,
+    pub amount: i128,
+    pub collateral_amount: i128,
+}
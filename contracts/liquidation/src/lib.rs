@@ -1,21 +1,35 @@
 #![no_std]
 
+mod events;
 mod reflector;
+mod test;
+use events::{AssetPauseChanged, AutoTopUpExecuted, BatchHealthReport, CollateralAdded, CollateralSwept, LoanBorrowed, LoanClosed, LoanCreated, LoanLiquidated, LoanRepaid};
 use reflector::{ReflectorClient, Asset, PriceData};
+use stellar_guard_common::decimal::{is_sane_amount, normalize_price, STELLAR_DECIMALS};
+use stellar_guard_common::{ownable, pausable, upgradeable};
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype,
-    Address, Env, Map, Vec, log, Symbol, String
+    contract, contractimpl, contracttype, token,
+    Address, BytesN, Env, I256, FromVal, IntoVal, Map, Val, Vec, log, Symbol, String
 };
 
-// Reflector Oracle Addresses - Testnet
+// Reflector Oracle Address - Testnet, the fallback before an oracle is set
 const TESTNET_EXTERNAL_ORACLE: &str = "CCYOZJCOPG34LLQQ7N24YXBM7LL62R7ONMZ3G6WZAAYPB5OYKOMJRN63";
-const TESTNET_STELLAR_ORACLE: &str = "CAVLP5DH2GJPZMVO7IJY4CVOD5MWEFTJFVPD2YY2FQXOQHRGHK4D6HLP";
 
-// Reflector Oracle Addresses - Mainnet
-const MAINNET_EXTERNAL_ORACLE: &str = "CAFJZQWSED6YAWZU3GWRTOCNPPCGBN32L7QV43XX5LZLFTK6JLN34DLN";
-const MAINNET_STELLAR_ORACLE: &str = "CALI2BYU2JE6WVRUFYTS6MSBNEHGJ35P4AVCZYF3B6QOE3QKOB2PLE6M";
 const MAX_PERSISTENT_TTL: u32 = 535680;
+// Bump on every release so deployments can be matched to source
+pub const CONTRACT_VERSION: u32 = 2;
+const DEFAULT_STALENESS_SECONDS: u64 = 600; // Prices older than 10 minutes are not acted on
+const SECONDS_PER_YEAR: i128 = 31_536_000;
+const MAX_INTEREST_RATE_BPS: u32 = 10000; // 100% APR
+const DEFAULT_THRESHOLD_GRACE_SECONDS: u64 = 604800; // Borrowers get a week to adjust to a new threshold
+const MAX_LIQUIDATABLE_SCAN: u64 = 100; // Loans scanned per get_liquidatable_loans call
+const MAX_MIGRATION_BATCH: u64 = 50; // Loans converted per migrate_loans call
+const MAX_LIQUIDATION_BATCH: u32 = 20; // Loans considered per check_and_liquidate_batch call
+// Upper bound on any liquidation bonus, so an admin cannot hand most of a
+// loan's collateral to liquidators
+const MAX_LIQUIDATION_BONUS_BPS: u32 = 2000; // 20%
+const AT_RISK_BUFFER_BPS: i128 = 1000; // Loans within 10% above their threshold are at risk
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -35,6 +49,28 @@ pub struct Loan {
     pub liquidation_threshold: i128, // in basis points (e.g., 15000 = 150%)
     pub created_at: u64,
     pub status: LoanStatus,
+    pub interest_rate_bps: u32, // annual rate, fixed at creation
+    pub last_accrued: u64, // ledger timestamp interest was last added to borrowed_amount
+    pub interest_remainder: i128, // fraction of a unit of interest not yet added, scaled by 10000 * SECONDS_PER_YEAR
+    pub previous_threshold: i128, // still enforced until threshold_effective_at
+    pub threshold_effective_at: u64,
+    pub maturity: Option<u64>, // term loans are liquidatable from this timestamp regardless of ratio
+}
+
+// Loan layout from before interest, threshold grace periods and maturities.
+// Records stored in it can't be read as Loan until migrate_loans rewrites
+// them.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LegacyLoan {
+    pub owner: Address,
+    pub collateral_asset: AssetType,
+    pub collateral_amount: i128,
+    pub borrowed_asset: AssetType,
+    pub borrowed_amount: i128,
+    pub liquidation_threshold: i128,
+    pub created_at: u64,
+    pub status: LoanStatus,
 }
 
 #[contracttype]
@@ -45,13 +81,83 @@ pub enum LoanStatus {
     Closed,
 }
 
+// When `required` is set, liquidations must be confirmed by a second oracle
+// whose prices agree with the primary within `agreement_band_bps`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OracleConsensusConfig {
+    pub required: bool,
+    pub secondary_oracle: Address,
+    pub agreement_band_bps: i128,
+}
+
+// Asset a liquidator takes their bonus in. Collateral bonuses are carved
+// out of the loan's collateral and accrue to the liquidator's pending
+// rewards in that asset; Debt bonuses are converted at the
+// oracle price and paid immediately from the contract's debt-token
+// liquidity.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RewardAsset {
+    Collateral,
+    Debt,
+}
+
+// Liquidator bonus as a share of seized collateral. At the threshold the
+// bonus is `bonus_bps`; deeper shortfalls scale it by threshold / ratio, up
+// to `max_bonus_bps`, so badly underwater loans still attract liquidators.
+// Set both equal for a flat bonus.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LiquidationParams {
+    pub bonus_bps: u32,
+    pub max_bonus_bps: u32,
+}
+
+// Admin floor for loans posting an asset as collateral. Loans may borrow at
+// most `max_ltv_bps` of the collateral value, checked on opening, further
+// borrowing and collateral withdrawal. New loans must choose a threshold
+// of at least `min_threshold`; existing loans are checked against the floor
+// too. A loan opened at the max LTV must sit above the floor.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetRiskParams {
+    pub max_ltv_bps: u32,
+    pub min_threshold: i128,
+}
+
+// Standing instruction to add `topup_amount` of collateral from `source`
+// whenever the loan's collateral ratio falls below `trigger_ratio` (bps).
+// Token collateral is pulled with transfer_from, so `source` must have
+// approved this contract for the amounts it expects to cover.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AutoTopUp {
+    pub trigger_ratio: i128,
+    pub topup_amount: i128,
+    pub source: Address,
+}
+
 #[contracttype]
 pub enum DataKey {
     Loans,
     LoanCounter,
     UserLoans(Address),
     OracleAddress,
-    LiquidationRewards,
+    LiquidationRewards(Address, AssetType), // Liquidator's unclaimed collateral rewards in an asset
+    OracleConsensus,
+    StalenessSeconds,
+    InterestRateBps, // Annual rate applied to new loans
+    MigrationCursor, // Last loan id checked by migrate_loans
+    BorrowCap(AssetType), // Max total borrowed against a collateral asset
+    TotalBorrowed(AssetType), // Running principal borrowed against a collateral asset
+    ThresholdGracePeriod, // Seconds before an updated loan threshold applies
+    LiquidationParams,
+    AssetPaused(AssetType), // Set while loans touching an asset are frozen
+    TotalPendingRewards(AssetType), // Sum of all LiquidationRewards entries for an asset
+    MaxLoanDuration, // Seconds from creation until a new loan matures; unset for open-ended loans
+    AssetRiskParams(AssetType), // Collateral LTV cap and threshold floor
+    AutoTopUp(u64), // Borrower's standing collateral top-up for a loan
 }
 
 #[contract]
@@ -59,15 +165,428 @@ pub struct LiquidationProtection;
 
 #[contractimpl]
 impl LiquidationProtection {
-    pub fn initialize(env: Env, oracle_address: Address) {
+    pub fn initialize(env: Env, admin: Address, oracle_address: Address, liquidation_bonus_bps: u32) {
+        if ownable::has_admin(&env) {
+            panic!("Already initialized");
+        }
+        let params = LiquidationParams {
+            bonus_bps: liquidation_bonus_bps,
+            max_bonus_bps: liquidation_bonus_bps,
+        };
+        Self::validate_liquidation_params(&params);
+        
+        ownable::set_admin(&env, &admin);
+        env.storage().instance().set(&DataKey::LiquidationParams, &params);
         env.storage().instance().set(&DataKey::OracleAddress, &oracle_address);
         env.storage().persistent().set(&DataKey::LoanCounter, &0u64);
-        env.storage().persistent().set(&DataKey::LiquidationRewards, &Map::<Address, i128>::new(&env));
         
         // Extend TTL
         env.storage().instance().extend_ttl(100, MAX_PERSISTENT_TTL);
     }
     
+    // Halt new loans and liquidations. Repayment and top-ups stay available.
+    pub fn pause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, true);
+    }
+    
+    pub fn unpause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, false);
+    }
+    
+    pub fn is_paused(env: Env) -> bool {
+        pausable::is_paused(&env)
+    }
+    
+    // Freeze new loans, borrowing and liquidations touching one asset,
+    // leaving loans on other assets operational. Repayment stays available.
+    pub fn set_asset_paused(env: Env, admin: Address, asset: AssetType, paused: bool) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let key = DataKey::AssetPaused(asset.clone());
+        if paused {
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+        
+        AssetPauseChanged { asset, paused }.publish(&env);
+    }
+    
+    pub fn is_asset_paused(env: Env, asset: AssetType) -> bool {
+        env.storage().persistent().has(&DataKey::AssetPaused(asset))
+    }
+    
+    pub fn version(_env: Env) -> u32 {
+        CONTRACT_VERSION
+    }
+    
+    // Swap in new contract code uploaded under `new_wasm_hash`, keeping the
+    // contract id and all state. Bump CONTRACT_VERSION with every release.
+    pub fn upgrade(env: Env, admin: Address, new_wasm_hash: BytesN<32>) {
+        upgradeable::upgrade(&env, &admin, CONTRACT_VERSION, new_wasm_hash);
+    }
+    
+    // Crate name and version the deployed code was built from
+    pub fn build_info(env: Env) -> String {
+        String::from_str(&env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    }
+    
+    pub fn get_admin(env: Env) -> Address {
+        ownable::get_admin(&env)
+    }
+    
+    pub fn is_admin(env: Env, who: Address) -> bool {
+        ownable::is_admin(&env, &who)
+    }
+    
+    // Nominate a new admin; takes effect once they call accept_admin
+    pub fn propose_admin(env: Env, current_admin: Address, new_admin: Address) {
+        ownable::propose_admin(&env, &current_admin, &new_admin);
+    }
+    
+    pub fn accept_admin(env: Env, new_admin: Address) {
+        ownable::accept_admin(&env, &new_admin);
+        log!(&env, "Admin transferred to {}", new_admin);
+    }
+    
+    // Address nominated by propose_admin and not yet accepted
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        ownable::get_pending_admin(&env)
+    }
+    
+    // Configure the second-oracle agreement requirement for liquidations
+    pub fn set_oracle_consensus(env: Env, admin: Address, config: OracleConsensusConfig) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if config.agreement_band_bps < 0 || config.agreement_band_bps > 10000 {
+            panic!("Invalid agreement band");
+        }
+        
+        env.storage().instance().set(&DataKey::OracleConsensus, &config);
+        
+        log!(&env, "Oracle consensus required: {}", config.required);
+    }
+    
+    pub fn get_oracle_consensus(env: Env) -> Option<OracleConsensusConfig> {
+        env.storage().instance().get(&DataKey::OracleConsensus)
+    }
+    
+    // Bonuses are capped at MAX_LIQUIDATION_BONUS_BPS (20%) of collateral
+    pub fn set_liquidation_params(env: Env, admin: Address, params: LiquidationParams) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        Self::validate_liquidation_params(&params);
+        
+        env.storage().instance().set(&DataKey::LiquidationParams, &params);
+        
+        log!(&env, "Liquidation bonus set: {}bps to {}bps", params.bonus_bps, params.max_bonus_bps);
+    }
+    
+    pub fn get_liquidation_params(env: Env) -> LiquidationParams {
+        env.storage().instance().get(&DataKey::LiquidationParams).unwrap()
+    }
+    
+    // Max age of an oracle price before liquidation checks ignore it
+    pub fn set_staleness_seconds(env: Env, admin: Address, staleness_seconds: u64) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if staleness_seconds == 0 {
+            panic!("Invalid staleness window");
+        }
+        
+        env.storage().instance().set(&DataKey::StalenessSeconds, &staleness_seconds);
+        
+        log!(&env, "Staleness window set: {}s", staleness_seconds);
+    }
+    
+    pub fn get_staleness_seconds(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::StalenessSeconds)
+            .unwrap_or(DEFAULT_STALENESS_SECONDS)
+    }
+    
+    // Annual interest rate for loans created from now on. Existing loans keep
+    // the rate they were opened with.
+    pub fn set_interest_rate(env: Env, admin: Address, interest_rate_bps: u32) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if interest_rate_bps > MAX_INTEREST_RATE_BPS {
+            panic!("Invalid interest rate");
+        }
+        
+        env.storage().instance().set(&DataKey::InterestRateBps, &interest_rate_bps);
+        
+        log!(&env, "Interest rate set: {}bps", interest_rate_bps);
+    }
+    
+    pub fn get_interest_rate(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::InterestRateBps).unwrap_or(0)
+    }
+    
+    // Make new loans term loans that must be repaid within `duration`
+    // seconds, after which they can be liquidated even if healthy. None
+    // restores open-ended loans; existing loans keep their maturity.
+    pub fn set_max_loan_duration(env: Env, admin: Address, duration: Option<u64>) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        match duration {
+            Some(duration) => env.storage().instance().set(&DataKey::MaxLoanDuration, &duration),
+            None => env.storage().instance().remove(&DataKey::MaxLoanDuration),
+        }
+    }
+    
+    pub fn get_max_loan_duration(env: Env) -> Option<u64> {
+        env.storage().instance().get(&DataKey::MaxLoanDuration)
+    }
+    
+    // Ids of every loan `user` has opened, including closed ones
+    pub fn get_user_loans(env: Env, user: Address) -> Vec<u64> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserLoans(user))
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+    
+    // Stored loan as of its last update; interest since then is not accrued
+    pub fn get_loan_details(env: Env, loan_id: u64) -> Loan {
+        Self::get_loan(&env, loan_id)
+    }
+    
+    pub fn get_loan_maturity(env: Env, loan_id: u64) -> Option<u64> {
+        Self::get_loan(&env, loan_id).maturity
+    }
+    
+    pub fn set_threshold_grace_period(env: Env, admin: Address, grace_seconds: u64) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        env.storage().instance().set(&DataKey::ThresholdGracePeriod, &grace_seconds);
+        
+        log!(&env, "Threshold grace period set: {}s", grace_seconds);
+    }
+    
+    pub fn get_threshold_grace_period(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::ThresholdGracePeriod)
+            .unwrap_or(DEFAULT_THRESHOLD_GRACE_SECONDS)
+    }
+    
+    // Change an existing loan's liquidation threshold. The current threshold
+    // keeps applying for the grace period so the borrower can top up first.
+    pub fn update_loan_threshold(env: Env, admin: Address, loan_id: u64, new_threshold: i128) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if new_threshold <= 10000 {
+            panic!("Liquidation threshold must be > 100%");
+        }
+        
+        let mut loan = Self::get_loan(&env, loan_id);
+        if loan.status != LoanStatus::Active {
+            panic!("Loan not active");
+        }
+        
+        if new_threshold < Self::threshold_floor(&env, &loan.collateral_asset) {
+            panic!("Threshold below asset floor");
+        }
+        
+        loan.previous_threshold = Self::effective_threshold(&env, &loan);
+        loan.liquidation_threshold = new_threshold;
+        loan.threshold_effective_at = env.ledger().timestamp() + Self::get_threshold_grace_period(env.clone());
+        Self::save_loan(&env, loan_id, &loan);
+        
+        log!(&env, "Loan {} threshold {}bps -> {}bps at {}",
+             loan_id, loan.previous_threshold, new_threshold, loan.threshold_effective_at);
+    }
+    
+    // Threshold liquidation checks apply to the loan right now
+    pub fn get_effective_threshold(env: Env, loan_id: u64) -> i128 {
+        let loan = Self::get_loan(&env, loan_id);
+        Self::effective_threshold(&env, &loan)
+    }
+    
+    // Borrowed amount including interest accrued since the loan was last touched
+    pub fn get_current_debt(env: Env, loan_id: u64) -> i128 {
+        let mut loan = Self::get_loan(&env, loan_id);
+        Self::accrue_interest(&env, &mut loan);
+        loan.borrowed_amount
+    }
+    
+    // Rewrite loans stored in the LegacyLoan layout as Loan, with no
+    // interest, their threshold in force and no maturity. Active legacy
+    // loans are added to the borrow totals. Checks up to MAX_MIGRATION_BATCH
+    // loans per call, resuming from a stored cursor; returns true once every
+    // loan is migrated. Safe to call again after completion.
+    pub fn migrate_loans(env: Env, admin: Address) -> bool {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let loan_count: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::LoanCounter)
+            .unwrap_or(0);
+        let cursor: u64 = env.storage()
+            .instance()
+            .get(&DataKey::MigrationCursor)
+            .unwrap_or(0);
+        let end = loan_count.min(cursor + MAX_MIGRATION_BATCH);
+        
+        // Read as raw values, since legacy and migrated records are mixed
+        let mut loans: Map<u64, Val> = env.storage()
+            .persistent()
+            .get(&DataKey::Loans)
+            .unwrap_or_else(|| Map::new(&env));
+        for loan_id in (cursor + 1)..=end {
+            let stored = match loans.get(loan_id) {
+                Some(stored) => stored,
+                None => continue,
+            };
+            if !Self::is_legacy_loan(&env, &stored) {
+                continue;
+            }
+            
+            let legacy = LegacyLoan::from_val(&env, &stored);
+            let loan = Self::loan_from_legacy(&env, legacy);
+            if loan.status == LoanStatus::Active {
+                let key = DataKey::TotalBorrowed(loan.collateral_asset.clone());
+                let total: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+                env.storage().persistent().set(&key, &(total + loan.borrowed_amount));
+            }
+            loans.set(loan_id, loan.into_val(&env));
+        }
+        
+        env.storage().persistent().set(&DataKey::Loans, &loans);
+        env.storage().instance().set(&DataKey::MigrationCursor, &end);
+        
+        log!(&env, "Migrated loans {} to {} of {}", cursor + 1, end, loan_count);
+        end == loan_count
+    }
+    
+    // Cap the total borrowed against `asset` as collateral. New loans that
+    // would push the running total over the cap are rejected. Uncapped
+    // until set.
+    pub fn set_max_borrow_per_collateral(env: Env, admin: Address, asset: AssetType, cap: i128) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if !is_sane_amount(cap) {
+            panic!("Invalid amount");
+        }
+        
+        env.storage().persistent().set(&DataKey::BorrowCap(asset), &cap);
+        
+        log!(&env, "Borrow cap set: {}", cap);
+    }
+    
+    pub fn get_max_borrow_per_collateral(env: Env, asset: AssetType) -> Option<i128> {
+        env.storage().persistent().get(&DataKey::BorrowCap(asset))
+    }
+    
+    // Risk floor for loans collateralized by `asset`. Raising the floor
+    // applies to open loans straight away.
+    pub fn set_asset_risk_params(env: Env, admin: Address, asset: AssetType, params: AssetRiskParams) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if params.max_ltv_bps == 0
+            || params.min_threshold <= 10000
+            || params.max_ltv_bps as i128 * params.min_threshold >= 100_000_000
+        {
+            panic!("Invalid risk params");
+        }
+        
+        env.storage().persistent().set(&DataKey::AssetRiskParams(asset), &params);
+        
+        log!(&env, "Asset risk params set: {}bps LTV, {}bps threshold", params.max_ltv_bps, params.min_threshold);
+    }
+    
+    pub fn get_asset_risk_params(env: Env, asset: AssetType) -> Option<AssetRiskParams> {
+        env.storage().persistent().get(&DataKey::AssetRiskParams(asset))
+    }
+    
+    // Outstanding principal borrowed against `asset` across active loans
+    pub fn get_total_borrowed(env: Env, asset: AssetType) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TotalBorrowed(asset))
+            .unwrap_or(0)
+    }
+    
+    // Collateral of a loan as (asset, amount) pairs. Loans hold a single
+    // collateral asset, so there is one entry.
+    pub fn get_loan_collateral(env: Env, loan_id: u64) -> Vec<(AssetType, i128)> {
+        let loan = Self::get_loan(&env, loan_id);
+        Vec::from_array(&env, [(loan.collateral_asset, loan.collateral_amount)])
+    }
+    
+    // Value of all of a loan's collateral at current oracle prices, each
+    // rescaled to STELLAR_DECIMALS. Summed in I256 like ratio_bps and
+    // saturated at i128::MAX.
+    pub fn get_collateral_value(env: Env, loan_id: u64) -> i128 {
+        let oracle_address = Self::get_oracle_address(&env);
+        let mut value = I256::from_i32(&env, 0);
+        
+        for (asset, amount) in Self::get_loan_collateral(env.clone(), loan_id).iter() {
+            let price = Self::get_price(&env, &oracle_address, &asset)
+                .unwrap_or_else(|| panic!("Price data unavailable"));
+            value = value.add(&I256::from_i128(&env, price).mul(&I256::from_i128(&env, amount)));
+        }
+        
+        value.to_i128().unwrap_or(i128::MAX)
+    }
+    
+    // Unclaimed collateral rewards `liquidator` has earned in `asset`
+    pub fn get_pending_rewards(env: Env, liquidator: Address, asset: AssetType) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::LiquidationRewards(liquidator, asset))
+            .unwrap_or(0)
+    }
+    
+    // Unclaimed liquidation rewards in `asset` across all liquidators, i.e.
+    // the seized collateral the contract holds back to pay every claim
+    pub fn get_total_pending_rewards(env: Env, asset: AssetType) -> i128 {
+        env.storage().persistent().get(&DataKey::TotalPendingRewards(asset)).unwrap_or(0)
+    }
+    
+    // Withdraw accrued liquidation rewards in one collateral asset. The
+    // entry is removed before the transfer.
+    pub fn claim_liquidation_rewards(env: Env, liquidator: Address, asset: AssetType) -> i128 {
+        liquidator.require_auth();
+        
+        let key = DataKey::LiquidationRewards(liquidator.clone(), asset.clone());
+        let amount: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+        if amount == 0 {
+            panic!("No rewards to claim");
+        }
+        
+        env.storage().persistent().remove(&key);
+        Self::adjust_total_pending_rewards(&env, &asset, -amount);
+        
+        Self::release_collateral(&env, &liquidator, &asset, amount);
+        
+        log!(&env, "Liquidator {} claimed {} rewards", liquidator, amount);
+        amount
+    }
+    
     // Create a collateralized loan position
     pub fn create_loan(
         env: Env,
@@ -79,6 +598,13 @@ impl LiquidationProtection {
         liquidation_threshold: i128,
     ) -> u64 {
         owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &collateral_asset);
+        Self::when_asset_not_paused(&env, &borrowed_asset);
+        
+        if !is_sane_amount(collateral_amount) || !is_sane_amount(borrowed_amount) {
+            panic!("Invalid amount");
+        }
         
         // Validate liquidation threshold (must be > 100%)
         if liquidation_threshold <= 10000 {
@@ -98,6 +624,15 @@ impl LiquidationProtection {
             panic!("Initial collateral insufficient");
         }
         
+        if let Some(params) = Self::get_asset_risk_params(env.clone(), collateral_asset.clone()) {
+            if liquidation_threshold < params.min_threshold {
+                panic!("Threshold below asset floor");
+            }
+        }
+        Self::require_within_max_ltv(&env, &collateral_asset, collateral_ratio);
+        
+        Self::increase_total_borrowed(&env, &collateral_asset, borrowed_amount);
+        
         let loan_id = Self::get_next_loan_id(&env);
         
         let loan = Loan {
@@ -109,10 +644,27 @@ impl LiquidationProtection {
             liquidation_threshold,
             created_at: env.ledger().timestamp(),
             status: LoanStatus::Active,
+            interest_rate_bps: Self::get_interest_rate(env.clone()),
+            last_accrued: env.ledger().timestamp(),
+            interest_remainder: 0,
+            previous_threshold: liquidation_threshold,
+            threshold_effective_at: env.ledger().timestamp(),
+            maturity: Self::get_max_loan_duration(env.clone())
+                .map(|duration| env.ledger().timestamp().saturating_add(duration)),
         };
         
         Self::save_loan(&env, loan_id, &loan);
         Self::add_user_loan(&env, &owner, loan_id);
+        Self::deposit_collateral(&env, &owner, &loan.collateral_asset, collateral_amount);
+        
+        LoanCreated {
+            loan_id,
+            owner,
+            collateral_asset: loan.collateral_asset,
+            collateral_amount,
+            borrowed_asset: loan.borrowed_asset,
+            borrowed_amount,
+        }.publish(&env);
         
         log!(&env, "Loan created: ID={}, CollRatio={}bps", loan_id, collateral_ratio);
         
@@ -121,122 +673,458 @@ impl LiquidationProtection {
     
     // Check if a loan position needs liquidation (from Reflector example)
     pub fn check_liquidation(env: Env, loan_id: u64) -> bool {
+        let mut loan = Self::get_loan(&env, loan_id);
+        
+        if loan.status != LoanStatus::Active {
+            return false;
+        }
+        
+        Self::accrue_interest(&env, &mut loan);
+        Self::save_loan(&env, loan_id, &loan);
+        
+        Self::liquidatable_ratio(&env, loan_id, &loan).is_some()
+    }
+    
+    // Page through loan ids `start_id..start_id + limit` and return the ones
+    // currently liquidatable. Read-only; `limit` is capped at
+    // MAX_LIQUIDATABLE_SCAN per call.
+    pub fn get_liquidatable_loans(env: Env, start_id: u64, limit: u32) -> Vec<u64> {
+        let mut liquidatable = Vec::new(&env);
+        for (loan_id, _) in Self::scan_liquidatable(&env, start_id, limit as u64) {
+            liquidatable.push_back(loan_id);
+        }
+        liquidatable
+    }
+    
+    // Liquidatable loans in `start..start + limit` whose reward is at least
+    // `min_reward`, paired with that reward. Same scan bound as
+    // get_liquidatable_loans.
+    pub fn get_profitable_liquidations(env: Env, min_reward: i128, start: u64, limit: u64) -> Vec<(u64, i128)> {
+        let mut profitable = Vec::new(&env);
+        for (loan_id, reward) in Self::scan_liquidatable(&env, start, limit) {
+            if reward >= min_reward {
+                profitable.push_back((loan_id, reward));
+            }
+        }
+        profitable
+    }
+    
+    // Evaluate many loans and publish a single BatchHealthReport event.
+    // Inactive loans and loans without price data are not counted. Does not
+    // liquidate anything.
+    pub fn check_health_batch(env: Env, loan_ids: Vec<u64>) {
+        let oracle_address = Self::get_oracle_address(&env);
+        let mut report = BatchHealthReport {
+            healthy: 0,
+            at_risk: 0,
+            liquidatable: 0,
+            liquidatable_ids: Vec::new(&env),
+        };
+        
+        for loan_id in loan_ids.iter() {
+            let mut loan = Self::get_loan(&env, loan_id);
+            if loan.status != LoanStatus::Active {
+                continue;
+            }
+            Self::accrue_interest(&env, &mut loan);
+            
+            let collateral_price = Self::get_price(&env, &oracle_address, &loan.collateral_asset);
+            let borrowed_price = Self::get_price(&env, &oracle_address, &loan.borrowed_asset);
+            let (collateral_price, borrowed_price) = match (collateral_price, borrowed_price) {
+                (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+                _ => continue,
+            };
+            
+            let ratio = Self::ratio_bps(&env, collateral_price, loan.collateral_amount, borrowed_price, loan.borrowed_amount);
+            let threshold = Self::effective_threshold(&env, &loan);
+            let at_risk_ratio = threshold * (10000 + AT_RISK_BUFFER_BPS) / 10000;
+            
+            if ratio <= threshold
+                && Self::secondary_oracle_agrees(&env, &loan, collateral_price, borrowed_price)
+            {
+                report.liquidatable += 1;
+                report.liquidatable_ids.push_back(loan_id);
+            } else if ratio <= at_risk_ratio {
+                report.at_risk += 1;
+            } else {
+                report.healthy += 1;
+            }
+        }
+        
+        report.publish(&env);
+    }
+    
+    // Execute liquidation. `min_reward` lets the liquidator abort if the
+    // bonus has fallen below what makes the liquidation worth it; it is in
+    // units of the chosen `reward_asset` (collateral by default). Returns
+    // the bonus in those units.
+    pub fn liquidate_position(
+        env: Env,
+        liquidator: Address,
+        loan_id: u64,
+        min_reward: Option<i128>,
+        reward_asset: Option<RewardAsset>,
+    ) -> i128 {
+        liquidator.require_auth();
+        pausable::when_not_paused(&env);
+        
+        if !Self::check_liquidation(env.clone(), loan_id) {
+            panic!("Position not eligible for liquidation");
+        }
+        
+        // check_liquidation has accrued interest up to now
+        let loan = Self::get_loan(&env, loan_id);
+        Self::when_loan_assets_not_paused(&env, &loan);
+        
+        Self::liquidate(&env, &liquidator, loan_id, loan, min_reward, reward_asset)
+    }
+    
+    // Liquidate every eligible loan in `loan_ids` with the reward credited in
+    // collateral, returning the ids liquidated. Missing, inactive, healthy
+    // and paused-asset loans are skipped rather than failing the batch. Only
+    // the first MAX_LIQUIDATION_BATCH ids are considered.
+    pub fn check_and_liquidate_batch(env: Env, liquidator: Address, loan_ids: Vec<u64>) -> Vec<u64> {
+        liquidator.require_auth();
+        pausable::when_not_paused(&env);
+        
+        let mut liquidated = Vec::new(&env);
+        for loan_id in loan_ids.iter().take(MAX_LIQUIDATION_BATCH as usize) {
+            if Self::find_loan(&env, loan_id).is_none() || !Self::check_liquidation(env.clone(), loan_id) {
+                continue;
+            }
+            
+            let loan = Self::get_loan(&env, loan_id);
+            if Self::is_asset_paused(env.clone(), loan.collateral_asset.clone())
+                || Self::is_asset_paused(env.clone(), loan.borrowed_asset.clone())
+            {
+                continue;
+            }
+            
+            Self::liquidate(&env, &liquidator, loan_id, loan, None, None);
+            liquidated.push_back(loan_id);
+        }
+        
+        log!(&env, "Batch liquidated {} loans", liquidated.len());
+        
+        liquidated
+    }
+    
+    // Move a liquidated loan's collateral, net of the liquidator's reward, to
+    // `to`, e.g. the treasury that absorbs its debt. Token collateral stays
+    // in the contract after liquidation until swept; each loan can be swept
+    // once.
+    pub fn sweep_liquidated_collateral(env: Env, admin: Address, loan_id: u64, to: Address) -> i128 {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let mut loan = Self::get_loan(&env, loan_id);
+        if loan.status != LoanStatus::Liquidated {
+            panic!("Loan not liquidated");
+        }
+        if loan.collateral_amount == 0 {
+            panic!("Collateral already swept");
+        }
+        
+        let amount = loan.collateral_amount;
+        loan.collateral_amount = 0;
+        Self::save_loan(&env, loan_id, &loan);
+        Self::release_collateral(&env, &to, &loan.collateral_asset, amount);
+        
+        CollateralSwept { loan_id, to, amount }.publish(&env);
+        
+        amount
+    }
+    
+    // Whether liquidate_position would succeed for `loan_id` right now, and
+    // the collateral reward it would pay net of selling it at `slippage_bps`
+    // below the oracle price, so liquidators can judge illiquid collateral.
+    // Read-only; (false, 0) if not liquidatable.
+    pub fn simulate_liquidation(env: Env, loan_id: u64, slippage_bps: Option<u32>) -> (bool, i128) {
+        let slippage_bps = slippage_bps.unwrap_or(0);
+        if slippage_bps > 10000 {
+            panic!("Invalid slippage");
+        }
+        
+        let mut loan = Self::get_loan(&env, loan_id);
+        if loan.status != LoanStatus::Active {
+            return (false, 0);
+        }
+        
+        Self::accrue_interest(&env, &mut loan);
+        match Self::liquidatable_ratio(&env, loan_id, &loan) {
+            Some(ratio) => {
+                let reward = Self::liquidation_reward(&env, &loan, ratio);
+                (true, reward * (10000 - slippage_bps as i128) / 10000)
+            },
+            None => (false, 0),
+        }
+    }
+    
+    // Health factor from spot prices, scaled so 10000 = 1.0; below 10000 the
+    // position is liquidatable. Returns 0 for inactive loans or missing prices.
+    pub fn get_health_factor(env: Env, loan_id: u64) -> i128 {
+        let mut loan = Self::get_loan(&env, loan_id);
+        
+        if loan.status != LoanStatus::Active {
+            return 0;
+        }
+        Self::accrue_interest(&env, &mut loan);
+        
+        let oracle_address = Self::get_oracle_address(&env);
+        let collateral_price = Self::get_price(&env, &oracle_address, &loan.collateral_asset);
+        let borrowed_price = Self::get_price(&env, &oracle_address, &loan.borrowed_asset);
+        let (collateral_price, borrowed_price) = match (collateral_price, borrowed_price) {
+            (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+            _ => return 0,
+        };
+        
+        // Same ratio as check_liquidation, relative to the threshold
+        let ratio = Self::ratio_bps(&env, collateral_price, loan.collateral_amount, borrowed_price, loan.borrowed_amount);
+        let health_factor = Self::health_factor_bps(&env, ratio, &loan);
+        
+        log!(&env, "Loan {} health factor: {}", loan_id, health_factor);
+        
+        health_factor
+    }
+    
+    // Monitor health factor using TWAP for more stable pricing
+    pub fn get_health_factor_twap(env: Env, loan_id: u64, periods: u32) -> i128 {
+        let mut loan = Self::get_loan(&env, loan_id);
+        
+        if loan.status != LoanStatus::Active {
+            return 0;
+        }
+        Self::accrue_interest(&env, &mut loan);
+        
+        let oracle_address = Self::get_oracle_address(&env);
+        let client = ReflectorClient::new(&env, &oracle_address);
+        
+        // Use TWAP for more stable pricing
+        let collateral_twap = match loan.collateral_asset {
+            AssetType::Crypto(ref symbol) => client.twap(&Asset::Other(symbol.clone()), &periods),
+            AssetType::Stellar(ref addr) => client.twap(&Asset::Stellar(addr.clone()), &periods),
+        };
+        let borrowed_twap = match loan.borrowed_asset {
+            AssetType::Crypto(ref symbol) => client.twap(&Asset::Other(symbol.clone()), &periods),
+            AssetType::Stellar(ref addr) => client.twap(&Asset::Stellar(addr.clone()), &periods),
+        };
+        
+        if collateral_twap.is_none() || borrowed_twap.is_none() {
+            return 0;
+        }
+        
+        // Health factor = collateral ratio relative to the threshold
+        // If < 10000, position can be liquidated
+        let ratio = Self::ratio_bps(
+            &env,
+            collateral_twap.unwrap(),
+            loan.collateral_amount,
+            borrowed_twap.unwrap(),
+            loan.borrowed_amount,
+        );
+        let health_factor = Self::health_factor_bps(&env, ratio, &loan);
+        
+        log!(&env, "Loan {} health factor (TWAP): {}", loan_id, health_factor);
+        
+        health_factor
+    }
+    
+    // Add collateral to improve health factor
+    pub fn add_collateral(env: Env, owner: Address, loan_id: u64, additional_amount: i128) {
+        owner.require_auth();
+        
+        let mut loan = Self::get_loan(&env, loan_id);
+        
+        if loan.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        if loan.status != LoanStatus::Active {
+            panic!("Loan not active");
+        }
+        
+        if !is_sane_amount(additional_amount) {
+            panic!("Invalid amount");
+        }
+        
+        Self::accrue_interest(&env, &mut loan);
+        loan.collateral_amount += additional_amount;
+        Self::save_loan(&env, loan_id, &loan);
+        Self::deposit_collateral(&env, &owner, &loan.collateral_asset, additional_amount);
+        
+        CollateralAdded {
+            loan_id,
+            owner,
+            amount: additional_amount,
+            collateral_amount: loan.collateral_amount,
+        }.publish(&env);
+        
+        log!(&env, "Added {} collateral to loan {}", additional_amount, loan_id);
+    }
+    
+    // Let keepers top up the loan's collateral from `source` once its ratio
+    // drops below `trigger_ratio`, which must sit above the liquidation
+    // threshold. Replaces any earlier policy for the loan.
+    pub fn set_auto_topup(
+        env: Env,
+        owner: Address,
+        loan_id: u64,
+        trigger_ratio: i128,
+        topup_amount: i128,
+        source: Address,
+    ) {
+        owner.require_auth();
+        if source != owner {
+            source.require_auth();
+        }
+        
         let loan = Self::get_loan(&env, loan_id);
         
+        if loan.owner != owner {
+            panic!("Unauthorized");
+        }
+        
         if loan.status != LoanStatus::Active {
-            return false;
+            panic!("Loan not active");
         }
         
-        // Get oracle address (using external oracle for all assets)
-        let oracle_address = env.storage()
-            .instance()
-            .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
-        
-        // Get prices from Reflector oracle
-        let client = ReflectorClient::new(&env, &oracle_address);
-        let collateral_price_data = match loan.collateral_asset {
-            AssetType::Crypto(ref symbol) => client.lastprice(&Asset::Other(symbol.clone())),
-            AssetType::Stellar(ref addr) => client.lastprice(&Asset::Stellar(addr.clone())),
-        };
-        let borrowed_price_data = match loan.borrowed_asset {
-            AssetType::Crypto(ref symbol) => client.lastprice(&Asset::Other(symbol.clone())),
-            AssetType::Stellar(ref addr) => client.lastprice(&Asset::Stellar(addr.clone())),
-        };
-        
-        if collateral_price_data.is_none() || borrowed_price_data.is_none() {
-            log!(&env, "Price data unavailable for loan {}", loan_id);
-            return false;
+        if !is_sane_amount(topup_amount) {
+            panic!("Invalid amount");
         }
         
-        let collateral_price = collateral_price_data.unwrap().price;
-        let borrowed_price = borrowed_price_data.unwrap().price;
+        if trigger_ratio <= Self::effective_threshold(&env, &loan) {
+            panic!("Trigger must be above threshold");
+        }
         
-        // Calculate collateral value and borrowed value
-        let collateral_value = collateral_price * loan.collateral_amount;
-        let borrowed_value = borrowed_price * loan.borrowed_amount;
+        // Top-ups are pulled from the source, so there has to be a token
+        if let AssetType::Crypto(_) = loan.collateral_asset {
+            panic!("Collateral has no token");
+        }
         
-        // Calculate current collateralization ratio
-        let collateralization_ratio = (collateral_value * 10000) / borrowed_value;
+        let policy = AutoTopUp { trigger_ratio, topup_amount, source };
+        env.storage().persistent().set(&DataKey::AutoTopUp(loan_id), &policy);
         
-        log!(&env, "Loan {} collateral ratio: {}bps (threshold: {}bps)", 
-             loan_id, collateralization_ratio, loan.liquidation_threshold);
+        log!(&env, "Auto top-up for loan {}: {} below {}bps", loan_id, topup_amount, trigger_ratio);
+    }
+    
+    pub fn cancel_auto_topup(env: Env, owner: Address, loan_id: u64) {
+        owner.require_auth();
         
-        // Check if below liquidation threshold
-        if collateralization_ratio <= loan.liquidation_threshold {
-            log!(&env, "LIQUIDATION TRIGGERED for loan {}", loan_id);
-            return true;
+        if Self::get_loan(&env, loan_id).owner != owner {
+            panic!("Unauthorized");
         }
         
-        false
+        env.storage().persistent().remove(&DataKey::AutoTopUp(loan_id));
     }
     
-    // Execute liquidation
-    pub fn liquidate_position(env: Env, liquidator: Address, loan_id: u64) -> i128 {
-        liquidator.require_auth();
+    pub fn get_auto_topup(env: Env, loan_id: u64) -> Option<AutoTopUp> {
+        env.storage().persistent().get(&DataKey::AutoTopUp(loan_id))
+    }
+    
+    // Keeper entry point: apply the loan's auto top-up if its ratio on fresh
+    // prices is below the trigger. Each call adds one `topup_amount`.
+    pub fn execute_auto_topup(env: Env, loan_id: u64) {
+        pausable::when_not_paused(&env);
         
-        if !Self::check_liquidation(env.clone(), loan_id) {
-            panic!("Position not eligible for liquidation");
+        let policy = Self::get_auto_topup(env.clone(), loan_id)
+            .unwrap_or_else(|| panic!("No auto top-up"));
+        let mut loan = Self::get_loan(&env, loan_id);
+        
+        if loan.status != LoanStatus::Active {
+            panic!("Loan not active");
         }
+        Self::when_asset_not_paused(&env, &loan.collateral_asset);
+        let token_address = match &loan.collateral_asset {
+            AssetType::Stellar(token_address) => token_address.clone(),
+            AssetType::Crypto(_) => panic!("Collateral has no token"),
+        };
+        Self::accrue_interest(&env, &mut loan);
         
-        let mut loan = Self::get_loan(&env, loan_id);
+        let oracle_address = Self::get_oracle_address(&env);
+        let collateral_price = Self::get_price(&env, &oracle_address, &loan.collateral_asset);
+        let borrowed_price = Self::get_price(&env, &oracle_address, &loan.borrowed_asset);
+        let (collateral_price, borrowed_price) = match (collateral_price, borrowed_price) {
+            (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+            _ => panic!("Price not available"),
+        };
         
-        // Calculate liquidation reward (typically 5-10% bonus)
-        let liquidation_bonus_bps = 500; // 5%
-        let reward = (loan.collateral_amount * liquidation_bonus_bps) / 10000;
+        let ratio = Self::ratio_bps(&env, collateral_price, loan.collateral_amount, borrowed_price, loan.borrowed_amount);
+        if ratio >= policy.trigger_ratio {
+            panic!("Top-up not triggered");
+        }
         
-        // Mark loan as liquidated
-        loan.status = LoanStatus::Liquidated;
+        loan.collateral_amount += policy.topup_amount;
         Self::save_loan(&env, loan_id, &loan);
+        let contract = env.current_contract_address();
+        token::Client::new(&env, &token_address).transfer_from(&contract, &policy.source, &contract, &policy.topup_amount);
         
-        // Record liquidation reward for liquidator
-        Self::add_liquidation_reward(&env, &liquidator, reward);
-        
-        log!(&env, "Loan {} liquidated by {}. Reward: {}", 
-             loan_id, liquidator, reward);
+        AutoTopUpExecuted {
+            loan_id,
+            source: policy.source,
+            amount: policy.topup_amount,
+            collateral_amount: loan.collateral_amount,
+        }.publish(&env);
         
-        reward
+        log!(&env, "Auto top-up of {} applied to loan {}", policy.topup_amount, loan_id);
     }
     
-    // Monitor health factor using TWAP for more stable pricing
-    pub fn get_health_factor_twap(env: Env, loan_id: u64, periods: u32) -> i128 {
-        let loan = Self::get_loan(&env, loan_id);
+    // Draw more of the borrowed asset against the loan's existing
+    // collateral. The loan must stay above its liquidation threshold and
+    // within the collateral asset's max LTV.
+    pub fn borrow_more(env: Env, owner: Address, loan_id: u64, additional_amount: i128) {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        
+        let mut loan = Self::get_loan(&env, loan_id);
+        Self::when_loan_assets_not_paused(&env, &loan);
+        
+        if loan.owner != owner {
+            panic!("Unauthorized");
+        }
         
         if loan.status != LoanStatus::Active {
-            return 0;
+            panic!("Loan not active");
         }
         
-        let oracle_address = Self::get_oracle_address(&env);
-        let client = ReflectorClient::new(&env, &oracle_address);
+        if !is_sane_amount(additional_amount) {
+            panic!("Invalid amount");
+        }
         
-        // Use TWAP for more stable pricing
-        let collateral_twap = match loan.collateral_asset {
-            AssetType::Crypto(ref symbol) => client.twap(&Asset::Other(symbol.clone()), &periods),
-            AssetType::Stellar(ref addr) => client.twap(&Asset::Stellar(addr.clone()), &periods),
-        };
-        let borrowed_twap = match loan.borrowed_asset {
-            AssetType::Crypto(ref symbol) => client.twap(&Asset::Other(symbol.clone()), &periods),
-            AssetType::Stellar(ref addr) => client.twap(&Asset::Stellar(addr.clone()), &periods),
-        };
+        Self::accrue_interest(&env, &mut loan);
+        loan.borrowed_amount += additional_amount;
         
-        if collateral_twap.is_none() || borrowed_twap.is_none() {
-            return 0;
+        let collateral_ratio = Self::calculate_collateral_ratio(
+            &env,
+            &loan.collateral_asset,
+            loan.collateral_amount,
+            &loan.borrowed_asset,
+            loan.borrowed_amount,
+        );
+        // During a grace period the loan must satisfy both thresholds
+        let threshold = Self::effective_threshold(&env, &loan).max(loan.liquidation_threshold);
+        if collateral_ratio <= threshold {
+            panic!("Insufficient collateral for borrow");
         }
+        Self::require_within_max_ltv(&env, &loan.collateral_asset, collateral_ratio);
         
-        let collateral_value = collateral_twap.unwrap() * loan.collateral_amount;
-        let borrowed_value = borrowed_twap.unwrap() * loan.borrowed_amount;
-        
-        // Health factor = (collateral_value * liquidation_threshold) / borrowed_value
-        // If < 1, position can be liquidated
-        let health_factor = (collateral_value * 10000) / (borrowed_value * loan.liquidation_threshold / 10000);
+        Self::increase_total_borrowed(&env, &loan.collateral_asset, additional_amount);
+        Self::save_loan(&env, loan_id, &loan);
+        Self::disburse(&env, &owner, &loan.borrowed_asset, additional_amount);
         
-        log!(&env, "Loan {} health factor (TWAP): {}", loan_id, health_factor);
+        LoanBorrowed {
+            loan_id,
+            owner,
+            amount: additional_amount,
+            borrowed_amount: loan.borrowed_amount,
+        }.publish(&env);
         
-        health_factor
+        log!(&env, "Borrowed {} more on loan {}", additional_amount, loan_id);
     }
     
-    // Add collateral to improve health factor
-    pub fn add_collateral(env: Env, owner: Address, loan_id: u64, additional_amount: i128) {
+    // Partial repayment to improve health. Token debt is paid back from the
+    // owner's balance.
+    pub fn repay_loan(env: Env, owner: Address, loan_id: u64, repay_amount: i128) {
         owner.require_auth();
         
         let mut loan = Self::get_loan(&env, loan_id);
@@ -249,17 +1137,47 @@ impl LiquidationProtection {
             panic!("Loan not active");
         }
         
-        loan.collateral_amount += additional_amount;
+        if !is_sane_amount(repay_amount) {
+            panic!("Invalid amount");
+        }
+        
+        // Interest is added first so repayments cover it. Any excess over the
+        // debt is ignored, so the balance never goes negative.
+        Self::accrue_interest(&env, &mut loan);
+        let repay_amount = repay_amount.min(loan.borrowed_amount);
+        loan.borrowed_amount -= repay_amount;
+        
+        if loan.borrowed_amount <= 0 {
+            loan.status = LoanStatus::Closed;
+        }
+        
         Self::save_loan(&env, loan_id, &loan);
+        Self::decrease_total_borrowed(&env, &loan.collateral_asset, repay_amount);
+        Self::collect_repayment(&env, &owner, &loan.borrowed_asset, repay_amount);
         
-        log!(&env, "Added {} collateral to loan {}", additional_amount, loan_id);
+        LoanRepaid {
+            loan_id,
+            owner,
+            amount: repay_amount,
+            remaining: loan.borrowed_amount.max(0),
+        }.publish(&env);
+        
+        // Repaying in full closes the loan and frees all of its collateral
+        if loan.status == LoanStatus::Closed {
+            Self::return_collateral(&env, loan_id, &loan);
+        }
+        
+        log!(&env, "Repaid {} on loan {}", repay_amount, loan_id);
     }
     
-    // Partial repayment to improve health
-    pub fn repay_loan(env: Env, owner: Address, loan_id: u64, repay_amount: i128) {
+    // Remove excess collateral. The loan must stay above its liquidation
+    // threshold and within the collateral asset's max LTV afterwards.
+    pub fn withdraw_collateral(env: Env, owner: Address, loan_id: u64, amount: i128) {
         owner.require_auth();
+        pausable::when_not_paused(&env);
         
         let mut loan = Self::get_loan(&env, loan_id);
+        Self::when_loan_assets_not_paused(&env, &loan);
         
         if loan.owner != owner {
             panic!("Unauthorized");
@@ -269,15 +1187,31 @@ impl LiquidationProtection {
             panic!("Loan not active");
         }
         
-        loan.borrowed_amount -= repay_amount;
+        if !is_sane_amount(amount) || amount >= loan.collateral_amount {
+            panic!("Invalid amount");
+        }
         
-        if loan.borrowed_amount <= 0 {
-            loan.status = LoanStatus::Closed;
+        Self::accrue_interest(&env, &mut loan);
+        loan.collateral_amount -= amount;
+        
+        let collateral_ratio = Self::calculate_collateral_ratio(
+            &env,
+            &loan.collateral_asset,
+            loan.collateral_amount,
+            &loan.borrowed_asset,
+            loan.borrowed_amount,
+        );
+        // During a grace period the loan must satisfy both thresholds
+        let threshold = Self::effective_threshold(&env, &loan).max(loan.liquidation_threshold);
+        if collateral_ratio <= threshold {
+            panic!("Insufficient collateral after withdrawal");
         }
+        Self::require_within_max_ltv(&env, &loan.collateral_asset, collateral_ratio);
         
         Self::save_loan(&env, loan_id, &loan);
+        Self::release_collateral(&env, &owner, &loan.collateral_asset, amount);
         
-        log!(&env, "Repaid {} on loan {}", repay_amount, loan_id);
+        log!(&env, "Withdrew {} collateral from loan {}", amount, loan_id);
     }
     
     // Internal helper functions
@@ -288,29 +1222,53 @@ impl LiquidationProtection {
         borrowed_asset: &AssetType,
         borrowed_amount: i128,
     ) -> i128 {
-        let oracle_address = env.storage()
-            .instance()
-            .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
-        let client = ReflectorClient::new(&env, &oracle_address);
+        let oracle_address = Self::get_oracle_address(env);
+        let collateral_price = Self::get_price_data(env, &oracle_address, collateral_asset);
+        let borrowed_price = Self::get_price_data(env, &oracle_address, borrowed_asset);
         
-        let collateral_price = match collateral_asset {
-            AssetType::Crypto(ref symbol) => client.lastprice(&Asset::Other(symbol.clone())),
-            AssetType::Stellar(ref addr) => client.lastprice(&Asset::Stellar(addr.clone())),
-        };
-        let borrowed_price = match borrowed_asset {
-            AssetType::Crypto(ref symbol) => client.lastprice(&Asset::Other(symbol.clone())),
-            AssetType::Stellar(ref addr) => client.lastprice(&Asset::Stellar(addr.clone())),
+        let (collateral_price, borrowed_price) = match (collateral_price, borrowed_price) {
+            (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+            _ => panic!("Price data unavailable"),
         };
         
-        if collateral_price.is_none() || borrowed_price.is_none() {
-            panic!("Price data unavailable");
+        if !Self::is_fresh(env, &collateral_price) || !Self::is_fresh(env, &borrowed_price) {
+            panic!("Price data is stale");
+        }
+        
+        Self::ratio_bps(env, collateral_price.price, collateral_amount, borrowed_price.price, borrowed_amount)
+    }
+    
+    // Collateral value over borrowed value in basis points. Price * amount
+    // for a large position overflows i128, so the products are taken in I256.
+    fn ratio_bps(
+        env: &Env,
+        collateral_price: i128,
+        collateral_amount: i128,
+        borrowed_price: i128,
+        borrowed_amount: i128,
+    ) -> i128 {
+        // Nothing owed: infinitely healthy rather than a division by zero
+        if borrowed_price <= 0 || borrowed_amount <= 0 {
+            return i128::MAX;
         }
         
-        let collateral_value = collateral_price.unwrap().price * collateral_amount;
-        let borrowed_value = borrowed_price.unwrap().price * borrowed_amount;
+        let collateral_value = I256::from_i128(env, collateral_price).mul(&I256::from_i128(env, collateral_amount));
+        let borrowed_value = I256::from_i128(env, borrowed_price).mul(&I256::from_i128(env, borrowed_amount));
         
-        (collateral_value * 10000) / borrowed_value
+        collateral_value
+            .mul(&I256::from_i32(env, 10000))
+            .div(&borrowed_value)
+            .to_i128()
+            .unwrap_or(i128::MAX)
+    }
+    
+    // Collateral ratio relative to the loan's threshold, in basis points.
+    // An unbounded ratio stays unbounded instead of overflowing.
+    fn health_factor_bps(env: &Env, ratio: i128, loan: &Loan) -> i128 {
+        if ratio == i128::MAX {
+            return i128::MAX;
+        }
+        ratio.saturating_mul(10000) / Self::effective_threshold(env, loan)
     }
     
     fn get_next_loan_id(env: &Env) -> u64 {
@@ -336,7 +1294,7 @@ impl LiquidationProtection {
         let mut loans: Map<u64, Loan> = env.storage()
             .persistent()
             .get(&DataKey::Loans)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
         
         loans.set(loan_id, loan.clone());
         env.storage().persistent().set(&DataKey::Loans, &loans);
@@ -348,19 +1306,54 @@ impl LiquidationProtection {
     }
     
     fn get_loan(env: &Env, loan_id: u64) -> Loan {
-        let loans: Map<u64, Loan> = env.storage()
+        Self::find_loan(env, loan_id).unwrap_or_else(|| panic!("Loan not found"))
+    }
+    
+    fn find_loan(env: &Env, loan_id: u64) -> Option<Loan> {
+        let loans: Map<u64, Val> = env.storage()
             .persistent()
             .get(&DataKey::Loans)
-            .unwrap_or(Map::new(&env));
+            .unwrap_or(Map::new(env));
         
-        loans.get(loan_id).unwrap()
+        loans.get(loan_id).map(|stored| {
+            if Self::is_legacy_loan(env, &stored) {
+                panic!("Loan not migrated");
+            }
+            Loan::from_val(env, &stored)
+        })
+    }
+    
+    // Structs are stored as field maps, so the field count tells the
+    // layouts apart; a mismatched decode aborts rather than returning Err
+    fn is_legacy_loan(env: &Env, stored: &Val) -> bool {
+        let fields: Map<Symbol, Val> = Map::from_val(env, stored);
+        fields.len() == 8
+    }
+    
+    fn loan_from_legacy(env: &Env, legacy: LegacyLoan) -> Loan {
+        Loan {
+            owner: legacy.owner,
+            collateral_asset: legacy.collateral_asset,
+            collateral_amount: legacy.collateral_amount,
+            borrowed_asset: legacy.borrowed_asset,
+            borrowed_amount: legacy.borrowed_amount,
+            liquidation_threshold: legacy.liquidation_threshold,
+            created_at: legacy.created_at,
+            status: legacy.status,
+            interest_rate_bps: 0,
+            last_accrued: env.ledger().timestamp(),
+            interest_remainder: 0,
+            previous_threshold: legacy.liquidation_threshold,
+            threshold_effective_at: legacy.created_at,
+            maturity: None,
+        }
     }
     
     fn add_user_loan(env: &Env, user: &Address, loan_id: u64) {
         let mut user_loans = env.storage()
             .persistent()
             .get(&DataKey::UserLoans(user.clone()))
-            .unwrap_or(Vec::new(&env));
+            .unwrap_or(Vec::new(env));
         
         user_loans.push_back(loan_id);
         env.storage()
@@ -373,27 +1366,407 @@ impl LiquidationProtection {
             .extend_ttl(&DataKey::UserLoans(user.clone()), 100, MAX_PERSISTENT_TTL);
     }
     
+    // Shared by liquidate_position and check_and_liquidate_batch once the
+    // loan is known to be liquidatable with interest accrued
+    fn liquidate(
+        env: &Env,
+        liquidator: &Address,
+        loan_id: u64,
+        mut loan: Loan,
+        min_reward: Option<i128>,
+        reward_asset: Option<RewardAsset>,
+    ) -> i128 {
+        // Prices were just confirmed fresh by check_liquidation
+        let oracle_address = Self::get_oracle_address(env);
+        let collateral_price = Self::get_price(env, &oracle_address, &loan.collateral_asset).unwrap();
+        let borrowed_price = Self::get_price(env, &oracle_address, &loan.borrowed_asset).unwrap();
+        let collateral_ratio = Self::ratio_bps(env, collateral_price, loan.collateral_amount, borrowed_price, loan.borrowed_amount);
+        
+        let reward_asset = reward_asset.unwrap_or(RewardAsset::Collateral);
+        let mut reward = Self::liquidation_reward(env, &loan, collateral_ratio);
+        if reward_asset == RewardAsset::Debt {
+            reward = Self::convert_to_debt_asset(env, &loan, reward, collateral_price, borrowed_price);
+        }
+        if min_reward.is_some_and(|min_reward| reward < min_reward) {
+            panic!("Reward below minimum");
+        }
+        
+        // Checks are done; apply every effect before the token transfer below.
+        // A reward token that calls back into liquidate_position then finds
+        // the loan already Liquidated and fails the eligibility check.
+        loan.status = LoanStatus::Liquidated;
+        if reward_asset == RewardAsset::Collateral {
+            loan.collateral_amount -= reward;
+        }
+        Self::save_loan(env, loan_id, &loan);
+        Self::decrease_total_borrowed(env, &loan.collateral_asset, loan.borrowed_amount);
+        
+        // Interactions last
+        match reward_asset {
+            RewardAsset::Collateral => Self::add_liquidation_reward(env, liquidator, &loan.collateral_asset, reward),
+            RewardAsset::Debt => Self::disburse(env, liquidator, &loan.borrowed_asset, reward),
+        }
+        
+        LoanLiquidated {
+            loan_id,
+            liquidator: liquidator.clone(),
+            reward,
+            reward_asset,
+            collateral_price,
+            borrowed_price,
+            collateral_ratio,
+        }.publish(env);
+        
+        log!(env, "Loan {} liquidated by {}. Reward: {}", 
+             loan_id, liquidator, reward);
+        
+        reward
+    }
+    
+    // Borrowers can't draw past the collateral asset's max LTV, whether by
+    // opening, borrowing more or withdrawing collateral
+    fn require_within_max_ltv(env: &Env, collateral_asset: &AssetType, collateral_ratio: i128) {
+        if let Some(params) = Self::get_asset_risk_params(env.clone(), collateral_asset.clone()) {
+            // LTV = 1 / collateral ratio, both in basis points
+            if collateral_ratio.saturating_mul(params.max_ltv_bps as i128) < 100_000_000 {
+                panic!("Exceeds max LTV");
+            }
+        }
+    }
+    
+    fn when_asset_not_paused(env: &Env, asset: &AssetType) {
+        if Self::is_asset_paused(env.clone(), asset.clone()) {
+            panic!("Asset paused");
+        }
+    }
+    
+    fn when_loan_assets_not_paused(env: &Env, loan: &Loan) {
+        Self::when_asset_not_paused(env, &loan.collateral_asset);
+        Self::when_asset_not_paused(env, &loan.borrowed_asset);
+    }
+    
+    // Active loans in `start_id..start_id + limit` that are liquidatable,
+    // with the reward for liquidating each now. `limit` is capped at
+    // MAX_LIQUIDATABLE_SCAN.
+    fn scan_liquidatable(env: &Env, start_id: u64, limit: u64) -> Vec<(u64, i128)> {
+        let loan_count: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::LoanCounter)
+            .unwrap_or(0);
+        let start_id = start_id.max(1);
+        let end = loan_count.min(start_id.saturating_add(limit.min(MAX_LIQUIDATABLE_SCAN)).saturating_sub(1));
+        
+        let mut liquidatable = Vec::new(env);
+        for loan_id in start_id..=end {
+            let mut loan = Self::get_loan(env, loan_id);
+            if loan.status != LoanStatus::Active {
+                continue;
+            }
+            
+            Self::accrue_interest(env, &mut loan);
+            if let Some(ratio) = Self::liquidatable_ratio(env, loan_id, &loan) {
+                liquidatable.push_back((loan_id, Self::liquidation_reward(env, &loan, ratio)));
+            }
+        }
+        liquidatable
+    }
+    
+    // Reward for liquidating `loan` at collateral ratio `ratio`, per the
+    // configured LiquidationParams
+    fn liquidation_reward(env: &Env, loan: &Loan, ratio: i128) -> i128 {
+        let params = Self::get_liquidation_params(env.clone());
+        let max_bonus_bps = params.max_bonus_bps as i128;
+        let bonus_bps = if ratio <= 0 {
+            max_bonus_bps
+        } else {
+            (params.bonus_bps as i128 * Self::effective_threshold(env, loan) / ratio).min(max_bonus_bps)
+        };
+        
+        (loan.collateral_amount * bonus_bps) / 10000
+    }
+    
+    // Value of `reward` collateral in the debt asset. The debt asset must be
+    // a token this contract holds enough of to pay out.
+    fn convert_to_debt_asset(
+        env: &Env,
+        loan: &Loan,
+        reward: i128,
+        collateral_price: i128,
+        borrowed_price: i128,
+    ) -> i128 {
+        let token_address = match &loan.borrowed_asset {
+            AssetType::Stellar(token_address) => token_address,
+            AssetType::Crypto(_) => panic!("Debt asset not payable"),
+        };
+        
+        let converted = reward * collateral_price / borrowed_price;
+        if token::Client::new(env, token_address).balance(&env.current_contract_address()) < converted {
+            panic!("Insufficient debt asset liquidity");
+        }
+        converted
+    }
+    
+    fn validate_liquidation_params(params: &LiquidationParams) {
+        if params.bonus_bps > params.max_bonus_bps || params.max_bonus_bps > MAX_LIQUIDATION_BONUS_BPS {
+            panic!("Invalid liquidation bonus");
+        }
+    }
+    
+    // Price check behind check_liquidation for an active loan with interest
+    // already accrued. Returns the collateral ratio if the loan can be
+    // liquidated. Does not write state.
+    fn liquidatable_ratio(env: &Env, loan_id: u64, loan: &Loan) -> Option<i128> {
+        // Get prices from Reflector oracle (using external oracle for all assets)
+        let oracle_address = Self::get_oracle_address(env);
+        let collateral_price_data = Self::get_price_data(env, &oracle_address, &loan.collateral_asset);
+        let borrowed_price_data = Self::get_price_data(env, &oracle_address, &loan.borrowed_asset);
+        
+        let (collateral_price_data, borrowed_price_data) = match (collateral_price_data, borrowed_price_data) {
+            (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+            _ => {
+                log!(env, "Price data unavailable for loan {}", loan_id);
+                return None;
+            }
+        };
+        
+        // Never act on a price that has stopped updating
+        if !Self::is_fresh(env, &collateral_price_data) || !Self::is_fresh(env, &borrowed_price_data) {
+            log!(env, "Stale price data for loan {}", loan_id);
+            return None;
+        }
+        
+        let collateral_price = collateral_price_data.price;
+        let borrowed_price = borrowed_price_data.price;
+        
+        // Calculate current collateralization ratio
+        let collateralization_ratio = Self::ratio_bps(
+            env,
+            collateral_price,
+            loan.collateral_amount,
+            borrowed_price,
+            loan.borrowed_amount,
+        );
+        
+        let threshold = Self::effective_threshold(env, loan);
+        log!(env, "Loan {} collateral ratio: {}bps (threshold: {}bps)", 
+             loan_id, collateralization_ratio, threshold);
+        
+        // A matured term loan is due whatever its collateralization
+        if loan.maturity.is_some_and(|maturity| env.ledger().timestamp() >= maturity) {
+            log!(env, "Loan {} matured unrepaid", loan_id);
+            return Some(collateralization_ratio);
+        }
+        
+        // Check if below liquidation threshold
+        if collateralization_ratio > threshold {
+            return None;
+        }
+        
+        if !Self::secondary_oracle_agrees(env, loan, collateral_price, borrowed_price) {
+            log!(env, "Liquidation of loan {} blocked: oracles disagree", loan_id);
+            return None;
+        }
+        
+        log!(env, "LIQUIDATION TRIGGERED for loan {}", loan_id);
+        Some(collateralization_ratio)
+    }
+    
+    // Add to the running total borrowed against `asset`, enforcing its cap
+    fn increase_total_borrowed(env: &Env, asset: &AssetType, amount: i128) {
+        let key = DataKey::TotalBorrowed(asset.clone());
+        let total: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+        let new_total = total + amount;
+        
+        let cap: Option<i128> = env.storage().persistent().get(&DataKey::BorrowCap(asset.clone()));
+        if cap.is_some_and(|cap| new_total > cap) {
+            panic!("Borrow cap exceeded");
+        }
+        
+        env.storage().persistent().set(&key, &new_total);
+    }
+    
+    // The total tracks principal only, so repayments that also cover
+    // interest are clamped at zero
+    fn decrease_total_borrowed(env: &Env, asset: &AssetType, amount: i128) {
+        let key = DataKey::TotalBorrowed(asset.clone());
+        let total: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+        env.storage().persistent().set(&key, &(total - amount).max(0));
+    }
+    
+    // On-chain token collateral is held by this contract while the loan is
+    // open. Symbol-only collateral is tracked by amount alone.
+    fn deposit_collateral(env: &Env, owner: &Address, asset: &AssetType, amount: i128) {
+        if let AssetType::Stellar(token_address) = asset {
+            token::Client::new(env, token_address).transfer(owner, env.current_contract_address(), &amount);
+        }
+    }
+    
+    // Send a closed loan's collateral back to its owner
+    fn return_collateral(env: &Env, loan_id: u64, loan: &Loan) {
+        Self::release_collateral(env, &loan.owner, &loan.collateral_asset, loan.collateral_amount);
+        
+        LoanClosed {
+            loan_id,
+            owner: loan.owner.clone(),
+            collateral_returned: loan.collateral_amount,
+        }.publish(env);
+    }
+    
+    fn release_collateral(env: &Env, owner: &Address, asset: &AssetType, amount: i128) {
+        if let AssetType::Stellar(token_address) = asset {
+            token::Client::new(env, token_address).transfer(&env.current_contract_address(), owner, &amount);
+        }
+    }
+    
+    // Repaid tokens go back into the liquidity disburse pays out of
+    fn collect_repayment(env: &Env, owner: &Address, asset: &AssetType, amount: i128) {
+        if let AssetType::Stellar(token_address) = asset {
+            token::Client::new(env, token_address).transfer(owner, env.current_contract_address(), &amount);
+        }
+    }
+    
+    // Borrowed tokens are paid out of liquidity held by this contract
+    fn disburse(env: &Env, owner: &Address, asset: &AssetType, amount: i128) {
+        if let AssetType::Stellar(token_address) = asset {
+            token::Client::new(env, token_address).transfer(&env.current_contract_address(), owner, &amount);
+        }
+    }
+    
+    // Add interest for the time since `last_accrued` to borrowed_amount.
+    // Applying this on every touch compounds the debt. The fraction of a unit
+    // left over is carried in interest_remainder, so frequent touches can't
+    // round the interest away.
+    fn accrue_interest(env: &Env, loan: &mut Loan) {
+        let now = env.ledger().timestamp();
+        let elapsed = now.saturating_sub(loan.last_accrued) as i128;
+        
+        if loan.status == LoanStatus::Active && elapsed > 0 && loan.interest_rate_bps > 0 {
+            let accrued = loan.borrowed_amount * loan.interest_rate_bps as i128 * elapsed
+                + loan.interest_remainder;
+            loan.borrowed_amount += accrued / (10000 * SECONDS_PER_YEAR);
+            loan.interest_remainder = accrued % (10000 * SECONDS_PER_YEAR);
+        }
+        loan.last_accrued = now;
+    }
+    
+    // Latest price from `oracle_address`, or None when missing or stale
+    fn get_price(env: &Env, oracle_address: &Address, asset: &AssetType) -> Option<i128> {
+        Self::get_price_data(env, oracle_address, asset)
+            .filter(|data| Self::is_fresh(env, data))
+            .map(|data| data.price)
+    }
+    
+    // Last price rescaled to STELLAR_DECIMALS, so the primary and secondary
+    // oracles compare correctly even when their feeds differ in precision
+    fn get_price_data(env: &Env, oracle_address: &Address, asset: &AssetType) -> Option<PriceData> {
+        let client = ReflectorClient::new(env, oracle_address);
+        let price_data = match asset {
+            AssetType::Crypto(ref symbol) => client.lastprice(&Asset::Other(symbol.clone())),
+            AssetType::Stellar(ref addr) => client.lastprice(&Asset::Stellar(addr.clone())),
+        }?;
+        Some(PriceData {
+            price: normalize_price(price_data.price, client.decimals(), STELLAR_DECIMALS),
+            timestamp: price_data.timestamp,
+        })
+    }
+    
+    fn is_fresh(env: &Env, price_data: &PriceData) -> bool {
+        let staleness_seconds = Self::get_staleness_seconds(env.clone());
+        env.ledger().timestamp().saturating_sub(price_data.timestamp) <= staleness_seconds
+    }
+    
+    // Confirm an undercollateralized reading against the secondary oracle.
+    // Assets the secondary oracle doesn't quote fall back to the primary alone.
+    fn secondary_oracle_agrees(
+        env: &Env,
+        loan: &Loan,
+        collateral_price: i128,
+        borrowed_price: i128,
+    ) -> bool {
+        let config: Option<OracleConsensusConfig> = env.storage()
+            .instance()
+            .get(&DataKey::OracleConsensus);
+        let config = match config {
+            Some(config) if config.required => config,
+            _ => return true,
+        };
+        
+        let secondary_collateral = Self::get_price_data(env, &config.secondary_oracle, &loan.collateral_asset);
+        let secondary_borrowed = Self::get_price_data(env, &config.secondary_oracle, &loan.borrowed_asset);
+        let (secondary_collateral, secondary_borrowed) = match (secondary_collateral, secondary_borrowed) {
+            (Some(collateral), Some(borrowed)) => (collateral, borrowed),
+            _ => return true,
+        };
+        
+        // A secondary feed that exists but has gone stale cannot confirm
+        if !Self::is_fresh(env, &secondary_collateral) || !Self::is_fresh(env, &secondary_borrowed) {
+            return false;
+        }
+        let (secondary_collateral, secondary_borrowed) = (secondary_collateral.price, secondary_borrowed.price);
+        
+        let within_band = |primary: i128, secondary: i128| {
+            (primary - secondary).abs() * 10000 <= primary * config.agreement_band_bps
+        };
+        if !within_band(collateral_price, secondary_collateral)
+            || !within_band(borrowed_price, secondary_borrowed)
+        {
+            return false;
+        }
+        
+        let secondary_ratio = Self::ratio_bps(
+            env,
+            secondary_collateral,
+            loan.collateral_amount,
+            secondary_borrowed,
+            loan.borrowed_amount,
+        );
+        secondary_ratio <= Self::effective_threshold(env, loan)
+    }
+    
+    // Threshold in force for the loan, never below its collateral's floor
+    fn effective_threshold(env: &Env, loan: &Loan) -> i128 {
+        let threshold = if env.ledger().timestamp() < loan.threshold_effective_at {
+            loan.previous_threshold
+        } else {
+            loan.liquidation_threshold
+        };
+        threshold.max(Self::threshold_floor(env, &loan.collateral_asset))
+    }
+    
+    fn threshold_floor(env: &Env, asset: &AssetType) -> i128 {
+        env.storage()
+            .persistent()
+            .get::<_, AssetRiskParams>(&DataKey::AssetRiskParams(asset.clone()))
+            .map(|params| params.min_threshold)
+            .unwrap_or(0)
+    }
+    
     fn get_oracle_address(env: &Env) -> Address {
         env.storage()
             .instance()
             .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)))
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)))
     }
     
-    fn add_liquidation_reward(env: &Env, liquidator: &Address, amount: i128) {
-        let mut rewards: Map<Address, i128> = env.storage()
-            .persistent()
-            .get(&DataKey::LiquidationRewards)
-            .unwrap_or(Map::new(&env));
-        
-        let current = rewards.get(liquidator.clone()).unwrap_or(0);
-        rewards.set(liquidator.clone(), current + amount);
-        
-        env.storage().persistent().set(&DataKey::LiquidationRewards, &rewards);
+    fn add_liquidation_reward(env: &Env, liquidator: &Address, asset: &AssetType, amount: i128) {
+        let key = DataKey::LiquidationRewards(liquidator.clone(), asset.clone());
+        let current: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+        env.storage().persistent().set(&key, &(current + amount));
         
         // Extend TTL
         env.storage()
             .persistent()
-            .extend_ttl(&DataKey::LiquidationRewards, 100, MAX_PERSISTENT_TTL);
+            .extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        
+        Self::adjust_total_pending_rewards(env, asset, amount);
+    }
+    
+    fn adjust_total_pending_rewards(env: &Env, asset: &AssetType, delta: i128) {
+        let key = DataKey::TotalPendingRewards(asset.clone());
+        let total = Self::get_total_pending_rewards(env.clone(), asset.clone()) + delta;
+        env.storage().persistent().set(&key, &total);
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
     }
 }
\ No newline at end of file
@@ -0,0 +1,1423 @@
+#![cfg(test)]
+
+use super::*;
+use mock_reflector::{Asset as MockAsset, MockReflector, MockReflectorClient};
+use soroban_sdk::{
+    contract, contractimpl, contracttype, symbol_short, testutils::{Address as _, Events, Ledger},
+    token::{StellarAssetClient, TokenClient},
+    vec, Env, Address, IntoVal, Map, Val
+};
+
+// Stand-in release for upgrade tests; any valid contract WASM will do
+const UPGRADE_WASM: &[u8] = include_bytes!("../../testdata/add_u64.wasm");
+
+const LIQUIDATION_BONUS_BPS: u32 = 500; // 5%
+
+#[contracttype]
+enum ReentrantKey {
+    Target,
+    Reentered,
+}
+
+// Debt token that tries to liquidate the same loan again from inside the
+// reward transfer, recording whether the nested call went through
+#[contract]
+struct ReentrantToken;
+
+#[contractimpl]
+impl ReentrantToken {
+    pub fn set_target(env: Env, contract: Address, loan_id: u64) {
+        env.storage().instance().set(&ReentrantKey::Target, &(contract, loan_id));
+    }
+
+    // None until a transfer has happened
+    pub fn reentered(env: Env) -> Option<bool> {
+        env.storage().instance().get(&ReentrantKey::Reentered)
+    }
+
+    pub fn balance(_env: Env, _id: Address) -> i128 {
+        i128::MAX
+    }
+
+    pub fn transfer(env: Env, _from: Address, to: Address, _amount: i128) {
+        let (contract, loan_id): (Address, u64) = env.storage().instance().get(&ReentrantKey::Target).unwrap();
+        let client = LiquidationProtectionClient::new(&env, &contract);
+        let reentered = client.try_liquidate_position(&to, &loan_id, &None, &None).is_ok();
+        env.storage().instance().set(&ReentrantKey::Reentered, &reentered);
+    }
+}
+
+struct TestSetup<'a> {
+    env: Env,
+    client: LiquidationProtectionClient<'a>,
+    oracle: MockReflectorClient<'a>,
+}
+
+fn setup() -> TestSetup<'static> {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let oracle_id = env.register(MockReflector, ());
+    let oracle = MockReflectorClient::new(&env, &oracle_id);
+
+    let contract_id = env.register(LiquidationProtection, ());
+    let client = LiquidationProtectionClient::new(&env, &contract_id);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &oracle_id, &LIQUIDATION_BONUS_BPS);
+
+    TestSetup { env, client, oracle }
+}
+
+fn set_price(setup: &TestSetup, asset: &Symbol, price: i128) {
+    set_oracle_price(setup, &setup.oracle, asset, price);
+}
+
+fn set_oracle_price(setup: &TestSetup, oracle: &MockReflectorClient, asset: &Symbol, price: i128) {
+    let timestamp = setup.env.ledger().timestamp();
+    oracle.set_price(&MockAsset::Other(asset.clone()), &price, &timestamp);
+}
+
+// Register a second mock oracle and require it to agree within 2%
+fn enable_consensus(setup: &TestSetup) -> MockReflectorClient<'static> {
+    let secondary_id = setup.env.register(MockReflector, ());
+    setup.client.set_oracle_consensus(&setup.client.get_admin(), &OracleConsensusConfig {
+        required: true,
+        secondary_oracle: secondary_id.clone(),
+        agreement_band_bps: 200,
+    });
+    MockReflectorClient::new(&setup.env, &secondary_id)
+}
+
+// 1000 XLM at $1 against 500 USDC borrowed, 150% threshold
+fn create_xlm_loan(setup: &TestSetup, owner: &Address) -> u64 {
+    create_loan_with_collateral(setup, owner, symbol_short!("XLM"), 10_000_000_000)
+}
+
+// Loan of 500 USDC against `amount` of `collateral` priced at $1
+fn create_loan_with_collateral(setup: &TestSetup, owner: &Address, collateral: Symbol, amount: i128) -> u64 {
+    set_price(setup, &collateral, 10_000_000);
+    set_price(setup, &symbol_short!("USDC"), 10_000_000);
+
+    setup.client.create_loan(
+        owner,
+        &AssetType::Crypto(collateral),
+        &amount,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    )
+}
+
+#[test]
+fn test_healthy_loan_not_liquidatable() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    assert_eq!(loan_id, 1);
+    assert!(!setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_loan_getters() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let other = Address::generate(env);
+
+    let first = create_xlm_loan(&setup, &owner);
+    let second = create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+    assert_eq!(setup.client.get_user_loans(&owner), vec![env, first, second]);
+    assert_eq!(setup.client.get_user_loans(&other), Vec::<u64>::new(env));
+
+    let loan = setup.client.get_loan_details(&first);
+    assert_eq!(loan.owner, owner);
+    assert_eq!(loan.collateral_asset, AssetType::Crypto(symbol_short!("XLM")));
+    assert_eq!(loan.borrowed_amount, 5_000_000_000);
+    assert_eq!(loan.status, LoanStatus::Active);
+}
+
+#[test]
+#[should_panic(expected = "Loan not found")]
+fn test_get_loan_details_missing() {
+    let setup = setup();
+    setup.client.get_loan_details(&99);
+}
+
+#[test]
+fn test_liquidation_after_price_drop() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // XLM falls to $0.70: 700 / 500 = 140% < 150%
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    assert!(setup.client.check_liquidation(&loan_id));
+
+    let reward = setup.client.liquidate_position(&liquidator, &loan_id, &None, &None);
+    assert_eq!(reward, 10_000_000_000 * 500 / 10000);
+    assert!(!setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_interest_accrues_on_debt() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+
+    client.set_interest_rate(&client.get_admin(), &1000); // 10% a year
+    let loan_id = create_xlm_loan(&setup, &owner);
+    assert_eq!(client.get_current_debt(&loan_id), 5_000_000_000);
+
+    setup.env.ledger().with_mut(|li| li.timestamp += 31_536_000);
+    assert_eq!(client.get_current_debt(&loan_id), 5_500_000_000);
+
+    // Repayment is applied after interest
+    client.repay_loan(&owner, &loan_id, &500_000_000);
+    assert_eq!(client.get_current_debt(&loan_id), 5_000_000_000);
+
+    // Interest alone can push a loan under its threshold: 800 / 550 < 150%
+    set_price(&setup, &symbol_short!("XLM"), 8_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    assert!(!client.check_liquidation(&loan_id));
+    setup.env.ledger().with_mut(|li| li.timestamp += 31_536_000);
+    set_price(&setup, &symbol_short!("XLM"), 8_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    assert!(client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_frequent_touches_do_not_round_away_interest() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    // 5 USDC at 10% accrues about 0.16 units a second
+    client.set_interest_rate(&client.get_admin(), &1000);
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &100_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &50_000_000,
+        &15000,
+    );
+
+    for _ in 0..100 {
+        env.ledger().with_mut(|li| li.timestamp += 5);
+        client.check_liquidation(&loan_id);
+    }
+
+    // The same as a single accrual over 500 seconds
+    assert_eq!(client.get_current_debt(&loan_id), 50_000_000 + 50_000_000 * 1000 * 500 / (10000 * 31_536_000));
+}
+
+#[test]
+fn test_over_repayment_stops_at_zero_debt() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    let collateral = AssetType::Crypto(symbol_short!("XLM"));
+    assert_eq!(client.get_total_borrowed(&collateral), 5_000_000_000);
+
+    client.repay_loan(&owner, &loan_id, &8_000_000_000);
+    let (_, _, data) = env.events().all().iter().find(|(_, topics, _)| {
+        *topics == (Symbol::new(env, "loan"), Symbol::new(env, "repaid"), loan_id, owner.clone()).into_val(env)
+    }).unwrap();
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let amount: i128 = data.get(Symbol::new(env, "amount")).unwrap().into_val(env);
+    assert_eq!(amount, 5_000_000_000);
+    assert_eq!(client.get_current_debt(&loan_id), 0);
+    assert_eq!(client.get_total_borrowed(&collateral), 0);
+
+    // A repaid loan is never liquidatable and later calls don't trap
+    assert!(!client.check_liquidation(&loan_id));
+    assert_eq!(client.get_health_factor(&loan_id), 0);
+    assert_eq!(LiquidationProtection::ratio_bps(env, 10_000_000, 10_000_000_000, 10_000_000, 0), i128::MAX);
+}
+
+#[test]
+fn test_withdraw_collateral() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    StellarAssetClient::new(env, &token_address).mint(&owner, &10_000_000_000);
+    let collateral = AssetType::Stellar(token_address.clone());
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+
+    // 1000 tokens against 500 USDC: 200%
+    let loan_id = client.create_loan(
+        &owner,
+        &collateral,
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    );
+    assert_eq!(token.balance(&client.address), 10_000_000_000);
+
+    // Down to 160%
+    client.withdraw_collateral(&owner, &loan_id, &2_000_000_000);
+    assert_eq!(token.balance(&owner), 2_000_000_000);
+    assert_eq!(token.balance(&client.address), 8_000_000_000);
+
+    // Another 100 would leave exactly 140%, below the threshold
+    assert!(client.try_withdraw_collateral(&owner, &loan_id, &1_000_000_000).is_err());
+    assert_eq!(token.balance(&client.address), 8_000_000_000);
+
+    // A partial repayment keeps the loan open with its collateral held
+    client.repay_loan(&owner, &loan_id, &1_000_000_000);
+    assert_eq!(client.get_loan_details(&loan_id).status, LoanStatus::Active);
+    assert_eq!(token.balance(&client.address), 8_000_000_000);
+
+    // Repaying the rest closes the loan and returns the collateral
+    client.repay_loan(&owner, &loan_id, &4_000_000_000);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "closed"), loan_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let returned: i128 = data.get(Symbol::new(env, "collateral_returned")).unwrap().into_val(env);
+    assert_eq!(returned, 8_000_000_000);
+    assert_eq!(token.balance(&owner), 10_000_000_000);
+    assert_eq!(client.get_loan_details(&loan_id).status, LoanStatus::Closed);
+    assert!(client.try_repay_loan(&owner, &loan_id, &1).is_err());
+}
+
+#[test]
+fn test_borrow_more() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    // The contract holds USDC liquidity to lend out
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    StellarAssetClient::new(env, &usdc_address).mint(&client.address, &10_000_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(usdc_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+
+    // 1000 XLM against 500 USDC: 200%
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &10_000_000_000,
+        &AssetType::Stellar(usdc_address.clone()),
+        &5_000_000_000,
+        &15000,
+    );
+
+    // Up to 600 USDC: 166%
+    client.borrow_more(&owner, &loan_id, &1_000_000_000);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "borrowed"), loan_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let borrowed: i128 = data.get(Symbol::new(env, "borrowed_amount")).unwrap().into_val(env);
+    assert_eq!(borrowed, 6_000_000_000);
+    assert_eq!(usdc.balance(&owner), 1_000_000_000);
+    assert_eq!(client.get_current_debt(&loan_id), 6_000_000_000);
+
+    // Another 100 would leave 142%, below the threshold
+    assert!(client.try_borrow_more(&owner, &loan_id, &1_000_000_000).is_err());
+    assert_eq!(usdc.balance(&owner), 1_000_000_000);
+}
+
+#[test]
+fn test_repay_pulls_borrowed_tokens() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    StellarAssetClient::new(env, &usdc_address).mint(&client.address, &10_000_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(usdc_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &10_000_000_000,
+        &AssetType::Stellar(usdc_address.clone()),
+        &5_000_000_000,
+        &15000,
+    );
+    client.borrow_more(&owner, &loan_id, &1_000_000_000);
+    assert_eq!(usdc.balance(&owner), 1_000_000_000);
+    assert_eq!(usdc.balance(&client.address), 9_000_000_000);
+
+    // Repaying more than the owner holds fails and leaves the debt alone
+    assert!(client.try_repay_loan(&owner, &loan_id, &6_000_000_000).is_err());
+    assert_eq!(client.get_current_debt(&loan_id), 6_000_000_000);
+
+    client.repay_loan(&owner, &loan_id, &1_000_000_000);
+    assert_eq!(usdc.balance(&owner), 0);
+    assert_eq!(usdc.balance(&client.address), 10_000_000_000);
+    assert_eq!(client.get_current_debt(&loan_id), 5_000_000_000);
+
+    // Settling the rest closes the loan
+    StellarAssetClient::new(env, &usdc_address).mint(&owner, &5_000_000_000);
+    client.repay_loan(&owner, &loan_id, &5_000_000_000);
+    assert_eq!(usdc.balance(&owner), 0);
+    assert_eq!(usdc.balance(&client.address), 15_000_000_000);
+    assert_eq!(client.get_loan_details(&loan_id).status, LoanStatus::Closed);
+}
+
+#[test]
+fn test_migrate_legacy_loans() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    let mut loan_ids = Vec::new(env);
+    for amount in [10_000_000_000i128, 12_000_000_000, 15_000_000_000] {
+        loan_ids.push_back(create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), amount));
+    }
+
+    // Rewrite them as a pre-upgrade deployment stored them
+    env.as_contract(&client.address, || {
+        let loans: Map<u64, Loan> = env.storage().persistent().get(&DataKey::Loans).unwrap();
+        let mut legacy_loans: Map<u64, LegacyLoan> = Map::new(env);
+        for (loan_id, loan) in loans.iter() {
+            legacy_loans.set(loan_id, LegacyLoan {
+                owner: loan.owner,
+                collateral_asset: loan.collateral_asset,
+                collateral_amount: loan.collateral_amount,
+                borrowed_asset: loan.borrowed_asset,
+                borrowed_amount: loan.borrowed_amount,
+                liquidation_threshold: loan.liquidation_threshold,
+                created_at: loan.created_at,
+                status: loan.status,
+            });
+        }
+        env.storage().persistent().set(&DataKey::Loans, &legacy_loans);
+        env.storage().persistent().remove(&DataKey::TotalBorrowed(xlm.clone()));
+    });
+    assert!(client.try_get_loan_details(&1).is_err());
+
+    // Loans opened after the upgrade sit alongside the legacy records
+    let new_loan_id = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 20_000_000_000);
+    assert_eq!(client.get_loan_details(&new_loan_id).collateral_amount, 20_000_000_000);
+
+    assert!(client.migrate_loans(&client.get_admin()));
+    assert!(client.migrate_loans(&client.get_admin()));
+
+    for (loan_id, amount) in loan_ids.iter().zip([10_000_000_000i128, 12_000_000_000, 15_000_000_000]) {
+        let loan = client.get_loan_details(&loan_id);
+        assert_eq!((loan.collateral_amount, loan.borrowed_amount), (amount, 5_000_000_000));
+        assert_eq!((loan.interest_rate_bps, loan.maturity), (0, None));
+        assert_eq!(client.get_effective_threshold(&loan_id), loan.liquidation_threshold);
+        assert_eq!(client.get_loan_collateral(&loan_id), vec![env, (xlm.clone(), amount)]);
+        // $1 per XLM: same value as the single-collateral fields
+        assert_eq!(client.get_collateral_value(&loan_id), 10_000_000 * amount);
+    }
+    assert_eq!(client.get_total_borrowed(&xlm), 4 * 5_000_000_000);
+}
+
+#[test]
+fn test_collateral_value_normalizes_and_does_not_overflow() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // $1 quoted at 14 decimals values the same as at 7
+    setup.oracle.set_decimals(&14);
+    set_price(&setup, &symbol_short!("XLM"), 100_000_000_000_000);
+    assert_eq!(client.get_collateral_value(&loan_id), 10_000_000 * 10_000_000_000);
+
+    // A whale-sized position whose value is past i128
+    setup.oracle.set_decimals(&7);
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000_000_000_000_000);
+    env.as_contract(&client.address, || {
+        let mut loans: Map<u64, Loan> = env.storage().persistent().get(&DataKey::Loans).unwrap();
+        let mut loan = loans.get(loan_id).unwrap();
+        loan.collateral_amount = 100_000_000_000_000_000_000;
+        loans.set(loan_id, loan);
+        env.storage().persistent().set(&DataKey::Loans, &loans);
+    });
+    assert_eq!(client.get_collateral_value(&loan_id), i128::MAX);
+}
+
+#[test]
+fn test_stale_price_blocks_liquidation() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    assert_eq!(client.get_staleness_seconds(), 600);
+
+    // The undercollateralized price is now 11 minutes old
+    setup.env.ledger().with_mut(|li| li.timestamp += 660);
+    assert!(!client.check_liquidation(&loan_id));
+
+    // A wider window accepts it again
+    client.set_staleness_seconds(&client.get_admin(), &900);
+    assert!(client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_claim_liquidation_rewards() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let collateral = AssetType::Stellar(token_address.clone());
+    StellarAssetClient::new(env, &token_address).mint(&owner, &10_000_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    let loan_id = client.create_loan(
+        &owner,
+        &collateral,
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    );
+
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &7_000_000, &env.ledger().timestamp());
+    let reward = client.liquidate_position(&liquidator, &loan_id, &None, &None);
+    assert_eq!(reward, 10_000_000_000 * 500 / 10000);
+    assert_eq!(client.get_pending_rewards(&liquidator, &collateral), reward);
+    assert_eq!(client.get_pending_rewards(&liquidator, &AssetType::Crypto(symbol_short!("XLM"))), 0);
+    assert_eq!(client.get_loan_details(&loan_id).collateral_amount, 10_000_000_000 - reward);
+
+    // Paid in the seized collateral token itself
+    assert_eq!(client.claim_liquidation_rewards(&liquidator, &collateral), reward);
+    assert_eq!(token.balance(&liquidator), reward);
+    assert_eq!(token.balance(&client.address), 10_000_000_000 - reward);
+    assert_eq!(client.get_pending_rewards(&liquidator, &collateral), 0);
+    assert!(client.try_claim_liquidation_rewards(&liquidator, &collateral).is_err());
+}
+
+#[test]
+fn test_total_pending_rewards() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let first = Address::generate(env);
+    let second = Address::generate(env);
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+    assert_eq!(client.get_total_pending_rewards(&xlm), 0);
+
+    let loan_ids = [
+        create_xlm_loan(&setup, &owner),
+        create_xlm_loan(&setup, &owner),
+        create_xlm_loan(&setup, &owner),
+    ];
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    let first_reward = client.liquidate_position(&first, &loan_ids[0], &None, &None)
+        + client.liquidate_position(&first, &loan_ids[1], &None, &None);
+    let second_reward = client.liquidate_position(&second, &loan_ids[2], &None, &None);
+    assert_eq!(client.get_total_pending_rewards(&xlm), first_reward + second_reward);
+    assert_eq!(client.get_total_pending_rewards(&AssetType::Crypto(symbol_short!("BTC"))), 0);
+
+    client.claim_liquidation_rewards(&first, &xlm);
+    assert_eq!(client.get_total_pending_rewards(&xlm), second_reward);
+    client.claim_liquidation_rewards(&second, &xlm);
+    assert_eq!(client.get_total_pending_rewards(&xlm), 0);
+}
+
+#[test]
+#[should_panic(expected = "Position not eligible for liquidation")]
+fn test_liquidate_healthy_loan_rejected() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    setup.client.liquidate_position(&liquidator, &loan_id, &None, &None);
+}
+
+#[test]
+fn test_consensus_liquidation_allowed() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let secondary = enable_consensus(&setup);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // Both oracles report XLM around $0.70
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("XLM"), 7_050_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("USDC"), 10_000_000);
+
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_consensus_liquidation_blocked_by_disagreement() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let secondary = enable_consensus(&setup);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // Primary is manipulated down while the secondary still reports $1
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("XLM"), 10_000_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("USDC"), 10_000_000);
+
+    assert!(!setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_consensus_normalizes_secondary_decimals() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let secondary = enable_consensus(&setup);
+    secondary.set_decimals(&14);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // The same ~$0.70 reading, reported with 14 decimals
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("XLM"), 70_500_000_000_000);
+    set_oracle_price(&setup, &secondary, &symbol_short!("USDC"), 100_000_000_000_000);
+
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_consensus_falls_back_to_single_feed() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    enable_consensus(&setup);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // Secondary oracle doesn't quote these assets
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_check_health_batch_report() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+
+    // 200% collateralized: healthy
+    let healthy = create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+    // 160% collateralized: within 10% of the 150% threshold
+    let at_risk = create_loan_with_collateral(&setup, &owner, symbol_short!("ETH"), 8_000_000_000);
+    // Two loans on XLM that become undercollateralized
+    let liquidatable_a = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 10_000_000_000);
+    let liquidatable_b = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 8_000_000_000);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    setup.client.check_health_batch(&vec![env, healthy, at_risk, liquidatable_a, liquidatable_b]);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "loan"), Symbol::new(env, "health_report")).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let count = |name: &str| -> u32 { data.get(Symbol::new(env, name)).unwrap().into_val(env) };
+    assert_eq!(count("healthy"), 1);
+    assert_eq!(count("at_risk"), 1);
+    assert_eq!(count("liquidatable"), 2);
+    let ids: Vec<u64> = data.get(Symbol::new(env, "liquidatable_ids")).unwrap().into_val(env);
+    assert_eq!(ids, vec![env, liquidatable_a, liquidatable_b]);
+
+    // Nothing was liquidated
+    assert!(setup.client.check_liquidation(&liquidatable_a));
+}
+
+#[test]
+fn test_get_liquidatable_loans_paginates() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+
+    let healthy = create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+    let underwater_a = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 10_000_000_000);
+    let underwater_b = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 8_000_000_000);
+    let closed = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 9_000_000_000);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    setup.client.liquidate_position(&liquidator, &closed, &None, &None);
+
+    assert_eq!(setup.client.get_liquidatable_loans(&1, &10), vec![env, underwater_a, underwater_b]);
+    assert_eq!(setup.client.get_liquidatable_loans(&healthy, &2), vec![env, underwater_a]);
+    assert_eq!(setup.client.get_liquidatable_loans(&underwater_b, &2), vec![env, underwater_b]);
+    assert_eq!(setup.client.get_liquidatable_loans(&5, &10), Vec::<u64>::new(env));
+}
+
+#[test]
+fn test_borrow_cap_per_collateral() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let admin = setup.client.get_admin();
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    assert_eq!(setup.client.get_max_borrow_per_collateral(&xlm), None);
+    setup.client.set_max_borrow_per_collateral(&admin, &xlm, &10_000_000_000);
+
+    // Two 500 USDC loans fill the 1000 USDC cap
+    let first = create_xlm_loan(&setup, &owner);
+    create_xlm_loan(&setup, &owner);
+    assert_eq!(setup.client.get_total_borrowed(&xlm), 10_000_000_000);
+    assert!(setup.client.try_create_loan(
+        &owner,
+        &xlm,
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    ).is_err());
+
+    // Other collateral assets are uncapped
+    create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+
+    // Repaying frees capacity
+    setup.client.repay_loan(&owner, &first, &5_000_000_000);
+    assert_eq!(setup.client.get_total_borrowed(&xlm), 5_000_000_000);
+    create_xlm_loan(&setup, &owner);
+}
+
+#[test]
+fn test_borrow_cap_requires_admin() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    assert!(setup.client.try_set_max_borrow_per_collateral(&other, &xlm, &1).is_err());
+}
+
+#[test]
+fn test_asset_risk_params_floor_new_loans() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let admin = setup.client.get_admin();
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+    let usdc = AssetType::Crypto(symbol_short!("USDC"));
+    let params = AssetRiskParams { max_ltv_bps: 5000, min_threshold: 16000 };
+
+    assert_eq!(setup.client.get_asset_risk_params(&xlm), None);
+    setup.client.set_asset_risk_params(&admin, &xlm, &params);
+    assert_eq!(setup.client.get_asset_risk_params(&xlm), Some(params));
+
+    // 150% is below the XLM floor
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    assert!(setup.client.try_create_loan(&owner, &xlm, &10_000_000_000, &usdc, &5_000_000_000, &15000).is_err());
+
+    // 600 USDC against 1000 XLM is a 60% LTV
+    assert!(setup.client.try_create_loan(&owner, &xlm, &10_000_000_000, &usdc, &6_000_000_000, &16000).is_err());
+
+    // At the floor and the LTV cap the loan opens
+    setup.client.create_loan(&owner, &xlm, &10_000_000_000, &usdc, &5_000_000_000, &16000);
+
+    // Other collateral keeps the borrower's choice
+    create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+}
+
+#[test]
+fn test_max_ltv_caps_borrow_more_and_withdrawal() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let admin = client.get_admin();
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+    let usdc = AssetType::Crypto(symbol_short!("USDC"));
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+
+    // 400 USDC against 1000 XLM: 40% LTV under a 50% cap
+    client.set_asset_risk_params(&admin, &xlm, &AssetRiskParams { max_ltv_bps: 5000, min_threshold: 16000 });
+    let loan_id = client.create_loan(&owner, &xlm, &10_000_000_000, &usdc, &4_000_000_000, &16000);
+
+    // 550 USDC would be 55%, still above the 160% threshold
+    assert!(client.try_borrow_more(&owner, &loan_id, &1_500_000_000).is_err());
+    client.borrow_more(&owner, &loan_id, &1_000_000_000);
+
+    // Dropping to 900 XLM would be 55.6%
+    assert!(client.try_withdraw_collateral(&owner, &loan_id, &1_000_000_000).is_err());
+    assert_eq!(client.get_loan_details(&loan_id).borrowed_amount, 5_000_000_000);
+    assert_eq!(client.get_loan_details(&loan_id).collateral_amount, 10_000_000_000);
+}
+
+#[test]
+fn test_asset_risk_params_raise_open_loan_threshold() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let admin = setup.client.get_admin();
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // 1000 XLM at $0.85 is 170%: safe at 150%, not at a 180% floor
+    set_price(&setup, &symbol_short!("XLM"), 8_500_000);
+    assert!(!setup.client.check_liquidation(&loan_id));
+
+    setup.client.set_asset_risk_params(&admin, &xlm, &AssetRiskParams { max_ltv_bps: 5000, min_threshold: 18000 });
+    assert_eq!(setup.client.get_effective_threshold(&loan_id), 18000);
+    assert!(setup.client.check_liquidation(&loan_id));
+    assert!(setup.client.try_update_loan_threshold(&admin, &loan_id, &15000).is_err());
+}
+
+#[test]
+fn test_asset_risk_params_validation() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+    let admin = setup.client.get_admin();
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    // A loan at 70% LTV is already under a 150% threshold
+    let inconsistent = AssetRiskParams { max_ltv_bps: 7000, min_threshold: 15000 };
+    assert!(setup.client.try_set_asset_risk_params(&admin, &xlm, &inconsistent).is_err());
+    let at_par = AssetRiskParams { max_ltv_bps: 5000, min_threshold: 10000 };
+    assert!(setup.client.try_set_asset_risk_params(&admin, &xlm, &at_par).is_err());
+
+    let params = AssetRiskParams { max_ltv_bps: 5000, min_threshold: 15000 };
+    assert!(setup.client.try_set_asset_risk_params(&other, &xlm, &params).is_err());
+}
+
+#[test]
+fn test_threshold_update_waits_for_grace_period() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let admin = setup.client.get_admin();
+
+    // 200% collateralized against a 150% threshold
+    let loan_id = create_xlm_loan(&setup, &owner);
+    setup.client.set_threshold_grace_period(&setup.client.get_admin(), &3600);
+    setup.client.update_loan_threshold(&admin, &loan_id, &25000);
+
+    assert_eq!(setup.client.get_effective_threshold(&loan_id), 15000);
+    assert!(!setup.client.check_liquidation(&loan_id));
+
+    env.ledger().with_mut(|li| li.timestamp += 3600);
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+
+    assert_eq!(setup.client.get_effective_threshold(&loan_id), 25000);
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_loan_lifecycle_events() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    let (contract, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(contract, setup.client.address);
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "created"), loan_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let borrowed: i128 = data.get(Symbol::new(env, "borrowed_amount")).unwrap().into_val(env);
+    assert_eq!(borrowed, 5_000_000_000);
+
+    setup.client.add_collateral(&owner, &loan_id, &1_000_000_000);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "collateral_added"), loan_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let collateral: i128 = data.get(Symbol::new(env, "collateral_amount")).unwrap().into_val(env);
+    assert_eq!(collateral, 11_000_000_000);
+
+    setup.client.repay_loan(&owner, &loan_id, &1_000_000_000);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "repaid"), loan_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let remaining: i128 = data.get(Symbol::new(env, "remaining")).unwrap().into_val(env);
+    assert_eq!(remaining, 4_000_000_000);
+
+    // XLM falls to $0.50: 550 / 400 = 137.5% < 150%
+    set_price(&setup, &symbol_short!("XLM"), 5_000_000);
+    setup.client.liquidate_position(&liquidator, &loan_id, &None, &None);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "liquidated"), loan_id, liquidator).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let field = |name: &str| -> i128 { data.get(Symbol::new(env, name)).unwrap().into_val(env) };
+    assert_eq!(field("collateral_ratio"), 13750);
+    assert_eq!(field("collateral_price"), 5_000_000);
+    assert_eq!(field("reward"), 11_000_000_000 * 500 / 10000);
+}
+
+#[test]
+fn test_liquidation_bonus_scales_with_shortfall() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    assert_eq!(
+        setup.client.get_liquidation_params(),
+        LiquidationParams { bonus_bps: 500, max_bonus_bps: 500 }
+    );
+    let params = LiquidationParams { bonus_bps: 500, max_bonus_bps: 1000 };
+    setup.client.set_liquidation_params(&setup.client.get_admin(), &params);
+    assert_eq!(setup.client.get_liquidation_params(), params);
+    assert!(setup.client.try_set_liquidation_params(&setup.client.get_admin(), &LiquidationParams { bonus_bps: 500, max_bonus_bps: 2500 }).is_err());
+    assert!(setup.client.try_set_liquidation_params(&owner, &LiquidationParams { bonus_bps: 500, max_bonus_bps: 500 }).is_err());
+    assert_eq!(setup.client.get_liquidation_params(), params);
+
+    let shallow = create_xlm_loan(&setup, &owner);
+    let deep = create_xlm_loan(&setup, &owner);
+
+    // 140% against 150%: 5% scaled by 150/140
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    let reward = setup.client.liquidate_position(&liquidator, &shallow, &None, &None);
+    assert_eq!(reward, 10_000_000_000 * (500 * 15000 / 14000) / 10000);
+
+    // 60% against 150%: capped at 10%
+    set_price(&setup, &symbol_short!("XLM"), 3_000_000);
+    let reward = setup.client.liquidate_position(&liquidator, &deep, &None, &None);
+    assert_eq!(reward, 10_000_000_000 * 1000 / 10000);
+}
+
+#[test]
+fn test_liquidation_reward_in_debt_asset() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    StellarAssetClient::new(env, &usdc_address).mint(&client.address, &10_000_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(usdc_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+
+    // 1000 XLM against 500 USDC
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &10_000_000_000,
+        &AssetType::Stellar(usdc_address.clone()),
+        &5_000_000_000,
+        &15000,
+    );
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    // 5% of 1000 XLM at $0.70, paid in USDC
+    let reward = client.liquidate_position(&liquidator, &loan_id, &None, &Some(RewardAsset::Debt));
+    assert_eq!(reward, 500_000_000 * 7 / 10);
+    assert_eq!(usdc.balance(&liquidator), reward);
+    assert_eq!(client.get_pending_rewards(&liquidator, &AssetType::Crypto(symbol_short!("XLM"))), 0);
+}
+
+#[test]
+fn test_auto_topup_pulls_collateral_from_source() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let source = Address::generate(env);
+
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    StellarAssetClient::new(env, &token_address).mint(&owner, &10_000_000_000);
+    StellarAssetClient::new(env, &token_address).mint(&source, &5_000_000_000);
+    token.approve(&source, &client.address, &5_000_000_000, &1000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &10_000_000, &env.ledger().timestamp());
+
+    // 1000 tokens against 500 USDC: 200%
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Stellar(token_address.clone()),
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    );
+    assert!(client.try_execute_auto_topup(&loan_id).is_err());
+
+    // The trigger has to fire before liquidation would
+    assert!(client.try_set_auto_topup(&owner, &loan_id, &15000, &2_000_000_000, &source).is_err());
+    assert!(client.try_set_auto_topup(&owner, &loan_id, &17000, &0, &source).is_err());
+    assert!(client.try_set_auto_topup(&source, &loan_id, &17000, &2_000_000_000, &source).is_err());
+
+    client.set_auto_topup(&owner, &loan_id, &17000, &2_000_000_000, &source);
+    assert_eq!(client.get_auto_topup(&loan_id).unwrap().source, source);
+
+    // 180% is above the trigger
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &9_000_000, &env.ledger().timestamp());
+    assert!(client.try_execute_auto_topup(&loan_id).is_err());
+
+    // 160%: another 200 tokens bring it back to 192%
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &8_000_000, &env.ledger().timestamp());
+    client.execute_auto_topup(&loan_id);
+    assert_eq!(token.balance(&source), 3_000_000_000);
+    assert_eq!(token.balance(&client.address), 12_000_000_000);
+    assert_eq!(client.get_loan_details(&loan_id).collateral_amount, 12_000_000_000);
+    assert!(client.try_execute_auto_topup(&loan_id).is_err());
+
+    client.cancel_auto_topup(&owner, &loan_id);
+    assert_eq!(client.get_auto_topup(&loan_id), None);
+}
+
+#[test]
+fn test_auto_topup_requires_token_collateral() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    assert!(client.try_set_auto_topup(&owner, &loan_id, &17000, &1_000_000_000, &owner).is_err());
+
+    // A policy stored before the check still can't add untransferred collateral
+    env.as_contract(&client.address, || {
+        let policy = AutoTopUp { trigger_ratio: 17000, topup_amount: 1_000_000_000, source: owner.clone() };
+        env.storage().persistent().set(&DataKey::AutoTopUp(loan_id), &policy);
+    });
+    set_price(&setup, &symbol_short!("XLM"), 8_000_000);
+    assert!(client.try_execute_auto_topup(&loan_id).is_err());
+    assert_eq!(client.get_loan_details(&loan_id).collateral_amount, 10_000_000_000);
+}
+
+#[test]
+fn test_check_and_liquidate_batch_skips_ineligible() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+    let admin = client.get_admin();
+
+    let underwater = create_xlm_loan(&setup, &owner);
+    let healthy = create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+    let paused = create_loan_with_collateral(&setup, &owner, symbol_short!("ETH"), 10_000_000_000);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    set_price(&setup, &symbol_short!("ETH"), 7_000_000);
+    client.set_asset_paused(&admin, &AssetType::Crypto(symbol_short!("ETH")), &true);
+
+    let liquidated = client.check_and_liquidate_batch(
+        &liquidator,
+        &vec![env, healthy, 99, underwater, paused, underwater],
+    );
+
+    assert_eq!(liquidated, vec![env, underwater]);
+    assert_eq!(
+        client.get_pending_rewards(&liquidator, &AssetType::Crypto(symbol_short!("XLM"))),
+        10_000_000_000 * 500 / 10000
+    );
+    assert!(!client.check_liquidation(&underwater));
+    assert!(client.check_liquidation(&paused));
+}
+
+#[test]
+fn test_check_and_liquidate_batch_capped() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+    env.cost_estimate().budget().reset_unlimited();
+
+    let mut loan_ids = Vec::new(env);
+    for _ in 0..MAX_LIQUIDATION_BATCH + 1 {
+        loan_ids.push_back(create_xlm_loan(&setup, &owner));
+    }
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    let liquidated = setup.client.check_and_liquidate_batch(&liquidator, &loan_ids);
+    assert_eq!(liquidated, loan_ids.slice(0..MAX_LIQUIDATION_BATCH));
+    assert!(setup.client.check_liquidation(&loan_ids.last().unwrap()));
+}
+
+#[test]
+fn test_sweep_liquidated_collateral() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+    let treasury = Address::generate(env);
+    let admin = client.get_admin();
+
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    StellarAssetClient::new(env, &token_address).mint(&owner, &10_000_000_000);
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Stellar(token_address.clone()),
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    );
+
+    // Active loans can't be swept
+    assert!(client.try_sweep_liquidated_collateral(&admin, &loan_id, &treasury).is_err());
+
+    setup.oracle.set_price(&MockAsset::Stellar(token_address.clone()), &7_000_000, &env.ledger().timestamp());
+    let reward = client.liquidate_position(&liquidator, &loan_id, &None, &None);
+    assert_eq!(token.balance(&client.address), 10_000_000_000);
+
+    // The liquidator's reward stays behind for them to claim
+    assert!(client.try_sweep_liquidated_collateral(&liquidator, &loan_id, &liquidator).is_err());
+    assert_eq!(client.sweep_liquidated_collateral(&admin, &loan_id, &treasury), 10_000_000_000 - reward);
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "loan"), Symbol::new(env, "collateral_swept"), loan_id).into_val(env)
+    );
+    assert_eq!(token.balance(&treasury), 10_000_000_000 - reward);
+    assert_eq!(token.balance(&client.address), reward);
+    client.claim_liquidation_rewards(&liquidator, &AssetType::Stellar(token_address.clone()));
+    assert_eq!(token.balance(&client.address), 0);
+    assert_eq!(client.get_loan_details(&loan_id).collateral_amount, 0);
+
+    assert!(client.try_sweep_liquidated_collateral(&admin, &loan_id, &treasury).is_err());
+}
+
+#[test]
+fn test_reentrant_liquidation_during_payout_fails() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let liquidator = Address::generate(env);
+
+    let token_id = env.register(ReentrantToken, ());
+    let token = ReentrantTokenClient::new(env, &token_id);
+    setup.oracle.set_price(&MockAsset::Stellar(token_id.clone()), &10_000_000, &env.ledger().timestamp());
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+
+    let loan_id = client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &10_000_000_000,
+        &AssetType::Stellar(token_id.clone()),
+        &5_000_000_000,
+        &15000,
+    );
+    token.set_target(&client.address, &loan_id);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    client.liquidate_position(&liquidator, &loan_id, &None, &Some(RewardAsset::Debt));
+
+    assert_eq!(token.reentered(), Some(false));
+    assert!(!client.check_liquidation(&loan_id));
+    assert_eq!(client.get_total_borrowed(&AssetType::Crypto(symbol_short!("XLM"))), 0);
+}
+
+#[test]
+fn test_liquidation_reward_in_unpayable_debt_asset_rejected() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    // USDC here is an off-chain symbol, so there is nothing to pay out
+    let loan_id = create_xlm_loan(&setup, &owner);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    assert!(setup.client.try_liquidate_position(&liquidator, &loan_id, &None, &Some(RewardAsset::Debt)).is_err());
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_liquidation_min_reward_guard() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    let reward = 10_000_000_000 * 500 / 10000;
+    assert!(setup.client.try_liquidate_position(&liquidator, &loan_id, &Some(reward + 1), &None).is_err());
+    assert_eq!(setup.client.liquidate_position(&liquidator, &loan_id, &Some(reward), &None), reward);
+}
+
+#[test]
+fn test_get_profitable_liquidations() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+
+    let small = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 8_000_000_000);
+    let large = create_loan_with_collateral(&setup, &owner, symbol_short!("XLM"), 10_000_000_000);
+    create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 20_000_000_000);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    // Rewards are 5% of collateral: 40 and 50 XLM
+    let floor = 450_000_000;
+    assert_eq!(
+        setup.client.get_profitable_liquidations(&floor, &1, &10),
+        vec![env, (large, 500_000_000i128)]
+    );
+    assert_eq!(
+        setup.client.get_profitable_liquidations(&0, &1, &10),
+        vec![env, (small, 400_000_000i128), (large, 500_000_000i128)]
+    );
+    assert_eq!(setup.client.get_profitable_liquidations(&0, &1, &1), vec![env, (small, 400_000_000i128)]);
+}
+
+#[test]
+fn test_whale_position_ratio_does_not_overflow() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+
+    // $100 with 14 decimals; price * amount * 10000 is far beyond i128
+    set_price(&setup, &symbol_short!("BTC"), 10_000_000_000_000_000);
+    set_price(&setup, &symbol_short!("ETH"), 10_000_000_000_000_000);
+    let loan_id = setup.client.create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("BTC")),
+        &5_000_000_000_000_000_000,
+        &AssetType::Crypto(symbol_short!("ETH")),
+        &2_500_000_000_000_000_000,
+        &15000,
+    );
+
+    assert!(!setup.client.check_liquidation(&loan_id));
+    assert_eq!(setup.client.get_health_factor(&loan_id), 20000 * 10000 / 15000);
+
+    set_price(&setup, &symbol_short!("BTC"), 7_000_000_000_000_000);
+    assert!(setup.client.check_liquidation(&loan_id));
+}
+
+#[test]
+fn test_pause_blocks_liquidation_but_not_repayment() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+    let loan_id = create_xlm_loan(&setup, &owner);
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+
+    let admin = client.get_admin();
+    assert!(client.try_pause(&liquidator).is_err());
+    client.pause(&admin);
+    assert!(client.try_liquidate_position(&liquidator, &loan_id, &None, &None).is_err());
+    assert!(client.try_create_loan(
+        &owner,
+        &AssetType::Crypto(symbol_short!("XLM")),
+        &10_000_000_000,
+        &AssetType::Crypto(symbol_short!("USDC")),
+        &5_000_000_000,
+        &15000,
+    ).is_err());
+
+    client.repay_loan(&owner, &loan_id, &100_000_000);
+
+    client.unpause(&admin);
+    client.liquidate_position(&liquidator, &loan_id, &None, &None);
+}
+
+#[test]
+fn test_spot_health_factor() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // 200% collateralized against a 150% threshold
+    assert_eq!(setup.client.get_health_factor(&loan_id), 13333);
+
+    // At the threshold the health factor is exactly 1.0
+    set_price(&setup, &symbol_short!("XLM"), 7_500_000);
+    assert_eq!(setup.client.get_health_factor(&loan_id), 10000);
+    assert!(setup.client.check_liquidation(&loan_id));
+
+    set_price(&setup, &symbol_short!("XLM"), 6_000_000);
+    assert_eq!(setup.client.get_health_factor(&loan_id), 8000);
+}
+
+#[test]
+fn test_health_factor_unbounded_without_debt_value() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let loan_id = create_xlm_loan(&setup, &owner);
+
+    // A zero borrowed price makes the collateral ratio unbounded
+    set_price(&setup, &symbol_short!("USDC"), 0);
+    setup.oracle.set_twap(&MockAsset::Other(symbol_short!("XLM")), &10_000_000);
+    setup.oracle.set_twap(&MockAsset::Other(symbol_short!("USDC")), &0);
+    assert_eq!(setup.client.get_health_factor(&loan_id), i128::MAX);
+    assert_eq!(setup.client.get_health_factor_twap(&loan_id, &5), i128::MAX);
+}
+
+#[test]
+fn test_version() {
+    let setup = setup();
+    assert_eq!(setup.client.version(), CONTRACT_VERSION);
+    assert_eq!(setup.client.version(), 2);
+    assert_eq!(
+        setup.client.build_info(),
+        String::from_str(&setup.env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    );
+}
+
+#[test]
+fn test_upgrade() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let other = Address::generate(env);
+    let new_wasm_hash = env.deployer().upload_contract_wasm(UPGRADE_WASM);
+
+    assert!(client.try_upgrade(&other, &new_wasm_hash).is_err());
+
+    client.upgrade(&admin, &new_wasm_hash);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "contract"), Symbol::new(env, "upgraded")).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let from_version: u32 = data.get(Symbol::new(env, "from_version")).unwrap().into_val(env);
+    let wasm_hash: BytesN<32> = data.get(Symbol::new(env, "new_wasm_hash")).unwrap().into_val(env);
+    assert_eq!((from_version, wasm_hash), (CONTRACT_VERSION, new_wasm_hash));
+
+    // The same contract id now runs the new code
+    let sum: u64 = env.invoke_contract(&client.address, &Symbol::new(env, "add"), vec![env, 1u64.into_val(env), 2u64.into_val(env)]);
+    assert_eq!(sum, 3);
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let setup = setup();
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let new_admin = Address::generate(&setup.env);
+
+    assert!(client.try_propose_admin(&new_admin, &new_admin).is_err());
+    client.propose_admin(&admin, &new_admin);
+    assert_eq!(client.get_admin(), admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+
+    assert!(client.try_accept_admin(&admin).is_err());
+    client.accept_admin(&new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+}
+
+#[test]
+fn test_asset_pause_only_blocks_that_asset() {
+    let setup = setup();
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    let xlm_loan = create_xlm_loan(&setup, &owner);
+    let btc_loan = create_loan_with_collateral(&setup, &owner, symbol_short!("BTC"), 10_000_000_000);
+
+    client.set_asset_paused(&admin, &xlm, &true);
+    assert!(client.is_asset_paused(&xlm));
+    assert!(!client.is_asset_paused(&AssetType::Crypto(symbol_short!("BTC"))));
+
+    // Both loans fall under 150%, only the BTC one may be liquidated
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    set_price(&setup, &symbol_short!("BTC"), 7_000_000);
+    assert!(client.try_liquidate_position(&liquidator, &xlm_loan, &None, &None).is_err());
+    assert!(client.liquidate_position(&liquidator, &btc_loan, &None, &None) > 0);
+    assert!(client
+        .try_create_loan(&owner, &xlm, &10_000_000_000, &AssetType::Crypto(symbol_short!("USDC")), &1_000_000_000, &15000)
+        .is_err());
+
+    client.set_asset_paused(&admin, &xlm, &false);
+    assert!(client.liquidate_position(&liquidator, &xlm_loan, &None, &None) > 0);
+}
+
+#[test]
+fn test_asset_pause_requires_admin() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+    let xlm = AssetType::Crypto(symbol_short!("XLM"));
+
+    assert!(setup.client.try_set_asset_paused(&other, &xlm, &true).is_err());
+    assert!(!setup.client.is_asset_paused(&xlm));
+}
+
+#[test]
+fn test_simulate_liquidation_with_slippage() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    let loan_id = create_xlm_loan(&setup, &owner);
+    assert_eq!(setup.client.simulate_liquidation(&loan_id, &None), (false, 0));
+
+    // 700 / 500 = 140% < 150%
+    set_price(&setup, &symbol_short!("XLM"), 7_000_000);
+    let (liquidatable, gross) = setup.client.simulate_liquidation(&loan_id, &None);
+    assert!(liquidatable);
+    assert_eq!(gross, 10_000_000_000 * 500 / 10000);
+
+    // Selling the seized collateral 3% under the oracle price
+    let (_, net) = setup.client.simulate_liquidation(&loan_id, &Some(300));
+    assert_eq!(net, gross * 9700 / 10000);
+    assert!(net < gross);
+    assert!(setup.client.try_simulate_liquidation(&loan_id, &Some(10001)).is_err());
+
+    assert_eq!(setup.client.liquidate_position(&liquidator, &loan_id, &None, &None), gross);
+    assert_eq!(setup.client.simulate_liquidation(&loan_id, &None), (false, 0));
+}
+
+#[test]
+fn test_matured_loan_liquidatable() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let liquidator = Address::generate(&setup.env);
+
+    let open_ended = create_xlm_loan(&setup, &owner);
+    client.set_max_loan_duration(&client.get_admin(), &Some(2_592_000)); // 30 days
+    let term_loan = create_xlm_loan(&setup, &owner);
+    let created_at = setup.env.ledger().timestamp();
+    assert_eq!(client.get_loan_maturity(&open_ended), None);
+    assert_eq!(client.get_loan_maturity(&term_loan), Some(created_at + 2_592_000));
+    assert!(!client.check_liquidation(&term_loan));
+
+    // Healthy at 200% but past maturity
+    setup.env.ledger().with_mut(|li| li.timestamp = created_at + 2_592_000);
+    set_price(&setup, &symbol_short!("XLM"), 10_000_000);
+    set_price(&setup, &symbol_short!("USDC"), 10_000_000);
+    assert!(client.check_liquidation(&term_loan));
+    assert!(!client.check_liquidation(&open_ended));
+    assert!(client.liquidate_position(&liquidator, &term_loan, &None, &None) > 0);
+}
@@ -0,0 +1,124 @@
+#![no_std]
+
+// Mock Reflector oracle for tests. Implements the subset of the Reflector
+// interface used by the StellarGuard contracts, with setters so tests can
+// control prices, TWAPs and timestamps.
+use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Symbol, Vec};
+
+// Mirrors the Reflector Asset type so values encode identically
+#[contracttype]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Asset {
+    Stellar(Address),
+    Other(Symbol),
+}
+
+// Mirrors the Reflector PriceData type
+#[contracttype]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct PriceData {
+    pub price: i128,
+    pub timestamp: u64,
+}
+
+#[contracttype]
+pub enum DataKey {
+    History(Asset),
+    Twap(Asset),
+    CrossPrice(Asset, Asset),
+    CrossTwap(Asset, Asset),
+    Decimals,
+    Resolution,
+    Assets,
+}
+
+#[contract]
+pub struct MockReflector;
+
+#[contractimpl]
+impl MockReflector {
+    // Record a new price for an asset; it becomes the last price and is
+    // appended to the history returned by `prices`
+    pub fn set_price(env: Env, asset: Asset, price: i128, timestamp: u64) {
+        let mut history = Self::history(&env, &asset);
+        if history.is_empty() {
+            let mut assets = Self::assets(env.clone());
+            assets.push_back(asset.clone());
+            env.storage().instance().set(&DataKey::Assets, &assets);
+        }
+        history.push_front(PriceData { price, timestamp });
+        env.storage().instance().set(&DataKey::History(asset), &history);
+    }
+
+    pub fn set_twap(env: Env, asset: Asset, twap: i128) {
+        env.storage().instance().set(&DataKey::Twap(asset), &twap);
+    }
+
+    pub fn set_x_price(env: Env, base_asset: Asset, quote_asset: Asset, price: i128, timestamp: u64) {
+        env.storage().instance().set(
+            &DataKey::CrossPrice(base_asset, quote_asset),
+            &PriceData { price, timestamp },
+        );
+    }
+
+    pub fn set_x_twap(env: Env, base_asset: Asset, quote_asset: Asset, twap: i128) {
+        env.storage().instance().set(&DataKey::CrossTwap(base_asset, quote_asset), &twap);
+    }
+
+    // Decimals reported by `decimals`; 7 unless set
+    pub fn set_decimals(env: Env, decimals: u32) {
+        env.storage().instance().set(&DataKey::Decimals, &decimals);
+    }
+
+    // Seconds between updates reported by `resolution`; 300 unless set
+    pub fn set_resolution(env: Env, resolution: u32) {
+        env.storage().instance().set(&DataKey::Resolution, &resolution);
+    }
+
+    // Reflector interface
+
+    pub fn decimals(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::Decimals).unwrap_or(7)
+    }
+
+    // Every asset given a price, in the order first set
+    pub fn assets(env: Env) -> Vec<Asset> {
+        env.storage().instance().get(&DataKey::Assets).unwrap_or_else(|| Vec::new(&env))
+    }
+
+    pub fn resolution(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::Resolution).unwrap_or(300)
+    }
+
+    pub fn lastprice(env: Env, asset: Asset) -> Option<PriceData> {
+        Self::history(&env, &asset).first()
+    }
+
+    // Newest record first, as Reflector returns them
+    pub fn prices(env: Env, asset: Asset, records: u32) -> Option<Vec<PriceData>> {
+        let history = Self::history(&env, &asset);
+        if history.is_empty() {
+            return None;
+        }
+        Some(history.slice(0..records.min(history.len())))
+    }
+
+    pub fn twap(env: Env, asset: Asset, _records: u32) -> Option<i128> {
+        env.storage().instance().get(&DataKey::Twap(asset))
+    }
+
+    pub fn x_last_price(env: Env, base_asset: Asset, quote_asset: Asset) -> Option<PriceData> {
+        env.storage().instance().get(&DataKey::CrossPrice(base_asset, quote_asset))
+    }
+
+    pub fn x_twap(env: Env, base_asset: Asset, quote_asset: Asset, _records: u32) -> Option<i128> {
+        env.storage().instance().get(&DataKey::CrossTwap(base_asset, quote_asset))
+    }
+
+    fn history(env: &Env, asset: &Asset) -> Vec<PriceData> {
+        env.storage()
+            .instance()
+            .get(&DataKey::History(asset.clone()))
+            .unwrap_or_else(|| Vec::new(env))
+    }
+}
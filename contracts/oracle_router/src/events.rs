@@ -0,0 +1,23 @@
+// Router configuration events, published for downstream contracts and monitoring
+use soroban_sdk::{contractevent, Address, Symbol};
+
+use crate::OracleKind;
+
+// An oracle feed was re-pointed with set_oracle
+#[contractevent(topics = ["oracle", "updated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OracleUpdated {
+    #[topic]
+    pub kind: OracleKind,
+    pub old_address: Option<Address>,
+    pub new_address: Address,
+}
+
+// A stablecoin was found off its peg by more than the caller's threshold
+#[contractevent(topics = ["peg", "breach"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PegBreach {
+    #[topic]
+    pub stablecoin: Symbol,
+    pub deviation_bps: i128,
+}
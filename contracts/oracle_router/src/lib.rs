@@ -1,11 +1,16 @@
 #![no_std]
 
+mod events;
 mod reflector;
+mod test;
+use events::{OracleUpdated, PegBreach};
 use reflector::{ReflectorClient, Asset, PriceData};
+use stellar_guard_common::decimal::normalize_price;
+use stellar_guard_common::{ownable, pausable, upgradeable};
 
 use soroban_sdk::{
     contract, contractimpl, contracttype, 
-    Address, Env, Symbol, String, log
+    Address, BytesN, Env, Map, Symbol, String, Vec, log
 };
 // Oracle addresses
 const TESTNET_EXTERNAL_ORACLE: &str = "CCYOZJCOPG34LLQQ7N24YXBM7LL62R7ONMZ3G6WZAAYPB5OYKOMJRN63";
@@ -17,6 +22,10 @@ const MAINNET_STELLAR_ORACLE: &str = "CBMS4EXBYPTVGBH6CB5QM4I5OY4P2QQ6L7HGFPFBRL
 const MAINNET_FOREX_ORACLE: &str = "CAHBESFLDZEUK5FMJOUSFRKPJJKXWKTLYF4HRLC7VGJJRMGD2X6V3EK5";
 
 const MAX_PERSISTENT_TTL: u32 = 535680;
+const NORMALIZED_DECIMALS: u32 = 14; // Common scale prices from different feeds are compared at
+const GUARD_TWAP_PERIODS: u32 = 5; // TWAP window get_price_guarded checks spot against
+// Bump on every release so deployments can be matched to source
+pub const CONTRACT_VERSION: u32 = 2;
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -34,12 +43,44 @@ pub enum Network {
     Testnet,
 }
 
+// The three Reflector feeds the router can read from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OracleKind {
+    External,
+    Stellar,
+    Forex,
+}
+
+// A price with the feed metadata needed to interpret it
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PriceInfo {
+    pub price: i128,
+    pub timestamp: u64,
+    pub decimals: u32,    // the feed's fixed-point precision
+    pub resolution: u32,  // seconds between the feed's updates
+    pub age_seconds: u64, // ledger time elapsed since `timestamp`
+}
+
+// Each oracle's price for a symbol at NORMALIZED_DECIMALS, None where it
+// isn't listed
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ArbitrageReport {
+    pub external_price: Option<i128>,
+    pub stellar_price: Option<i128>,
+    pub forex_price: Option<i128>,
+    pub max_spread_bps: i128,
+}
+
 #[contracttype]
 pub enum DataKey {
     Network,
     ExternalOracle,
     StellarOracle,
     ForexOracle,
+    AssetRoute(Symbol), // OracleKind overriding the default for a symbol
 }
 
 #[contract]
@@ -47,7 +88,13 @@ pub struct OracleRouter;
 
 #[contractimpl]
 impl OracleRouter {
-    pub fn initialize(env: Env, network: Network) {
+    pub fn initialize(env: Env, admin: Address, network: Network) {
+        if ownable::has_admin(&env) {
+            panic!("Already initialized");
+        }
+        
+        ownable::set_admin(&env, &admin);
+        
         // Set network
         env.storage().instance().set(&DataKey::Network, &network);
         
@@ -87,29 +134,187 @@ impl OracleRouter {
         env.storage().instance().extend_ttl(100, MAX_PERSISTENT_TTL);
     }
     
-    // Get the appropriate oracle for an asset type
-    pub fn get_oracle_for_asset(env: Env, asset: AssetType) -> Address {
-        match asset {
-            AssetType::Crypto(_) => {
-                env.storage().instance()
-                    .get(&DataKey::ExternalOracle)
-                    .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)))
+    // Stop serving prices, e.g. while an upstream oracle is misbehaving
+    pub fn pause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, true);
+    }
+    
+    pub fn unpause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, false);
+    }
+    
+    pub fn is_paused(env: Env) -> bool {
+        pausable::is_paused(&env)
+    }
+    
+    pub fn version(_env: Env) -> u32 {
+        CONTRACT_VERSION
+    }
+    
+    // Swap in new contract code uploaded under `new_wasm_hash`, keeping the
+    // contract id and all state. Bump CONTRACT_VERSION with every release.
+    pub fn upgrade(env: Env, admin: Address, new_wasm_hash: BytesN<32>) {
+        upgradeable::upgrade(&env, &admin, CONTRACT_VERSION, new_wasm_hash);
+    }
+    
+    // Crate name and version the deployed code was built from
+    pub fn build_info(env: Env) -> String {
+        String::from_str(&env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    }
+    
+    pub fn get_admin(env: Env) -> Address {
+        ownable::get_admin(&env)
+    }
+    
+    pub fn is_admin(env: Env, who: Address) -> bool {
+        ownable::is_admin(&env, &who)
+    }
+    
+    // Nominate a new admin; takes effect once they call accept_admin
+    pub fn propose_admin(env: Env, current_admin: Address, new_admin: Address) {
+        ownable::propose_admin(&env, &current_admin, &new_admin);
+    }
+    
+    pub fn accept_admin(env: Env, new_admin: Address) {
+        ownable::accept_admin(&env, &new_admin);
+        log!(&env, "Admin transferred to {}", new_admin);
+    }
+    
+    // Address nominated by propose_admin and not yet accepted
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        ownable::get_pending_admin(&env)
+    }
+    
+    // Route `symbol` to a specific oracle regardless of its asset type, e.g.
+    // a stablecoin Reflector lists on the external feed
+    pub fn set_asset_route(env: Env, admin: Address, symbol: Symbol, oracle_kind: OracleKind) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let key = DataKey::AssetRoute(symbol);
+        env.storage().persistent().set(&key, &oracle_kind);
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    // Fall back to the default oracle for `symbol`'s asset type
+    pub fn remove_asset_route(env: Env, admin: Address, symbol: Symbol) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        env.storage().persistent().remove(&DataKey::AssetRoute(symbol));
+    }
+    
+    pub fn get_asset_route(env: Env, symbol: Symbol) -> Option<OracleKind> {
+        env.storage().persistent().get(&DataKey::AssetRoute(symbol))
+    }
+    
+    // Re-point one of the feeds, e.g. after Reflector migrates a contract
+    pub fn set_oracle(env: Env, admin: Address, oracle_kind: OracleKind, address: Address) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let key = Self::oracle_key(&oracle_kind);
+        let old_address: Option<Address> = env.storage().instance().get(&key);
+        env.storage().instance().set(&key, &address);
+        env.storage().instance().extend_ttl(100, MAX_PERSISTENT_TTL);
+        
+        OracleUpdated {
+            kind: oracle_kind,
+            old_address,
+            new_address: address,
+        }.publish(&env);
+    }
+    
+    fn oracle_key(oracle_kind: &OracleKind) -> DataKey {
+        match oracle_kind {
+            OracleKind::External => DataKey::ExternalOracle,
+            OracleKind::Stellar => DataKey::StellarOracle,
+            OracleKind::Forex => DataKey::ForexOracle,
+        }
+    }
+    
+    fn oracle_kind_for_asset(env: &Env, asset: &AssetType) -> OracleKind {
+        let route = match asset {
+            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
+                Self::get_asset_route(env.clone(), symbol.clone())
             },
-            AssetType::StellarNative(_) => {
-                env.storage().instance()
-                    .get(&DataKey::StellarOracle)
-                    .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_STELLAR_ORACLE)))
+            AssetType::StellarNative(_) => None,
+        };
+        route.unwrap_or(match asset {
+            AssetType::Crypto(_) => OracleKind::External,
+            AssetType::StellarNative(_) => OracleKind::Stellar,
+            AssetType::Stablecoin(_) | AssetType::Forex(_) => OracleKind::Forex,
+        })
+    }
+    
+    fn oracle_address(env: &Env, oracle_kind: &OracleKind) -> Address {
+        let default_address = match oracle_kind {
+            OracleKind::External => TESTNET_EXTERNAL_ORACLE,
+            OracleKind::Stellar => TESTNET_STELLAR_ORACLE,
+            OracleKind::Forex => TESTNET_FOREX_ORACLE,
+        };
+        env.storage().instance()
+            .get(&Self::oracle_key(oracle_kind))
+            .unwrap_or(Address::from_string(&String::from_str(env, default_address)))
+    }
+    
+    fn cross_oracle_address(
+        env: &Env,
+        base_asset: &AssetType,
+        quote_asset: &AssetType,
+        oracle_kind: Option<OracleKind>
+    ) -> Address {
+        let oracle_kind = oracle_kind.unwrap_or(match (base_asset, quote_asset) {
+            (AssetType::StellarNative(_), AssetType::StellarNative(_)) => OracleKind::Stellar,
+            _ => OracleKind::External,
+        });
+        env.storage().instance()
+            .get(&Self::oracle_key(&oracle_kind))
+            .unwrap_or_else(|| panic!("Oracle not configured"))
+    }
+    
+    // Rescale `price` from the oracle's own decimals to NORMALIZED_DECIMALS,
+    // so feeds reporting different precision can be compared
+    fn normalize_price(client: &ReflectorClient, price: i128) -> i128 {
+        normalize_price(price, client.decimals(), NORMALIZED_DECIMALS)
+    }
+    
+    fn to_reflector_asset(asset_type: AssetType) -> Asset {
+        match asset_type {
+            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
+                Asset::Other(symbol)
             },
-            AssetType::Stablecoin(_) | AssetType::Forex(_) => {
-                env.storage().instance()
-                    .get(&DataKey::ForexOracle)
-                    .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_FOREX_ORACLE)))
+            AssetType::StellarNative(address) => {
+                Asset::Stellar(address)
             }
         }
     }
     
+    // Get the appropriate oracle for an asset type. Symbol routes set with
+    // set_asset_route take precedence over the per-type defaults.
+    pub fn get_oracle_for_asset(env: Env, asset: AssetType) -> Address {
+        let oracle_kind = Self::oracle_kind_for_asset(&env, &asset);
+        Self::oracle_address(&env, &oracle_kind)
+    }
+    
+    // Assets the given oracle publishes prices for
+    pub fn list_assets(env: Env, oracle_kind: OracleKind) -> Vec<Asset> {
+        let oracle_address = Self::oracle_address(&env, &oracle_kind);
+        ReflectorClient::new(&env, &oracle_address).assets()
+    }
+    
+    // Whether the oracle get_price would route `asset_type` to lists it
+    pub fn is_supported(env: Env, asset_type: AssetType) -> bool {
+        let oracle_kind = Self::oracle_kind_for_asset(&env, &asset_type);
+        Self::list_assets(env, oracle_kind).contains(Self::to_reflector_asset(asset_type))
+    }
+    
     // Get price with automatic oracle selection
     pub fn get_price(env: Env, asset_type: AssetType) -> Option<PriceData> {
+        pausable::when_not_paused(&env);
         let oracle_address = Self::get_oracle_for_asset(env.clone(), asset_type.clone());
         let client = ReflectorClient::new(&env, &oracle_address);
         
@@ -125,8 +330,167 @@ impl OracleRouter {
         client.lastprice(&asset)
     }
     
+    // get_price along with the serving oracle's decimals and resolution and
+    // the price's age, so prices from different feeds can be normalized
+    pub fn get_price_detailed(env: Env, asset_type: AssetType) -> Option<PriceInfo> {
+        let price_data = Self::get_price(env.clone(), asset_type.clone())?;
+        let oracle_address = Self::get_oracle_for_asset(env.clone(), asset_type);
+        let client = ReflectorClient::new(&env, &oracle_address);
+        
+        Some(PriceInfo {
+            price: price_data.price,
+            timestamp: price_data.timestamp,
+            decimals: client.decimals(),
+            resolution: client.resolution(),
+            age_seconds: env.ledger().timestamp().saturating_sub(price_data.timestamp),
+        })
+    }
+    
+    // get_price, or None when the spot price is more than max_deviation_bps
+    // away from the asset's short TWAP, e.g. a flash-manipulated tick. Also
+    // None if the oracle has no TWAP to compare against.
+    pub fn get_price_guarded(env: Env, asset_type: AssetType, max_deviation_bps: u32) -> Option<PriceData> {
+        pausable::when_not_paused(&env);
+        let oracle_address = Self::get_oracle_for_asset(env.clone(), asset_type.clone());
+        let client = ReflectorClient::new(&env, &oracle_address);
+        let asset = Self::to_reflector_asset(asset_type);
+        
+        let price_data = client.lastprice(&asset)?;
+        let twap = client.twap(&asset, &GUARD_TWAP_PERIODS)?;
+        if twap <= 0 {
+            return None;
+        }
+        
+        let deviation_bps = (price_data.price - twap).abs() * 10000 / twap;
+        if deviation_bps > max_deviation_bps as i128 {
+            log!(&env, "Price {} rejected: {}bps from TWAP {}", price_data.price, deviation_bps, twap);
+            return None;
+        }
+        
+        Some(price_data)
+    }
+    
+    // get_price for several assets at once, results in the order of `assets`.
+    // Assets are grouped by oracle so each feed's client is built once.
+    pub fn get_prices(env: Env, assets: Vec<AssetType>) -> Vec<Option<PriceData>> {
+        pausable::when_not_paused(&env);
+        
+        let mut prices: Vec<Option<PriceData>> = Vec::new(&env);
+        let mut oracle_kinds: Vec<OracleKind> = Vec::new(&env);
+        for asset_type in assets.iter() {
+            prices.push_back(None);
+            oracle_kinds.push_back(Self::oracle_kind_for_asset(&env, &asset_type));
+        }
+        
+        for oracle_kind in [OracleKind::External, OracleKind::Stellar, OracleKind::Forex] {
+            if !oracle_kinds.contains(&oracle_kind) {
+                continue;
+            }
+            
+            let oracle_address = Self::oracle_address(&env, &oracle_kind);
+            let client = ReflectorClient::new(&env, &oracle_address);
+            for (i, asset_type) in assets.iter().enumerate() {
+                if oracle_kinds.get_unchecked(i as u32) == oracle_kind {
+                    prices.set(i as u32, client.lastprice(&Self::to_reflector_asset(asset_type)));
+                }
+            }
+        }
+        
+        prices
+    }
+    
+    // Median of the external, stellar and forex oracle prices for the same
+    // symbol, at NORMALIZED_DECIMALS. Oracles without a price are ignored;
+    // None unless at least two respond, so a single stuck or compromised
+    // feed cannot set the price.
+    pub fn get_median_price(env: Env, asset_symbol: Symbol) -> Option<PriceData> {
+        Self::get_median_price_with_sources(env, asset_symbol).map(|(price_data, _)| price_data)
+    }
+    
+    // get_median_price along with the number of oracles it was taken over
+    pub fn get_median_price_with_sources(env: Env, asset_symbol: Symbol) -> Option<(PriceData, u32)> {
+        pausable::when_not_paused(&env);
+        
+        let asset = Asset::Other(asset_symbol);
+        let mut prices: Vec<PriceData> = Vec::new(&env);
+        for key in [DataKey::ExternalOracle, DataKey::StellarOracle, DataKey::ForexOracle] {
+            let oracle_address: Address = match env.storage().instance().get(&key) {
+                Some(oracle_address) => oracle_address,
+                None => continue,
+            };
+            let client = ReflectorClient::new(&env, &oracle_address);
+            if let Some(mut price_data) = client.lastprice(&asset) {
+                price_data.price = Self::normalize_price(&client, price_data.price);
+                
+                // Insertion sort by price
+                let mut i = 0;
+                while i < prices.len() && prices.get_unchecked(i).price < price_data.price {
+                    i += 1;
+                }
+                prices.insert(i, price_data);
+            }
+        }
+        
+        let sources = prices.len();
+        if sources < 2 {
+            return None;
+        }
+        
+        // Middle price of three; mean of the two when only two respond. The
+        // older timestamp is reported for the pair.
+        let median = if sources % 2 == 1 {
+            prices.get_unchecked(sources / 2)
+        } else {
+            let low = prices.get_unchecked(sources / 2 - 1);
+            let high = prices.get_unchecked(sources / 2);
+            PriceData {
+                price: (low.price + high.price) / 2,
+                timestamp: low.timestamp.min(high.timestamp),
+            }
+        };
+        
+        log!(&env, "Median price {} from {} oracles", median.price, sources);
+        Some((median, sources))
+    }
+    
+    // Mean of the oracles' prices for `asset_symbol` weighted by `weights`,
+    // at NORMALIZED_DECIMALS. Oracles without a price are skipped and the remaining weights
+    // renormalized; the oldest contributing timestamp is reported. None if
+    // no weighted oracle has a price.
+    pub fn get_weighted_price(env: Env, asset_symbol: Symbol, weights: Map<OracleKind, u32>) -> Option<PriceData> {
+        pausable::when_not_paused(&env);
+        
+        let asset = Asset::Other(asset_symbol);
+        let mut weighted_sum: i128 = 0;
+        let mut total_weight: i128 = 0;
+        let mut timestamp = u64::MAX;
+        for (oracle_kind, weight) in weights.iter() {
+            if weight == 0 {
+                continue;
+            }
+            
+            let oracle_address = Self::oracle_address(&env, &oracle_kind);
+            let client = ReflectorClient::new(&env, &oracle_address);
+            if let Some(price_data) = client.lastprice(&asset) {
+                weighted_sum += Self::normalize_price(&client, price_data.price) * weight as i128;
+                total_weight += weight as i128;
+                timestamp = timestamp.min(price_data.timestamp);
+            }
+        }
+        
+        if total_weight == 0 {
+            return None;
+        }
+        
+        Some(PriceData {
+            price: weighted_sum / total_weight,
+            timestamp,
+        })
+    }
+    
     // Get TWAP price with automatic oracle selection
     pub fn get_twap(env: Env, asset_type: AssetType, periods: u32) -> Option<i128> {
+        pausable::when_not_paused(&env);
         let oracle_address = Self::get_oracle_for_asset(env.clone(), asset_type.clone());
         let client = ReflectorClient::new(&env, &oracle_address);
         
@@ -142,121 +506,92 @@ impl OracleRouter {
         client.twap(&asset, &periods)
     }
     
-    // Get cross price between two assets
+    // Get cross price between two assets, from `oracle_kind` or by default
+    // the stellar oracle for two Stellar assets and the external one otherwise.
+    // None if the chosen oracle doesn't quote the pair.
     pub fn get_cross_price(
         env: Env, 
         base_asset: AssetType, 
-        quote_asset: AssetType
+        quote_asset: AssetType,
+        oracle_kind: Option<OracleKind>
     ) -> Option<PriceData> {
-        // Determine which oracle to use (prefer external for cross-chain)
-        let oracle_address = match (&base_asset, &quote_asset) {
-            (AssetType::StellarNative(_), AssetType::StellarNative(_)) => {
-                env.storage().instance().get(&DataKey::StellarOracle).unwrap()
-            },
-            _ => {
-                env.storage().instance().get(&DataKey::ExternalOracle).unwrap()
-            }
-        };
+        pausable::when_not_paused(&env);
         
+        let oracle_address = Self::cross_oracle_address(&env, &base_asset, &quote_asset, oracle_kind);
         let client = ReflectorClient::new(&env, &oracle_address);
         
-        let base = match base_asset {
-            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
-                Asset::Other(symbol)
-            },
-            AssetType::StellarNative(address) => {
-                Asset::Stellar(address)
-            }
-        };
-        
-        let quote = match quote_asset {
-            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
-                Asset::Other(symbol)
-            },
-            AssetType::StellarNative(address) => {
-                Asset::Stellar(address)
-            }
-        };
-        
+        let base = Self::to_reflector_asset(base_asset);
+        let quote = Self::to_reflector_asset(quote_asset);
         client.x_last_price(&base, &quote)
     }
     
-    // Get cross TWAP between two assets
+    // Get cross TWAP between two assets, selecting the oracle as
+    // get_cross_price does
     pub fn get_cross_twap(
         env: Env,
         base_asset: AssetType,
         quote_asset: AssetType,
-        periods: u32
+        periods: u32,
+        oracle_kind: Option<OracleKind>
     ) -> Option<i128> {
-        let oracle_address = match (&base_asset, &quote_asset) {
-            (AssetType::StellarNative(_), AssetType::StellarNative(_)) => {
-                env.storage().instance().get(&DataKey::StellarOracle).unwrap()
-            },
-            _ => {
-                env.storage().instance().get(&DataKey::ExternalOracle).unwrap()
-            }
-        };
+        pausable::when_not_paused(&env);
         
+        let oracle_address = Self::cross_oracle_address(&env, &base_asset, &quote_asset, oracle_kind);
         let client = ReflectorClient::new(&env, &oracle_address);
         
-        let base = match base_asset {
-            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
-                Asset::Other(symbol)
-            },
-            AssetType::StellarNative(address) => {
-                Asset::Stellar(address)
-            }
-        };
-        
-        let quote = match quote_asset {
-            AssetType::Crypto(symbol) | AssetType::Stablecoin(symbol) | AssetType::Forex(symbol) => {
-                Asset::Other(symbol)
-            },
-            AssetType::StellarNative(address) => {
-                Asset::Stellar(address)
-            }
-        };
-        
+        let base = Self::to_reflector_asset(base_asset);
+        let quote = Self::to_reflector_asset(quote_asset);
         client.x_twap(&base, &quote, &periods)
     }
     
-    // Check for arbitrage opportunities between oracles
-    pub fn check_arbitrage(env: Env, asset_symbol: Symbol) -> Option<i128> {
-        let external_oracle: Address = env.storage()
-            .instance()
-            .get(&DataKey::ExternalOracle)
-            .unwrap();
-        let stellar_oracle: Address = env.storage()
-            .instance()
-            .get(&DataKey::StellarOracle)
-            .unwrap();
-        
-        let external_client = ReflectorClient::new(&env, &external_oracle);
-        let stellar_client = ReflectorClient::new(&env, &stellar_oracle);
-        
+    // Compare the external, stellar and forex oracle prices for a symbol.
+    // None unless at least two of them list it.
+    pub fn check_arbitrage(env: Env, asset_symbol: Symbol) -> Option<ArbitrageReport> {
         let asset = Asset::Other(asset_symbol);
+        let mut quoted = Vec::new(&env);
+        let mut legs: Vec<Option<i128>> = Vec::new(&env);
+        for oracle_kind in [OracleKind::External, OracleKind::Stellar, OracleKind::Forex] {
+            let oracle_address = Self::oracle_address(&env, &oracle_kind);
+            let client = ReflectorClient::new(&env, &oracle_address);
+            let price = client
+                .lastprice(&asset)
+                .map(|price_data| Self::normalize_price(&client, price_data.price));
+            if let Some(price) = price {
+                quoted.push_back(price);
+            }
+            legs.push_back(price);
+        }
         
-        let external_price = external_client.lastprice(&asset);
-        let stellar_price = stellar_client.lastprice(&asset);
+        if quoted.len() < 2 {
+            return None;
+        }
         
-        if external_price.is_some() && stellar_price.is_some() {
-            let ext_price = external_price.unwrap().price;
-            let stel_price = stellar_price.unwrap().price;
-            
-            // Return price difference
-            let diff = ext_price - stel_price;
-            let percentage = (diff * 10000) / ext_price; // Basis points
-            
-            log!(&env, "Arbitrage check: External={}, Stellar={}, Diff={}bps", 
-                 ext_price, stel_price, percentage);
-            
-            Some(percentage)
-        } else {
-            None
+        let mut low = i128::MAX;
+        let mut high = i128::MIN;
+        for price in quoted.iter() {
+            low = low.min(price);
+            high = high.max(price);
+        }
+        if low <= 0 {
+            return None;
         }
+        
+        // Widest pairwise spread, in basis points of the lower price
+        let max_spread_bps = (high - low) * 10000 / low;
+        
+        log!(&env, "Arbitrage check: External={}, Stellar={}, Forex={}, Spread={}bps",
+             legs.get_unchecked(0), legs.get_unchecked(1), legs.get_unchecked(2), max_spread_bps);
+        
+        Some(ArbitrageReport {
+            external_price: legs.get_unchecked(0),
+            stellar_price: legs.get_unchecked(1),
+            forex_price: legs.get_unchecked(2),
+            max_spread_bps,
+        })
     }
     
-    // Check stablecoin peg using forex oracle
+    // Check stablecoin peg using forex oracle. None if either price is
+    // missing or the USD reference is not positive.
     pub fn check_stablecoin_peg(env: Env, stablecoin: Symbol) -> Option<i128> {
         let forex_oracle: Address = env.storage()
             .instance()
@@ -266,7 +601,11 @@ impl OracleRouter {
         let client = ReflectorClient::new(&env, &forex_oracle);
         
         // Get USD price (should be 1.0)
-        let usd_price = client.lastprice(&Asset::Other(Symbol::new(&env, "USD")));
+        let usd = client.lastprice(&Asset::Other(Symbol::new(&env, "USD")))?.price;
+        let usd = Self::normalize_price(&client, usd);
+        if usd <= 0 {
+            return None;
+        }
         
         // Get stablecoin price from external oracle
         let external_oracle: Address = env.storage()
@@ -274,20 +613,33 @@ impl OracleRouter {
             .get(&DataKey::ExternalOracle)
             .unwrap();
         let external_client = ReflectorClient::new(&env, &external_oracle);
-        let stable_price = external_client.lastprice(&Asset::Other(stablecoin));
+        let stable = external_client.lastprice(&Asset::Other(stablecoin))?.price;
+        let stable = Self::normalize_price(&external_client, stable);
         
-        if usd_price.is_some() && stable_price.is_some() {
-            let usd = usd_price.unwrap().price;
-            let stable = stable_price.unwrap().price;
-            
-            // Calculate deviation from peg
-            let deviation = ((stable - usd) * 10000) / usd; // Basis points
-            
-            log!(&env, "Stablecoin peg deviation: {}bps", deviation);
-            
-            Some(deviation)
-        } else {
-            None
+        // Calculate deviation from peg
+        let deviation = ((stable - usd) * 10000) / usd; // Basis points
+        
+        log!(&env, "Stablecoin peg deviation: {}bps", deviation);
+        
+        Some(deviation)
+    }
+    
+    // Whether `stablecoin` is more than threshold_bps off its peg in either
+    // direction, publishing PegBreach when it is. False without price data.
+    pub fn is_stablecoin_depegged(env: Env, stablecoin: Symbol, threshold_bps: u32) -> bool {
+        let deviation_bps = match Self::check_stablecoin_peg(env.clone(), stablecoin.clone()) {
+            Some(deviation_bps) => deviation_bps,
+            None => return false,
+        };
+        
+        if deviation_bps.abs() <= threshold_bps as i128 {
+            return false;
         }
+        
+        PegBreach {
+            stablecoin,
+            deviation_bps,
+        }.publish(&env);
+        true
     }
-}
\ No newline at end of file
+}
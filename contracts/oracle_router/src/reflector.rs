@@ -3,6 +3,7 @@ use soroban_sdk::{contracttype, Address, Env, Symbol, Vec};
 
 // Oracle contract interface exported as ReflectorClient
 #[soroban_sdk::contractclient(name = "ReflectorClient")]
+#[allow(dead_code)] // only the generated client is used
 pub trait Contract {
     fn base(e: Env) -> Asset;
     fn assets(e: Env) -> Vec<Asset>;
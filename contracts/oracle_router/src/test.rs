@@ -0,0 +1,428 @@
+#![cfg(test)]
+
+use super::*;
+use mock_reflector::{Asset as MockAsset, MockReflector, MockReflectorClient};
+use soroban_sdk::{
+    map, symbol_short, vec, testutils::{Address as _, Events, Ledger}, Env, Address, IntoVal, Map, Val
+};
+
+// Stand-in release for upgrade tests; any valid contract WASM will do
+const UPGRADE_WASM: &[u8] = include_bytes!("../../testdata/add_u64.wasm");
+
+struct TestSetup<'a> {
+    env: Env,
+    client: OracleRouterClient<'a>,
+    external: MockReflectorClient<'a>,
+    stellar: MockReflectorClient<'a>,
+    forex: MockReflectorClient<'a>,
+}
+
+// Router on testnet with each feed pointed at its own mock oracle
+fn setup() -> TestSetup<'static> {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register(OracleRouter, ());
+    let client = OracleRouterClient::new(&env, &contract_id);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &Network::Testnet);
+
+    let external = register_oracle(&env, &client, &admin, OracleKind::External);
+    let stellar = register_oracle(&env, &client, &admin, OracleKind::Stellar);
+    let forex = register_oracle(&env, &client, &admin, OracleKind::Forex);
+
+    TestSetup { env, client, external, stellar, forex }
+}
+
+fn register_oracle(env: &Env, client: &OracleRouterClient, admin: &Address, oracle_kind: OracleKind) -> MockReflectorClient<'static> {
+    let oracle_id = env.register(MockReflector, ());
+    client.set_oracle(admin, &oracle_kind, &oracle_id);
+    MockReflectorClient::new(env, &oracle_id)
+}
+
+fn set_price(setup: &TestSetup, oracle: &MockReflectorClient, symbol: &Symbol, price: i128) {
+    let timestamp = setup.env.ledger().timestamp();
+    oracle.set_price(&MockAsset::Other(symbol.clone()), &price, &timestamp);
+}
+
+#[test]
+fn test_arbitrage_compares_feeds_with_different_decimals() {
+    let setup = setup();
+    let btc = symbol_short!("BTC");
+
+    // $1.00 at 7 decimals against $1.01 at 14 decimals: 1% apart
+    setup.stellar.set_decimals(&14);
+    set_price(&setup, &setup.external, &btc, 10_000_000);
+    set_price(&setup, &setup.stellar, &btc, 101_000_000_000_000);
+
+    let report = setup.client.check_arbitrage(&btc).unwrap();
+    assert_eq!(report.external_price, Some(100_000_000_000_000));
+    assert_eq!(report.stellar_price, Some(101_000_000_000_000));
+    assert_eq!(report.forex_price, None);
+    assert_eq!(report.max_spread_bps, 100);
+
+    // A single listing has nothing to compare against
+    assert_eq!(setup.client.check_arbitrage(&symbol_short!("ETH")), None);
+}
+
+#[test]
+fn test_stablecoin_peg_with_different_decimals() {
+    let setup = setup();
+    let usdc = symbol_short!("USDC");
+
+    // USD at 14 decimals on the forex feed, USDC at $0.99 with 7 decimals
+    setup.forex.set_decimals(&14);
+    set_price(&setup, &setup.forex, &symbol_short!("USD"), 100_000_000_000_000);
+    set_price(&setup, &setup.external, &usdc, 9_900_000);
+
+    assert_eq!(setup.client.check_stablecoin_peg(&usdc), Some(-100));
+    assert_eq!(setup.client.check_stablecoin_peg(&symbol_short!("USDT")), None);
+}
+
+#[test]
+fn test_median_price_normalizes_decimals() {
+    let setup = setup();
+    let btc = symbol_short!("BTC");
+
+    // $1.00 and $1.04 at 7 decimals, $1.02 at 14 decimals
+    setup.stellar.set_decimals(&14);
+    set_price(&setup, &setup.external, &btc, 10_000_000);
+    set_price(&setup, &setup.stellar, &btc, 102_000_000_000_000);
+    set_price(&setup, &setup.forex, &btc, 10_400_000);
+
+    let (median, sources) = setup.client.get_median_price_with_sources(&btc).unwrap();
+    assert_eq!(median.price, 102_000_000_000_000);
+    assert_eq!(sources, 3);
+}
+
+#[test]
+fn test_median_price_needs_two_sources() {
+    let setup = setup();
+    let btc = symbol_short!("BTC");
+
+    set_price(&setup, &setup.external, &btc, 10_000_000);
+    assert_eq!(setup.client.get_median_price(&btc), None);
+
+    // Two sources average
+    setup.forex.set_decimals(&14);
+    set_price(&setup, &setup.forex, &btc, 102_000_000_000_000);
+    assert_eq!(setup.client.get_median_price(&btc).unwrap().price, 101_000_000_000_000);
+}
+
+#[test]
+fn test_weighted_price_normalizes_decimals() {
+    let setup = setup();
+    let env = &setup.env;
+    let btc = symbol_short!("BTC");
+
+    // $1.00 at 7 decimals weighted 3, $1.04 at 14 decimals weighted 1
+    setup.stellar.set_decimals(&14);
+    set_price(&setup, &setup.external, &btc, 10_000_000);
+    set_price(&setup, &setup.stellar, &btc, 104_000_000_000_000);
+
+    let weights = map![env, (OracleKind::External, 3u32), (OracleKind::Stellar, 1u32), (OracleKind::Forex, 2u32)];
+    let price = setup.client.get_weighted_price(&btc, &weights).unwrap();
+    assert_eq!(price.price, 101_000_000_000_000);
+
+    // Unlisted everywhere
+    assert_eq!(setup.client.get_weighted_price(&symbol_short!("ETH"), &weights), None);
+}
+
+#[test]
+fn test_asset_route_overrides_default_oracle() {
+    let setup = setup();
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let usdc = symbol_short!("USDC");
+    let stablecoin = AssetType::Stablecoin(usdc.clone());
+
+    set_price(&setup, &setup.forex, &usdc, 10_000_000);
+    set_price(&setup, &setup.external, &usdc, 9_990_000);
+    assert_eq!(client.get_oracle_for_asset(&stablecoin), setup.forex.address);
+    assert_eq!(client.get_price(&stablecoin).unwrap().price, 10_000_000);
+
+    client.set_asset_route(&admin, &usdc, &OracleKind::External);
+    assert_eq!(client.get_asset_route(&usdc), Some(OracleKind::External));
+    assert_eq!(client.get_oracle_for_asset(&stablecoin), setup.external.address);
+    assert_eq!(client.get_price(&stablecoin).unwrap().price, 9_990_000);
+
+    client.remove_asset_route(&admin, &usdc);
+    assert_eq!(client.get_asset_route(&usdc), None);
+    assert_eq!(client.get_oracle_for_asset(&stablecoin), setup.forex.address);
+}
+
+#[test]
+fn test_asset_route_requires_admin() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+    let usdc = symbol_short!("USDC");
+
+    assert!(setup.client.try_set_asset_route(&other, &usdc, &OracleKind::External).is_err());
+    assert!(setup.client.try_remove_asset_route(&other, &usdc).is_err());
+}
+
+#[test]
+fn test_set_oracle_repoints_feed() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let btc = AssetType::Crypto(symbol_short!("BTC"));
+
+    let replacement_id = env.register(MockReflector, ());
+    let replacement = MockReflectorClient::new(env, &replacement_id);
+    set_price(&setup, &replacement, &symbol_short!("BTC"), 20_000_000);
+    client.set_oracle(&admin, &OracleKind::External, &replacement_id);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "oracle"), Symbol::new(env, "updated"), OracleKind::External).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let old_address: Option<Address> = data.get(Symbol::new(env, "old_address")).unwrap().into_val(env);
+    assert_eq!(old_address, Some(setup.external.address.clone()));
+
+    assert_eq!(client.get_oracle_for_asset(&btc), replacement_id);
+    assert_eq!(client.get_price(&btc).unwrap().price, 20_000_000);
+}
+
+#[test]
+fn test_set_oracle_requires_admin() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+
+    assert!(setup.client.try_set_oracle(&other, &OracleKind::External, &other).is_err());
+}
+
+#[test]
+fn test_price_detailed_reports_feed_metadata() {
+    let setup = setup();
+    let env = &setup.env;
+    let token = Address::generate(env);
+    let xlm = AssetType::StellarNative(token.clone());
+
+    setup.stellar.set_decimals(&14);
+    setup.stellar.set_resolution(&60);
+    setup.stellar.set_price(&MockAsset::Stellar(token), &1_000_000_000_000, &env.ledger().timestamp());
+    env.ledger().with_mut(|li| li.timestamp += 45);
+
+    let info = setup.client.get_price_detailed(&xlm).unwrap();
+    assert_eq!(info.price, 1_000_000_000_000);
+    assert_eq!(info.decimals, 14);
+    assert_eq!(info.resolution, 60);
+    assert_eq!(info.age_seconds, 45);
+    assert_eq!(setup.client.get_price_detailed(&AssetType::Crypto(symbol_short!("BTC"))), None);
+}
+
+#[test]
+fn test_get_prices_batches_across_oracles() {
+    let setup = setup();
+    let env = &setup.env;
+    let btc = symbol_short!("BTC");
+    let eur = symbol_short!("EUR");
+
+    set_price(&setup, &setup.external, &btc, 600_000_000_000);
+    set_price(&setup, &setup.forex, &eur, 11_000_000);
+
+    let assets = vec![
+        env,
+        AssetType::Forex(eur),
+        AssetType::Crypto(symbol_short!("ETH")),
+        AssetType::Crypto(btc),
+    ];
+    let prices = setup.client.get_prices(&assets);
+    assert_eq!(prices.len(), 3);
+    assert_eq!(prices.get_unchecked(0).unwrap().price, 11_000_000);
+    assert_eq!(prices.get_unchecked(1), None);
+    assert_eq!(prices.get_unchecked(2).unwrap().price, 600_000_000_000);
+}
+
+#[test]
+fn test_price_guarded_rejects_spot_far_from_twap() {
+    let setup = setup();
+    let btc = symbol_short!("BTC");
+    let asset = AssetType::Crypto(btc.clone());
+
+    // Without a TWAP there is nothing to check against
+    set_price(&setup, &setup.external, &btc, 10_300_000);
+    assert_eq!(setup.client.get_price_guarded(&asset, &500), None);
+
+    // 3% above the TWAP passes a 5% guard but not a 2% one
+    setup.external.set_twap(&MockAsset::Other(btc), &10_000_000);
+    assert_eq!(setup.client.get_price_guarded(&asset, &500).unwrap().price, 10_300_000);
+    assert_eq!(setup.client.get_price_guarded(&asset, &200), None);
+}
+
+#[test]
+fn test_arbitrage_reports_all_three_legs() {
+    let setup = setup();
+    let btc = symbol_short!("BTC");
+
+    set_price(&setup, &setup.external, &btc, 10_000_000);
+    set_price(&setup, &setup.stellar, &btc, 10_100_000);
+    set_price(&setup, &setup.forex, &btc, 10_300_000);
+
+    // Widest spread is external against forex
+    let report = setup.client.check_arbitrage(&btc).unwrap();
+    assert_eq!(report.external_price, Some(100_000_000_000_000));
+    assert_eq!(report.stellar_price, Some(101_000_000_000_000));
+    assert_eq!(report.forex_price, Some(103_000_000_000_000));
+    assert_eq!(report.max_spread_bps, 300);
+}
+
+#[test]
+fn test_stablecoin_depeg_publishes_breach() {
+    let setup = setup();
+    let env = &setup.env;
+    let usdc = symbol_short!("USDC");
+
+    set_price(&setup, &setup.forex, &symbol_short!("USD"), 10_000_000);
+    set_price(&setup, &setup.external, &usdc, 9_900_000);
+
+    // 1% off peg is inside a 2% band
+    assert!(!setup.client.is_stablecoin_depegged(&usdc, &200));
+
+    assert!(setup.client.is_stablecoin_depegged(&usdc, &50));
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "peg"), Symbol::new(env, "breach"), usdc.clone()).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let deviation_bps: i128 = data.get(Symbol::new(env, "deviation_bps")).unwrap().into_val(env);
+    assert_eq!(deviation_bps, -100);
+
+    // No price data is never a breach
+    assert!(!setup.client.is_stablecoin_depegged(&symbol_short!("USDT"), &0));
+}
+
+#[test]
+fn test_list_assets_and_is_supported() {
+    let setup = setup();
+    let env = &setup.env;
+    let btc = symbol_short!("BTC");
+    let usdc = symbol_short!("USDC");
+
+    set_price(&setup, &setup.external, &btc, 600_000_000_000);
+    set_price(&setup, &setup.external, &usdc, 10_000_000);
+    set_price(&setup, &setup.external, &btc, 610_000_000_000);
+
+    assert_eq!(
+        setup.client.list_assets(&OracleKind::External),
+        vec![env, Asset::Other(btc.clone()), Asset::Other(usdc.clone())]
+    );
+    assert_eq!(setup.client.list_assets(&OracleKind::Forex), Vec::new(env));
+
+    assert!(setup.client.is_supported(&AssetType::Crypto(btc)));
+    // Stablecoins route to the forex feed, which doesn't list USDC
+    assert!(!setup.client.is_supported(&AssetType::Stablecoin(usdc.clone())));
+    setup.client.set_asset_route(&setup.client.get_admin(), &usdc, &OracleKind::External);
+    assert!(setup.client.is_supported(&AssetType::Stablecoin(usdc)));
+}
+
+#[test]
+fn test_cross_price_oracle_selection() {
+    let setup = setup();
+    let env = &setup.env;
+    let btc = AssetType::Crypto(symbol_short!("BTC"));
+    let eth = AssetType::Crypto(symbol_short!("ETH"));
+    let token_a = Address::generate(env);
+    let token_b = Address::generate(env);
+    let now = env.ledger().timestamp();
+
+    let btc_asset = MockAsset::Other(symbol_short!("BTC"));
+    let eth_asset = MockAsset::Other(symbol_short!("ETH"));
+    setup.external.set_x_price(&btc_asset, &eth_asset, &200_000_000, &now);
+    setup.external.set_x_twap(&btc_asset, &eth_asset, &195_000_000);
+    setup.forex.set_x_price(&btc_asset, &eth_asset, &210_000_000, &now);
+    setup.stellar.set_x_price(&MockAsset::Stellar(token_a.clone()), &MockAsset::Stellar(token_b.clone()), &5_000_000, &now);
+
+    // Defaults: external for symbols, stellar for two Stellar assets
+    assert_eq!(setup.client.get_cross_price(&btc, &eth, &None).unwrap().price, 200_000_000);
+    assert_eq!(setup.client.get_cross_twap(&btc, &eth, &5, &None), Some(195_000_000));
+    let pair_price = setup.client.get_cross_price(
+        &AssetType::StellarNative(token_a),
+        &AssetType::StellarNative(token_b),
+        &None,
+    );
+    assert_eq!(pair_price.unwrap().price, 5_000_000);
+
+    // An explicit oracle overrides the default
+    assert_eq!(setup.client.get_cross_price(&btc, &eth, &Some(OracleKind::Forex)).unwrap().price, 210_000_000);
+    assert_eq!(setup.client.get_cross_twap(&btc, &eth, &5, &Some(OracleKind::Forex)), None);
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let setup = setup();
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let new_admin = Address::generate(&setup.env);
+
+    assert!(client.try_propose_admin(&new_admin, &new_admin).is_err());
+    client.propose_admin(&admin, &new_admin);
+    assert_eq!(client.get_admin(), admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+
+    assert!(client.try_accept_admin(&admin).is_err());
+    client.accept_admin(&new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+}
+
+#[test]
+fn test_pause_requires_admin() {
+    let setup = setup();
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let other = Address::generate(&setup.env);
+
+    assert!(client.try_pause(&other).is_err());
+    assert!(!client.is_paused());
+
+    client.pause(&admin);
+    assert!(client.is_paused());
+    assert!(client.try_unpause(&other).is_err());
+    client.unpause(&admin);
+    assert!(!client.is_paused());
+}
+
+#[test]
+fn test_version() {
+    let setup = setup();
+    assert_eq!(setup.client.version(), CONTRACT_VERSION);
+    assert_eq!(setup.client.version(), 2);
+    assert_eq!(
+        setup.client.build_info(),
+        String::from_str(&setup.env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    );
+}
+
+#[test]
+fn test_is_admin() {
+    let setup = setup();
+    let admin = setup.client.get_admin();
+    let user = Address::generate(&setup.env);
+
+    assert!(setup.client.is_admin(&admin));
+    assert!(!setup.client.is_admin(&user));
+}
+
+#[test]
+fn test_upgrade() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let admin = client.get_admin();
+    let other = Address::generate(env);
+    let new_wasm_hash = env.deployer().upload_contract_wasm(UPGRADE_WASM);
+
+    assert!(client.try_upgrade(&other, &new_wasm_hash).is_err());
+
+    client.upgrade(&admin, &new_wasm_hash);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "contract"), Symbol::new(env, "upgraded")).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let from_version: u32 = data.get(Symbol::new(env, "from_version")).unwrap().into_val(env);
+    let wasm_hash: BytesN<32> = data.get(Symbol::new(env, "new_wasm_hash")).unwrap().into_val(env);
+    assert_eq!((from_version, wasm_hash), (CONTRACT_VERSION, new_wasm_hash));
+
+    // The same contract id now runs the new code
+    let sum: u64 = env.invoke_contract(&client.address, &Symbol::new(env, "add"), vec![env, 1u64.into_val(env), 2u64.into_val(env)]);
+    assert_eq!(sum, 3);
+}
@@ -0,0 +1,13 @@
+// Settlement (DEX) Client Interface
+use soroban_sdk::{Address, Env};
+
+// Settlement contract interface exported as DexClient. Any DEX or router
+// (Soroswap, Phoenix, ...) can be plugged in through an adapter contract
+// implementing this trait.
+#[soroban_sdk::contractclient(name = "DexClient")]
+pub trait Dex {
+    // Pull `amount_in` of `asset_in` from `from` under the allowance it
+    // granted, swap it and send the proceeds to `to`. Must fail rather than
+    // return less than `min_amount_out`. Returns the realized output amount.
+    fn swap(e: Env, asset_in: Address, amount_in: i128, min_amount_out: i128, from: Address, to: Address) -> i128;
+}
@@ -0,0 +1,94 @@
+// Order lifecycle events, published for keepers, indexers and frontends
+use soroban_sdk::{contractevent, Address};
+
+use crate::AssetRef;
+
+#[contractevent(topics = ["order", "created"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderCreated {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+    pub stop_price: i128,
+}
+
+#[contractevent(topics = ["order", "executed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderExecuted {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+    pub price: i128,
+}
+
+#[contractevent(topics = ["order", "partial_fill"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderPartiallyFilled {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+    pub amount: i128,
+    pub price: i128,
+}
+
+#[contractevent(topics = ["order", "cancelled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderCancelled {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+}
+
+#[contractevent(topics = ["order", "expired"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderExpired {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+}
+
+// Stop price and amount after a modify_order call
+#[contractevent(topics = ["order", "modified"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderModified {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+    pub stop_price: i128,
+    pub amount: i128,
+}
+
+// A trailing stop ratcheted up after the price set a new high
+#[contractevent(topics = ["order", "trailing"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrailingAdjusted {
+    #[topic]
+    pub order_id: u64,
+    #[topic]
+    pub owner: Address,
+    pub asset: AssetRef,
+    pub old_stop_price: i128,
+    pub new_stop_price: i128,
+    pub highest_price: i128,
+}
+
+// Creation and execution on `asset` halted or resumed
+#[contractevent(topics = ["asset", "paused"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetPauseChanged {
+    #[topic]
+    pub asset: AssetRef,
+    pub paused: bool,
+}
@@ -1,34 +1,62 @@
 #![no_std]
 
+pub mod dex;
+mod events;
 mod reflector;
+mod test;
+use dex::DexClient;
+use events::{AssetPauseChanged, OrderCancelled, OrderExpired, OrderCreated, OrderExecuted, OrderModified, OrderPartiallyFilled, TrailingAdjusted};
 use reflector::{ReflectorClient, Asset, PriceData};
+use stellar_guard_common::decimal::{normalize_price, to_scaled, MAX_SANE_AMOUNT, STELLAR_DECIMALS};
+use stellar_guard_common::{ownable, pausable, upgradeable};
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype,
-    Address, Env, Map, Vec, log, Symbol, String
+    contract, contractimpl, contracttype, token,
+    vec, Address, BytesN, Env, I256, Map, Vec, log, Symbol, String
 };
 
-// Reflector Oracle Addresses - Testnet
+// Reflector Oracle Address - Testnet, the fallback before an oracle is set
 const TESTNET_EXTERNAL_ORACLE: &str = "CCYOZJCOPG34LLQQ7N24YXBM7LL62R7ONMZ3G6WZAAYPB5OYKOMJRN63";
-const TESTNET_STELLAR_ORACLE: &str = "CAVLP5DH2GJPZMVO7IJY4CVOD5MWEFTJFVPD2YY2FQXOQHRGHK4D6HLP";
-const TESTNET_FOREX_ORACLE: &str = "CCSSOHTBL3LEWUCBBEB5NJFC2OKFRC74OWEIJIZLRJBGAAU4VMU5NV4W";
-
-// Reflector Oracle Addresses - Mainnet  
-const MAINNET_EXTERNAL_ORACLE: &str = "CAFJZQWSED6YAWZU3GWRTOCNPPCGBN32L7QV43XX5LZLFTK6JLN34DLN";
-const MAINNET_STELLAR_ORACLE: &str = "CALI2BYU2JE6WVRUFYTS6MSBNEHGJ35P4AVCZYF3B6QOE3QKOB2PLE6M";
-const MAINNET_FOREX_ORACLE: &str = "CBKGPWGKSKZF52CFHMTRR23TBWTPMRDIYZ4O2P5VS65BMHYH4DXMCJZC";
 
 // Contract Constants
 const MAX_PERSISTENT_TTL: u32 = 31536000; // 1 year in seconds
-const MIN_ORDER_AMOUNT: i128 = 1_000_000; // 0.1 token (7 decimals)
-const PROTOCOL_FEE_BPS: u32 = 10; // 0.1%
-const MAX_ORDERS_PER_USER: u32 = 100; // Max orders per user
+// Bump on every release so deployments can be matched to source
+pub const CONTRACT_VERSION: u32 = 2;
+const MIN_ORDER_AMOUNT: i128 = to_scaled(0, 1_000_000, STELLAR_DECIMALS); // 0.1 token, default minimum
+const PROTOCOL_FEE_BPS: u32 = 10; // 0.1%, default execution fee
+const MAX_EXECUTION_FEE_BPS: u32 = 100; // 1%
+const MAX_ORDERS_PER_USER: u32 = 100; // Default max orders per user
+const MAX_ORDERS_PER_USER_CAP: u32 = 200; // Highest configurable max orders per user
+const MAX_MEMO_LENGTH: u32 = 64; // Max memo length in bytes
+const MAX_KEEPER_REWARD_BPS: u32 = 500; // 5%
+const MAX_SCALE_LEVELS: u32 = 10; // Max take-profit levels per scaled order
+const CONFIDENCE_SAMPLES: u32 = 5; // Recent prices used to measure dispersion
+const MAX_BULK_CANCEL: u32 = 50; // Max orders cancelled per cancel_orders_before call
+const VAR_TRIGGER_BAND_BPS: i128 = 500; // Orders within 5% of a trigger count towards VaR
+const MAX_VAR_SCAN: u32 = 200; // Max orders scanned per get_protocol_var call
+const MAX_TIMELINE_EVENTS: u32 = 20; // Audit entries kept per order
+const MAX_FEE_RECIPIENTS: u32 = 10; // Max addresses sharing protocol fees
+const MAX_CHANDELIER_LOOKBACK: u32 = 100; // Max oracle records a chandelier stop looks back over
+const MAX_HIGH_CONFIRMATIONS: u32 = 10; // Max checks a trailing high can be required to hold for
+const MAX_GUARANTEE_PREMIUM_BPS: u32 = 500; // 5%, max premium for a guaranteed stop
+const ANCHOR_TWAP_PERIODS: u32 = 5; // TWAP window a stale trailing anchor is refreshed from
+const MAX_FALLBACK_ORACLES: u32 = 3; // Oracles tried after the primary for a spot price
+const PRICE_SCALE: i128 = to_scaled(1, 0, STELLAR_DECIMALS); // Oracle prices carry 7 decimals
+
+// Asset an order is placed on: a Stellar Classic/Soroban asset or an
+// external symbol (BTC, ETH, ...) quoted by Reflector
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AssetRef {
+    Stellar(Address),
+    Other(Symbol),
+}
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct StopLossOrder {
     pub owner: Address,
-    pub asset: Symbol,
+    pub asset: AssetRef,
     pub amount: i128,
     pub stop_price: i128,
     pub trailing_percent: Option<u32>,
@@ -36,6 +64,19 @@ pub struct StopLossOrder {
     pub take_profit_price: Option<i128>,
     pub created_at: u64,
     pub status: OrderStatus,
+    pub executed_amount: Option<i128>, // realized output from settlement
+    pub memo: Option<String>, // user label, at most MAX_MEMO_LENGTH bytes
+    pub expires_at: Option<u64>, // ledger timestamp after which the order expires
+    pub trigger_source: TriggerSource,
+    pub max_confidence_bps: Option<u32>, // defer execution while recent price dispersion exceeds this
+    pub limit_price: Option<i128>, // stop-limit: the stop never fills below this price
+    pub filled_amount: i128, // amount sold so far across partial fills and execution
+    pub avg_fill_price: i128, // fill price weighted by amount over filled_amount
+    pub fill_or_kill_floor: Option<i128>, // cancel instead of filling below this price
+    pub high_confirmations: Option<u32>, // checks a new high must hold for before a trailing stop ratchets
+    pub candidate_high: i128, // lowest price across the checks confirming a new high
+    pub high_checks: u32, // checks the candidate high has held for; 0 when none is pending
+    pub high_checked_at: u64, // ledger timestamp of the latest counted check
 }
 
 #[contracttype]
@@ -44,6 +85,56 @@ pub enum OrderStatus {
     Active,
     Executed,
     Cancelled,
+    Expired,
+}
+
+// Outcome of check_and_execute_detailed
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CheckResult {
+    Executed(i128),         // filled in full at this price
+    PartiallyFilled(i128),  // sold a chunk or scale level at this price, rest still Active
+    Cancelled,              // killed instead of filled below its fill-or-kill floor
+    TrailingAdjusted(i128), // not triggered, but the stop moved to this price
+    NotTriggered,
+    Inactive,               // already closed, or expired by this check
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TriggerDirection {
+    Above, // fire when the price rises to or above the trigger
+    Below, // fire when the price falls to or below the trigger
+}
+
+// Cross-asset trigger: the order fires when the (trigger_asset, position
+// asset) cross price crosses the order's stop_price in `direction`. The
+// cross price is the spot x_last_price, or x_twap when twap_periods is set.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CrossTrigger {
+    pub trigger_asset: AssetRef,
+    pub direction: TriggerDirection,
+    pub twap_periods: Option<u32>,
+}
+
+// Chandelier exit: the stop sits `drop_bps` below the highest of the last
+// `lookback_periods` oracle prices, so it follows the rolling high up and
+// down rather than only ratcheting.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChandelierTrigger {
+    pub lookback_periods: u32,
+    pub drop_bps: u32,
+}
+
+// Which price an order's trigger is evaluated against
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TriggerSource {
+    OrderAsset,          // the order asset's own price
+    Cross(CrossTrigger), // a cross price, for cross-asset stops
+    Chandelier(ChandelierTrigger), // own price against a rolling-high stop
 }
 
 #[contracttype]
@@ -55,16 +146,111 @@ pub enum OrderType {
     OCO, // One-Cancels-Other
 }
 
+// Parameters for a single order in a batch request. For trailing stops
+// `stop_price` carries the trailing percent; for take-profit orders it is
+// the take-profit price.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderParams {
+    pub asset: AssetRef,
+    pub amount: i128,
+    pub stop_price: i128,
+    pub order_type: OrderType,
+    pub memo: Option<String>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OrderEventKind {
+    Created,
+    TrailingAdjusted,
+    PartiallyFilled,
+    Executed,
+    Cancelled,
+    Expired,
+    Modified,
+}
+
+// Entry in an order's audit timeline. `price` is the stop for Created,
+// TrailingAdjusted and Modified, the fill price for fills and execution, and 0 for
+// Cancelled and Expired. `amount` is the amount sold for fills and the order
+// amount otherwise.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderEvent {
+    pub kind: OrderEventKind,
+    pub timestamp: u64,
+    pub price: i128,
+    pub amount: i128,
+}
+
+// Keeper reward that decays linearly from max_reward_bps, when an order
+// first becomes eligible to execute, to min_reward_bps after decay_seconds,
+// so the first keeper to act earns the most
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeeperAuction {
+    pub max_reward_bps: u32,
+    pub min_reward_bps: u32,
+    pub decay_seconds: u64,
+}
+
+// The two prices an OCO order closes at, with how far each sits from the
+// current price in basis points of it. A distance at or below zero means
+// that leg has been reached.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OcoLevels {
+    pub stop_price: i128,
+    pub take_profit_price: i128,
+    pub stop_distance_bps: i128,
+    pub take_profit_distance_bps: i128,
+}
+
+// Admin-tunable contract settings
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub staleness_seconds: u64, // max age of an oracle price before it is rejected
+    pub execution_fee_bps: u32, // percentage fee taken from each settled order
+    pub creation_fee: i128, // flat fee per order created, in fee_token
+    pub fee_token: Option<Address>, // token the creation fee is paid in
+    pub min_order_amount: i128, // smallest amount an order may be created with
+    pub max_orders_per_user: u32, // open orders a single user may hold
+}
+
 #[contracttype]
 pub enum DataKey {
-    Orders,
+    Order(u64),
     OrderCounter,
     AllOrderIds,  // New: Track all order IDs
     UserOrders(Address),
     Config,
-    Admin,
     OracleAddress,
-    ProtocolFeeRecipient,
+    FeeRecipients, // Vec<(Address, weight_bps)> sharing protocol fees
+    DexAddress,
+    KeeperRewardBps,
+    CreationFeesCollected, // Running total of creation fees paid to the recipient
+    AccruedFees(Address), // Execution fees held for the fee recipients, per token
+    KeeperRewards(Address), // Map<token, accrued amount>
+    ScaleLevels(u64), // Unfilled (price, amount) levels of a scaled take-profit
+    ReserveToken, // Token the protocol reserve pays out in
+    Reserve(AssetRef), // Reserve balance committed to buying an asset
+    TotalReserve, // Sum of all Reserve balances, in the reserve token
+    ReserveProceeds(AssetRef), // Order-asset tokens the reserve has bought, awaiting withdrawal
+    OrderTimeline(u64), // Vec<OrderEvent>, at most MAX_TIMELINE_EVENTS
+    AssetPaused(AssetRef), // Set while creation and execution on an asset are halted
+    AnchorRefreshGap, // Seconds unchecked after which a trailing anchor is refreshed from TWAP
+    TrailingCheckedAt(u64), // Ledger timestamp a trailing order was last checked
+    KeeperAuction, // Decaying keeper reward replacing KeeperRewardBps when set
+    EligibleSince(u64), // Ledger timestamp a check first found the order triggered
+    GuaranteePremium(u64), // Premium bps of a guaranteed stop, charged as a fee on execution
+    GuaranteedLiability(AssetRef), // Quote value of open guaranteed stops on an asset
+    MockPrice(AssetRef), // Sandbox builds only: PriceData used instead of the oracle's last price
+    MaxFillSize, // Largest amount sold per execution; larger orders fill in chunks
+    FallbackOracles, // Vec<Address> tried in order when the primary oracle has no fresh price
+    ActiveOrderCount, // Orders currently Active, maintained on creation and close
+    ExecutedOrderCount, // Orders that have reached Executed
 }
 
 #[contract]
@@ -77,623 +263,2620 @@ impl StopLossContract {
         admin: Address,
         oracle_address: Address,
         fee_recipient: Address,
+        keeper_reward_bps: u32,
+        staleness_seconds: u64,
     ) {
-        if env.storage().instance().has(&DataKey::Admin) {
+        if ownable::has_admin(&env) {
             panic!("Already initialized");
         }
         
-        env.storage().instance().set(&DataKey::Admin, &admin);
+        if keeper_reward_bps > MAX_KEEPER_REWARD_BPS {
+            panic!("Invalid keeper reward");
+        }
+        Self::validate_staleness(staleness_seconds);
+        
+        ownable::set_admin(&env, &admin);
         env.storage().instance().set(&DataKey::OracleAddress, &oracle_address);
-        env.storage().instance().set(&DataKey::ProtocolFeeRecipient, &fee_recipient);
+        env.storage().instance().set(&DataKey::FeeRecipients, &vec![&env, (fee_recipient, 10000u32)]);
+        env.storage().instance().set(&DataKey::KeeperRewardBps, &keeper_reward_bps);
+        env.storage().instance().set(&DataKey::Config, &Config {
+            staleness_seconds,
+            execution_fee_bps: PROTOCOL_FEE_BPS,
+            creation_fee: 0,
+            fee_token: None,
+            min_order_amount: MIN_ORDER_AMOUNT,
+            max_orders_per_user: MAX_ORDERS_PER_USER,
+        });
         env.storage().persistent().set(&DataKey::OrderCounter, &0u64);
         
         // Extend instance TTL
         env.storage().instance().extend_ttl(100, MAX_PERSISTENT_TTL);
     }
     
-    pub fn create_stop_loss(
-        env: Env,
-        owner: Address,
-        asset: Symbol,
-        amount: i128,
-        stop_price: i128,
-    ) -> u64 {
-        owner.require_auth();
-        
-        if amount < MIN_ORDER_AMOUNT {
-            panic!("Amount too small");
+    // Set the settlement contract used to execute triggered orders
+    pub fn set_dex_address(env: Env, admin: Address, dex_address: Address) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        let order_id = Self::get_next_order_id(&env);
-        let current_price = Self::get_current_price(&env, &asset);
-        
-        let order = StopLossOrder {
-            owner: owner.clone(),
-            asset,
-            amount,
-            stop_price,
-            trailing_percent: None,
-            highest_price: current_price,
-            take_profit_price: None,
-            created_at: env.ledger().timestamp(),
-            status: OrderStatus::Active,
-        };
-        
-        Self::save_order(&env, order_id, &order);
-        Self::add_user_order(&env, &owner, order_id);
+        env.storage().instance().set(&DataKey::DexAddress, &dex_address);
         
-        log!(&env, "Stop-loss order created: {}", order_id);
-        order_id
+        log!(&env, "DEX address set: {}", dex_address);
     }
     
-    pub fn create_trailing_stop(
-        env: Env,
-        owner: Address,
-        asset: Symbol,
-        amount: i128,
-        trailing_percent: u32,
-    ) -> u64 {
-        owner.require_auth();
-        
-        if amount < MIN_ORDER_AMOUNT {
-            panic!("Amount too small");
+    pub fn get_dex_address(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::DexAddress)
+    }
+    
+    // Token the protocol reserve is held and paid out in. Reserve fills
+    // value it 1:1 with the oracle's quote currency. Can't change while any
+    // reserve balance is held, as that would re-denominate it.
+    pub fn set_reserve_token(env: Env, admin: Address, token: Address) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        if trailing_percent == 0 || trailing_percent > 50 {
-            panic!("Invalid trailing percent");
+        if Self::get_total_reserve(env.clone()) != 0 {
+            panic!("Reserve not empty");
         }
         
-        let order_id = Self::get_next_order_id(&env);
-        let current_price = Self::get_current_price(&env, &asset);
-        let stop_price = current_price * (100 - trailing_percent as i128) / 100;
-        
-        let order = StopLossOrder {
-            owner: owner.clone(),
-            asset,
-            amount,
-            stop_price,
-            trailing_percent: Some(trailing_percent),
-            highest_price: current_price,
-            take_profit_price: None,
-            created_at: env.ledger().timestamp(),
-            status: OrderStatus::Active,
-        };
-        
-        Self::save_order(&env, order_id, &order);
-        Self::add_user_order(&env, &owner, order_id);
+        env.storage().instance().set(&DataKey::ReserveToken, &token);
         
-        log!(&env, "Trailing stop order created: {}", order_id);
-        order_id
+        log!(&env, "Reserve token set: {}", token);
     }
     
-    pub fn create_oco_order(
-        env: Env,
-        owner: Address,
-        asset: Symbol,
-        amount: i128,
-        stop_price: i128,
-        take_profit_price: i128,
-    ) -> u64 {
-        owner.require_auth();
-        
-        if amount < MIN_ORDER_AMOUNT {
-            panic!("Amount too small");
-        }
-        
-        let order_id = Self::get_next_order_id(&env);
-        let current_price = Self::get_current_price(&env, &asset);
+    pub fn get_reserve_token(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::ReserveToken)
+    }
+    
+    // Deposit reserve tokens committed to buying `asset` from triggered
+    // orders when no DEX is configured or its swap fails
+    pub fn fund_reserve(env: Env, from: Address, asset: AssetRef, amount: i128) {
+        from.require_auth();
         
-        if stop_price >= current_price || take_profit_price <= current_price {
-            panic!("Invalid price levels");
+        if amount <= 0 {
+            panic!("Invalid amount");
         }
         
-        let order = StopLossOrder {
-            owner: owner.clone(),
-            asset,
-            amount,
-            stop_price,
-            trailing_percent: None,
-            highest_price: current_price,
-            take_profit_price: Some(take_profit_price),
-            created_at: env.ledger().timestamp(),
-            status: OrderStatus::Active,
-        };
+        let reserve_token = Self::get_reserve_token(env.clone())
+            .unwrap_or_else(|| panic!("Reserve token not set"));
+        token::Client::new(&env, &reserve_token).transfer(&from, env.current_contract_address(), &amount);
         
-        Self::save_order(&env, order_id, &order);
-        Self::add_user_order(&env, &owner, order_id);
+        Self::adjust_reserve(&env, &asset, amount);
         
-        log!(&env, "OCO order created: {}", order_id);
-        order_id
+        log!(&env, "Reserve funded by {}: {}", from, amount);
     }
     
-    pub fn check_and_execute(env: Env, order_id: u64) -> bool {
-        let mut order = Self::get_order(&env, order_id);
-        
-        if order.status != OrderStatus::Active {
-            return false;
+    pub fn get_reserve(env: Env, asset: AssetRef) -> i128 {
+        env.storage().persistent().get(&DataKey::Reserve(asset)).unwrap_or(0)
+    }
+    
+    pub fn get_total_reserve(env: Env) -> i128 {
+        env.storage().persistent().get(&DataKey::TotalReserve).unwrap_or(0)
+    }
+    
+    // Take reserve tokens committed to `asset` back out. The balance backing
+    // open guaranteed stops on the asset stays in place.
+    pub fn withdraw_reserve(env: Env, admin: Address, asset: AssetRef, amount: i128, to: Address) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        let current_price = Self::get_current_price(&env, &order.asset);
-        let mut should_execute = false;
-        let mut execution_reason = "";
-        
-        // Update trailing stop if applicable
-        if let Some(trailing_percent) = order.trailing_percent {
-            if current_price > order.highest_price {
-                order.highest_price = current_price;
-                let new_stop = current_price * (100 - trailing_percent as i128) / 100;
-                if new_stop > order.stop_price {
-                    order.stop_price = new_stop;
-                    Self::save_order(&env, order_id, &order);
-                    log!(&env, "Trailing stop adjusted to: {}", new_stop);
-                }
-            }
+        let balance = Self::get_reserve(env.clone(), asset.clone());
+        if amount <= 0 || amount > balance {
+            panic!("Invalid amount");
         }
-        
-        // Check stop-loss condition
-        if current_price <= order.stop_price {
-            should_execute = true;
-            execution_reason = "stop-loss triggered";
+        if balance - amount < Self::get_guaranteed_liability(env.clone(), asset.clone()) {
+            panic!("Reserve backs open guarantees");
         }
         
-        // Check take-profit condition
-        if let Some(take_profit) = order.take_profit_price {
-            if current_price >= take_profit {
-                should_execute = true;
-                execution_reason = "take-profit triggered";
-            }
-        }
+        let reserve_token = Self::get_reserve_token(env.clone())
+            .unwrap_or_else(|| panic!("Reserve token not set"));
+        Self::adjust_reserve(&env, &asset, -amount);
+        token::Client::new(&env, &reserve_token).transfer(&env.current_contract_address(), &to, &amount);
         
-        if should_execute {
-            Self::execute_order(&env, order_id, current_price);
-            log!(&env, "Order {} executed: {}", order_id, execution_reason);
-            true
-        } else {
-            false
-        }
+        log!(&env, "Reserve withdrawn to {}: {}", to, amount);
     }
     
-    // NEW: Create TWAP-based stop loss for more stable execution
-    pub fn create_twap_stop(
-        env: Env,
-        owner: Address,
-        asset: Symbol,
-        amount: i128,
-        twap_periods: u32,
-        stop_percentage: u32,
-    ) -> u64 {
-        owner.require_auth();
-        
-        if amount < MIN_ORDER_AMOUNT {
-            panic!("Amount too small");
+    // Order-asset tokens the reserve has bought from triggered orders
+    pub fn get_reserve_proceeds(env: Env, asset: AssetRef) -> i128 {
+        env.storage().persistent().get(&DataKey::ReserveProceeds(asset)).unwrap_or(0)
+    }
+    
+    // Send everything the reserve has bought of `asset` to `to`. Returns the
+    // amount swept.
+    pub fn sweep_reserve_proceeds(env: Env, admin: Address, asset: AssetRef, to: Address) -> i128 {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        if twap_periods < 3 || twap_periods > 20 {
-            panic!("TWAP periods must be between 3 and 20");
+        let token_address = match &asset {
+            AssetRef::Stellar(token_address) => token_address.clone(),
+            AssetRef::Other(_) => panic!("Asset has no token"),
+        };
+        let amount = Self::get_reserve_proceeds(env.clone(), asset.clone());
+        if amount == 0 {
+            panic!("Nothing to sweep");
         }
         
-        let order_id = Self::get_next_order_id(&env);
-        
-        // Get TWAP price instead of spot price
-        let twap_price = Self::get_twap_price(&env, &asset, twap_periods);
-        let stop_price = twap_price * (100 - stop_percentage as i128) / 100;
-        
-        let order = StopLossOrder {
-            owner: owner.clone(),
-            asset,
-            amount,
-            stop_price,
-            trailing_percent: None,
-            highest_price: twap_price,
-            take_profit_price: None,
-            created_at: env.ledger().timestamp(),
-            status: OrderStatus::Active,
-        };
+        env.storage().persistent().remove(&DataKey::ReserveProceeds(asset));
+        token::Client::new(&env, &token_address).transfer(&env.current_contract_address(), &to, &amount);
         
-        Self::save_order(&env, order_id, &order);
-        Self::add_user_order(&env, &owner, order_id);
+        log!(&env, "Reserve proceeds swept to {}: {}", to, amount);
+        amount
+    }
+    
+    // Oracle prices older than this are rejected. Tune to the Reflector
+    // resolution of the assets being traded.
+    pub fn set_staleness_seconds(env: Env, admin: Address, staleness_seconds: u64) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        Self::validate_staleness(staleness_seconds);
         
-        log!(&env, "TWAP stop-loss created: {} (TWAP: {}, Stop: {})", 
-             order_id, twap_price, stop_price);
+        let mut config = Self::get_config(env.clone());
+        config.staleness_seconds = staleness_seconds;
+        env.storage().instance().set(&DataKey::Config, &config);
         
-        order_id
+        log!(&env, "Staleness window set: {}s", staleness_seconds);
     }
     
-    // NEW: Check and execute using TWAP instead of spot price
-    pub fn check_and_execute_twap(env: Env, order_id: u64, twap_periods: u32) -> bool {
-        let mut order = Self::get_order(&env, order_id);
-        
-        if order.status != OrderStatus::Active {
-            return false;
+    // Replace the fixed keeper reward with a decaying one, or restore the
+    // fixed reward with None
+    pub fn set_keeper_auction(env: Env, admin: Address, auction: Option<KeeperAuction>) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        // Use TWAP for more stable price comparison
-        let twap_price = Self::get_twap_price(&env, &order.asset, twap_periods);
-        let mut should_execute = false;
-        
-        // Update trailing stop based on TWAP
-        if let Some(trailing_percent) = order.trailing_percent {
-            if twap_price > order.highest_price {
-                order.highest_price = twap_price;
-                let new_stop = twap_price * (100 - trailing_percent as i128) / 100;
-                if new_stop > order.stop_price {
-                    order.stop_price = new_stop;
-                    Self::save_order(&env, order_id, &order);
-                    log!(&env, "TWAP trailing stop adjusted to: {}", new_stop);
+        match auction {
+            Some(auction) => {
+                if auction.max_reward_bps > MAX_KEEPER_REWARD_BPS
+                    || auction.min_reward_bps > auction.max_reward_bps
+                    || auction.decay_seconds == 0
+                {
+                    panic!("Invalid keeper auction");
                 }
-            }
+                env.storage().instance().set(&DataKey::KeeperAuction, &auction);
+            },
+            None => env.storage().instance().remove(&DataKey::KeeperAuction),
         }
+    }
+    
+    pub fn get_keeper_auction(env: Env) -> Option<KeeperAuction> {
+        env.storage().instance().get(&DataKey::KeeperAuction)
+    }
+    
+    // Keeper reward executing `order_id` would pay now. Under an auction an
+    // order not yet seen triggered pays the maximum.
+    pub fn get_keeper_reward_bps(env: Env, order_id: u64) -> u32 {
+        let auction: KeeperAuction = match Self::get_keeper_auction(env.clone()) {
+            Some(auction) => auction,
+            None => return env.storage().instance().get(&DataKey::KeeperRewardBps).unwrap_or(0),
+        };
         
-        // Check conditions using TWAP
-        if twap_price <= order.stop_price {
-            should_execute = true;
-            log!(&env, "TWAP stop triggered: {} <= {}", twap_price, order.stop_price);
-        }
+        let now = env.ledger().timestamp();
+        let eligible_since: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::EligibleSince(order_id))
+            .unwrap_or(now);
+        let elapsed = now.saturating_sub(eligible_since).min(auction.decay_seconds);
         
-        if let Some(take_profit) = order.take_profit_price {
-            if twap_price >= take_profit {
-                should_execute = true;
-                log!(&env, "TWAP take-profit triggered: {} >= {}", twap_price, take_profit);
-            }
+        let decay = (auction.max_reward_bps - auction.min_reward_bps) as u64 * elapsed / auction.decay_seconds;
+        auction.max_reward_bps - decay as u32
+    }
+    
+    // Oracles tried in order, after the primary, when it has no fresh spot
+    // price for an asset. An empty list disables the fallback.
+    pub fn set_fallback_oracles(env: Env, admin: Address, oracles: Vec<Address>) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
-        if should_execute {
-            Self::execute_order(&env, order_id, twap_price);
-            true
-        } else {
-            false
+        if oracles.len() > MAX_FALLBACK_ORACLES {
+            panic!("Too many fallback oracles");
         }
+        env.storage().instance().set(&DataKey::FallbackOracles, &oracles);
     }
     
-    // NEW: Create cross-asset stop order (e.g., stop BTC position if ETH crashes)
-    pub fn create_cross_asset_stop(
-        env: Env,
-        owner: Address,
-        position_asset: Symbol,
-        trigger_asset: Symbol,
-        amount: i128,
-        trigger_price: i128,
-    ) -> u64 {
-        owner.require_auth();
-        
-        if amount < MIN_ORDER_AMOUNT {
-            panic!("Amount too small");
+    pub fn get_fallback_oracles(env: Env) -> Vec<Address> {
+        env.storage()
+            .instance()
+            .get(&DataKey::FallbackOracles)
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+    
+    // Cap the amount sold per execution. Larger orders fill one chunk per
+    // check, staying Active until fully sold. None fills orders in one shot.
+    pub fn set_max_fill_size(env: Env, admin: Address, max_fill_size: Option<i128>) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
         }
         
+        match max_fill_size {
+            Some(max_fill_size) => {
+                if max_fill_size <= 0 {
+                    panic!("Invalid fill size");
+                }
+                env.storage().instance().set(&DataKey::MaxFillSize, &max_fill_size);
+            },
+            None => env.storage().instance().remove(&DataKey::MaxFillSize),
+        }
+    }
+    
+    pub fn get_max_fill_size(env: Env) -> Option<i128> {
+        env.storage().instance().get(&DataKey::MaxFillSize)
+    }
+    
+    // A trailing order left unchecked for longer than `gap_seconds` may have
+    // missed a high; its next check first ratchets against the recent TWAP.
+    // 0 disables the refresh.
+    pub fn set_anchor_refresh_gap(env: Env, admin: Address, gap_seconds: u64) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        env.storage().instance().set(&DataKey::AnchorRefreshGap, &gap_seconds);
+        
+        log!(&env, "Anchor refresh gap set: {}s", gap_seconds);
+    }
+    
+    pub fn get_anchor_refresh_gap(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::AnchorRefreshGap).unwrap_or(0)
+    }
+    
+    // Replace the whole config, so a deployment can tune its economics
+    // without a redeploy
+    pub fn update_config(env: Env, admin: Address, config: Config) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        Self::validate_staleness(config.staleness_seconds);
+        if config.execution_fee_bps > MAX_EXECUTION_FEE_BPS {
+            panic!("Invalid execution fee");
+        }
+        if config.creation_fee < 0 || (config.creation_fee > 0 && config.fee_token.is_none()) {
+            panic!("Invalid creation fee");
+        }
+        if config.min_order_amount <= 0 || config.min_order_amount > MAX_SANE_AMOUNT {
+            panic!("Invalid min order amount");
+        }
+        if config.max_orders_per_user == 0 || config.max_orders_per_user > MAX_ORDERS_PER_USER_CAP {
+            panic!("Invalid max orders per user");
+        }
+        
+        env.storage().instance().set(&DataKey::Config, &config);
+        
+        log!(&env, "Config updated: {}bps fee, {} min amount, {} max orders",
+             config.execution_fee_bps, config.min_order_amount, config.max_orders_per_user);
+    }
+    
+    // Choose between a percentage fee at execution, a flat fee at creation,
+    // or both. Set execution_fee_bps to 0 to charge only on creation.
+    pub fn set_fee_config(
+        env: Env,
+        admin: Address,
+        execution_fee_bps: u32,
+        creation_fee: i128,
+        fee_token: Option<Address>,
+    ) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if execution_fee_bps > MAX_EXECUTION_FEE_BPS {
+            panic!("Invalid execution fee");
+        }
+        if creation_fee < 0 || (creation_fee > 0 && fee_token.is_none()) {
+            panic!("Invalid creation fee");
+        }
+        
+        let mut config = Self::get_config(env.clone());
+        config.execution_fee_bps = execution_fee_bps;
+        config.creation_fee = creation_fee;
+        config.fee_token = fee_token;
+        env.storage().instance().set(&DataKey::Config, &config);
+        
+        log!(&env, "Fees set: {}bps on execution, {} on creation", execution_fee_bps, creation_fee);
+    }
+    
+    // Share protocol fees among several recipients (treasury, insurance,
+    // ...). Weights are in basis points and must sum to 10000.
+    pub fn set_fee_recipients(env: Env, admin: Address, recipients: Vec<(Address, u32)>) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        if recipients.is_empty() || recipients.len() > MAX_FEE_RECIPIENTS {
+            panic!("Invalid fee recipients");
+        }
+        
+        let mut total_weight = 0u32;
+        for (_, weight_bps) in recipients.iter() {
+            if weight_bps == 0 {
+                panic!("Invalid fee recipients");
+            }
+            total_weight += weight_bps;
+        }
+        if total_weight != 10000 {
+            panic!("Fee weights must sum to 10000");
+        }
+        
+        env.storage().instance().set(&DataKey::FeeRecipients, &recipients);
+        
+        log!(&env, "Fee recipients set: {}", recipients.len());
+    }
+    
+    pub fn get_fee_recipients(env: Env) -> Vec<(Address, u32)> {
+        env.storage().instance().get(&DataKey::FeeRecipients).unwrap()
+    }
+    
+    pub fn get_creation_fees_collected(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::CreationFeesCollected).unwrap_or(0)
+    }
+    
+    // Execution fees collected in `token` and not yet claimed
+    pub fn get_accrued_fees(env: Env, token: Address) -> i128 {
+        env.storage().persistent().get(&DataKey::AccruedFees(token)).unwrap_or(0)
+    }
+    
+    // Pay out the execution fees accrued in `token`, split across the fee
+    // recipients by their current weights. Needs the auth of the primary
+    // (first) recipient. The balance is zeroed before the transfers.
+    pub fn claim_fees(env: Env, token: Address) -> i128 {
+        let recipients = Self::get_fee_recipients(env.clone());
+        recipients.get(0).unwrap().0.require_auth();
+        
+        let amount = Self::get_accrued_fees(env.clone(), token.clone());
+        if amount == 0 {
+            panic!("No fees to claim");
+        }
+        env.storage().persistent().remove(&DataKey::AccruedFees(token.clone()));
+        
+        let contract_address = env.current_contract_address();
+        let token_client = token::Client::new(&env, &token);
+        for (fee_recipient, share) in Self::fee_shares(&env, amount) {
+            token_client.transfer(&contract_address, &fee_recipient, &share);
+        }
+        
+        log!(&env, "Claimed {} in fees", amount);
+        amount
+    }
+    
+    pub fn get_config(env: Env) -> Config {
+        env.storage()
+            .instance()
+            .get(&DataKey::Config)
+            .unwrap_or_else(|| panic!("Not initialized"))
+    }
+    
+    // Seconds since the oracle last published a price for `asset`, so
+    // frontends can warn before creating an order against stale data
+    pub fn get_price_age(env: Env, asset: AssetRef) -> u64 {
+        let price_info = Self::get_last_price(&env, &asset);
+        env.ledger().timestamp().saturating_sub(price_info.timestamp)
+    }
+    
+    // The TWAP create_twap_stop would anchor to, so users can preview it.
+    // None when the oracle has no TWAP or its latest price for `asset` is
+    // outside the staleness window, as the average would include stale data.
+    pub fn get_twap(env: Env, asset: AssetRef, periods: u32) -> Option<i128> {
+        let oracle_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
+        
+        let client = ReflectorClient::new(&env, &oracle_address);
+        let asset_type = Self::to_oracle_asset(&asset);
+        
+        if let Some(price_info) = client.lastprice(&asset_type) {
+            let config = Self::get_config(env.clone());
+            if env.ledger().timestamp().saturating_sub(price_info.timestamp) > config.staleness_seconds {
+                return None;
+            }
+        }
+        
+        client.twap(&asset_type, &periods).map(|twap| Self::scale_price(&client, twap))
+    }
+    
+    // Override the oracle's last price for `asset`, for end-to-end testing on
+    // staging deployments. Only builds with the `sandbox` feature accept
+    // or read mock prices; never enable it for mainnet.
+    pub fn set_mock_price(env: Env, admin: Address, asset: AssetRef, price: i128, timestamp: u64) {
+        if !cfg!(feature = "sandbox") {
+            panic!("Sandbox mode unavailable");
+        }
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let key = DataKey::MockPrice(asset);
+        env.storage().persistent().set(&key, &PriceData { price, timestamp });
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    // Halt order creation and execution, e.g. during an oracle incident.
+    // Cancellation stays available so users can exit.
+    pub fn pause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, true);
+    }
+    
+    pub fn unpause(env: Env, admin: Address) {
+        pausable::set_paused(&env, &admin, false);
+    }
+    
+    pub fn is_paused(env: Env) -> bool {
+        pausable::is_paused(&env)
+    }
+    
+    // Halt creation and execution of orders on one asset, leaving the rest
+    // of the contract running. Cancellation stays available.
+    pub fn set_asset_paused(env: Env, admin: Address, asset: AssetRef, paused: bool) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        
+        let key = DataKey::AssetPaused(asset.clone());
+        if paused {
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+        
+        AssetPauseChanged { asset, paused }.publish(&env);
+    }
+    
+    pub fn is_asset_paused(env: Env, asset: AssetRef) -> bool {
+        env.storage().persistent().has(&DataKey::AssetPaused(asset))
+    }
+    
+    pub fn version(_env: Env) -> u32 {
+        CONTRACT_VERSION
+    }
+    
+    // Swap in new contract code uploaded under `new_wasm_hash`, keeping the
+    // contract id and all state. Bump CONTRACT_VERSION with every release.
+    pub fn upgrade(env: Env, admin: Address, new_wasm_hash: BytesN<32>) {
+        upgradeable::upgrade(&env, &admin, CONTRACT_VERSION, new_wasm_hash);
+    }
+    
+    // Crate name and version the deployed code was built from
+    pub fn build_info(env: Env) -> String {
+        String::from_str(&env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    }
+    
+    pub fn get_admin(env: Env) -> Address {
+        ownable::get_admin(&env)
+    }
+    
+    pub fn is_admin(env: Env, who: Address) -> bool {
+        ownable::is_admin(&env, &who)
+    }
+    
+    // Nominate a new admin; takes effect once they call accept_admin
+    pub fn propose_admin(env: Env, current_admin: Address, new_admin: Address) {
+        ownable::propose_admin(&env, &current_admin, &new_admin);
+    }
+    
+    pub fn accept_admin(env: Env, new_admin: Address) {
+        ownable::accept_admin(&env, &new_admin);
+        log!(&env, "Admin transferred to {}", new_admin);
+    }
+    
+    // Address nominated by propose_admin and not yet accepted
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        ownable::get_pending_admin(&env)
+    }
+    
+    pub fn create_stop_loss(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        let order = Self::new_stop_loss_order(&env, &owner, asset, amount, stop_price);
+        let order_id = Self::get_next_order_id(&env);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    // Stop-loss that, once triggered, only executes while the price is at or
+    // above `limit_price`, so a crash can't fill it at any price
+    pub fn create_stop_limit(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+        limit_price: i128,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        if limit_price <= 0 || limit_price > stop_price {
+            panic!("Invalid limit price");
+        }
+        
+        let mut order = Self::new_stop_loss_order(&env, &owner, asset, amount, stop_price);
+        order.limit_price = Some(limit_price);
+        let order_id = Self::get_next_order_id(&env);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    // Stop-loss that always fills at `stop_price`, even if the market gaps
+    // through it, for `premium_bps` added to the protocol fee on execution.
+    // If the DEX can't pay the stop value the reserve buys at the stop. The
+    // protocol's exposure is tracked per asset in get_guaranteed_liability.
+    pub fn create_guaranteed_stop(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+        premium_bps: u32,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        if premium_bps == 0 || premium_bps > MAX_GUARANTEE_PREMIUM_BPS {
+            panic!("Invalid premium");
+        }
+        
+        let order = Self::new_stop_loss_order(&env, &owner, asset, amount, stop_price);
+        let order_id = Self::get_next_order_id(&env);
+        
+        Self::save_order(&env, order_id, &order);
+        let key = DataKey::GuaranteePremium(order_id);
+        env.storage().persistent().set(&key, &premium_bps);
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        Self::reserve_guarantee(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    // Value the protocol has guaranteed to pay for open guaranteed stops on
+    // `asset`: the sum of amount * stop_price, in the reserve token
+    pub fn get_guaranteed_liability(env: Env, asset: AssetRef) -> i128 {
+        env.storage().persistent().get(&DataKey::GuaranteedLiability(asset)).unwrap_or(0)
+    }
+    
+    // Stop-loss that expires at the given ledger timestamp if not triggered
+    pub fn create_stop_loss_with_expiry(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+        expires_at: u64,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        if expires_at <= env.ledger().timestamp() {
+            panic!("Invalid expiry");
+        }
+        
+        let mut order = Self::new_stop_loss_order(&env, &owner, asset, amount, stop_price);
+        order.expires_at = Some(expires_at);
+        let order_id = Self::get_next_order_id(&env);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    pub fn create_trailing_stop(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        trailing_percent: u32,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        let order = Self::new_trailing_stop_order(&env, &owner, asset, amount, trailing_percent);
+        let order_id = Self::get_next_order_id(&env);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    pub fn create_oco_order(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+        take_profit_price: i128,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        Self::validate_amount(&env, amount);
+        
+        let order_id = Self::get_next_order_id(&env);
+        let current_price = Self::get_current_price(&env, &asset);
+        
+        if stop_price >= current_price || take_profit_price <= current_price {
+            panic!("Invalid price levels");
+        }
+        
+        let order = StopLossOrder {
+            take_profit_price: Some(take_profit_price),
+            ..Self::new_order(&env, &owner, asset, amount, stop_price, current_price)
+        };
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    // Take profit in stages. `levels` are (target price, amount) pairs in
+    // ascending price order whose amounts sum to `total_amount`. Each portion
+    // is sold once its target is reached; the order stays Active until the
+    // last level fills.
+    pub fn create_scaled_take_profit(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        total_amount: i128,
+        levels: Vec<(i128, i128)>,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        if levels.is_empty() || levels.len() > MAX_SCALE_LEVELS {
+            panic!("Invalid number of levels");
+        }
+        
+        let mut level_sum: i128 = 0;
+        let mut last_price: i128 = 0;
+        for (price, amount) in levels.iter() {
+            if price <= last_price {
+                panic!("Levels must be in ascending price order");
+            }
+            Self::validate_amount(&env, amount);
+            level_sum += amount;
+            last_price = price;
+        }
+        
+        if level_sum != total_amount {
+            panic!("Level amounts must sum to total");
+        }
+        
+        let (first_price, _) = levels.get_unchecked(0);
         let order_id = Self::get_next_order_id(&env);
+        let order = Self::new_take_profit_order(&env, &owner, asset, total_amount, first_price);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        env.storage().persistent().set(&DataKey::ScaleLevels(order_id), &levels);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        order_id
+    }
+    
+    // Unfilled (price, amount) levels of a scaled take-profit order
+    pub fn get_scale_levels(env: Env, order_id: u64) -> Vec<(i128, i128)> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ScaleLevels(order_id))
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+    
+    // Create several orders in one call; the whole batch is rejected if it
+    // would push the owner past the max orders per user
+    pub fn create_batch_orders(env: Env, owner: Address, orders: Vec<OrderParams>) -> Vec<u64> {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        
+        if orders.is_empty() {
+            panic!("Empty batch");
+        }
+        
+        let existing_orders = Self::get_user_orders(env.clone(), owner.clone());
+        if existing_orders.len() + orders.len() > Self::get_config(env.clone()).max_orders_per_user {
+            panic!("Max orders per user exceeded");
+        }
+        
+        let mut order_ids = Vec::new(&env);
+        for params in orders.iter() {
+            let order = Self::new_order_from_params(&env, &owner, params);
+            
+            let order_id = Self::get_next_order_id(&env);
+            Self::save_order(&env, order_id, &order);
+            Self::add_user_order(&env, &owner, order_id);
+            Self::publish_order_created(&env, order_id, &order);
+            order_ids.push_back(order_id);
+        }
+        
+        Self::collect_creation_fee(&env, &owner, order_ids.len());
+        
+        log!(&env, "Batch of {} orders created", order_ids.len());
+        order_ids
+    }
+    
+    // Anyone may call this; `keeper` is credited the keeper reward if the
+    // order executes. Returns whether the order was filled or closed by its
+    // trigger; check_and_execute_detailed says which.
+    pub fn check_and_execute(env: Env, keeper: Address, order_id: u64) -> bool {
+        Self::acted_on(&Self::check_and_execute_detailed(env, keeper, order_id))
+    }
+    
+    // check_and_execute, reporting whether the order was inactive, left
+    // untouched, had its trailing stop moved, or was filled
+    pub fn check_and_execute_detailed(env: Env, keeper: Address, order_id: u64) -> CheckResult {
+        Self::check_order(&env, &keeper, order_id, None)
+    }
+    
+    // NEW: Create TWAP-based stop loss for more stable execution
+    pub fn create_twap_stop(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        twap_periods: u32,
+        stop_percentage: u32,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        Self::validate_amount(&env, amount);
+        
+        if !(3..=20).contains(&twap_periods) {
+            panic!("TWAP periods must be between 3 and 20");
+        }
+        
+        let order_id = Self::get_next_order_id(&env);
+        
+        // Get TWAP price instead of spot price
+        let twap_price = Self::get_twap_price(&env, &asset, twap_periods);
+        let stop_price = twap_price * (100 - stop_percentage as i128) / 100;
+        
+        let order = Self::new_order(&env, &owner, asset, amount, stop_price, twap_price);
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        
+        order_id
+    }
+    
+    // check_and_execute against the asset's TWAP over `twap_periods` instead
+    // of the spot price. Every other protection applies as usual.
+    pub fn check_and_execute_twap(env: Env, keeper: Address, order_id: u64, twap_periods: u32) -> bool {
+        Self::acted_on(&Self::check_order(&env, &keeper, order_id, Some(twap_periods)))
+    }
+    
+    // NEW: Create cross-asset stop order (e.g., stop BTC position if ETH crashes)
+    pub fn create_cross_asset_stop(
+        env: Env,
+        owner: Address,
+        position_asset: AssetRef,
+        amount: i128,
+        trigger_price: i128,
+        cross_trigger: CrossTrigger,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &position_asset);
+        Self::when_asset_not_paused(&env, &cross_trigger.trigger_asset);
+        
+        Self::validate_amount(&env, amount);
+        
+        if let Some(periods) = cross_trigger.twap_periods {
+            if !(3..=20).contains(&periods) {
+                panic!("TWAP periods must be between 3 and 20");
+            }
+        }
+        
+        let order_id = Self::get_next_order_id(&env);
+        
+        // Get cross price ratio
+        let cross_price = Self::get_cross_trigger_price(&env, &cross_trigger, &position_asset);
+        
+        // The stop price is the cross price that triggers the order
+        let order = StopLossOrder {
+            trigger_source: TriggerSource::Cross(cross_trigger),
+            ..Self::new_order(&env, &owner, position_asset, amount, trigger_price, cross_price)
+        };
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        
+        order_id
+    }
+    
+    // Stop that trails `drop_bps` below the rolling high of the last
+    // `lookback_periods` oracle prices
+    pub fn create_chandelier_stop(
+        env: Env,
+        owner: Address,
+        asset: AssetRef,
+        amount: i128,
+        lookback_periods: u32,
+        drop_bps: u32,
+    ) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        Self::when_asset_not_paused(&env, &asset);
+        
+        Self::validate_amount(&env, amount);
+        
+        if lookback_periods == 0 || lookback_periods > MAX_CHANDELIER_LOOKBACK {
+            panic!("Invalid lookback periods");
+        }
+        if drop_bps == 0 || drop_bps >= 10000 {
+            panic!("Invalid drop");
+        }
+        
+        let current_price = Self::get_current_price(&env, &asset);
+        let rolling_high = Self::get_rolling_high(&env, &asset, lookback_periods)
+            .unwrap_or(current_price)
+            .max(current_price);
+        let stop_price = Self::chandelier_stop_for(rolling_high, drop_bps);
+        let order_id = Self::get_next_order_id(&env);
+        
+        let order = StopLossOrder {
+            trigger_source: TriggerSource::Chandelier(ChandelierTrigger { lookback_periods, drop_bps }),
+            ..Self::new_order(&env, &owner, asset, amount, stop_price, rolling_high)
+        };
+        
+        Self::save_order(&env, order_id, &order);
+        Self::add_user_order(&env, &owner, order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, order_id, &order);
+        
+        order_id
+    }
+    
+    // NEW: Get historical price volatility for risk assessment. Returns the
+    // standard deviation over `periods`, in price units like stop_price.
+    pub fn get_price_volatility(env: Env, asset: AssetRef, periods: u32) -> i128 {
+        let volatility = match Self::get_price_dispersion(&env, &asset, periods) {
+            Some((std_dev, _)) => std_dev,
+            None => return 0,
+        };
+        
+        log!(&env, "Price volatility over {} periods: {}", periods, volatility);
+        
+        volatility
+    }
+    
+    // Mark an active order past its expiry as expired. Callable by anyone so
+    // keepers can clean up stale orders; returns whether the order was expired.
+    pub fn purge_expired(env: Env, order_id: u64) -> bool {
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.status != OrderStatus::Active {
+            return false;
+        }
+        
+        Self::expire_if_due(&env, order_id, &mut order)
+    }
+    
+    pub fn cancel_order(env: Env, owner: Address, order_id: u64) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        if order.status != OrderStatus::Active {
+            panic!("Order not active");
+        }
+        
+        Self::close_cancelled(&env, order_id, &mut order);
+    }
+    
+    // Change an active order's stop price and/or amount in place, keeping its
+    // id. A new stop must be below the current price; on a trailing order it
+    // also re-anchors the trail at the current price.
+    pub fn modify_order(
+        env: Env,
+        owner: Address,
+        order_id: u64,
+        new_stop_price: Option<i128>,
+        new_amount: Option<i128>,
+    ) {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        if order.status != OrderStatus::Active {
+            panic!("Order not active");
+        }
+        Self::when_order_assets_not_paused(&env, &order);
+        Self::release_guarantee(&env, order_id, &order);
+        
+        if let Some(amount) = new_amount {
+            Self::validate_amount(&env, amount);
+            // Scale levels must keep summing to the order amount
+            if env.storage().persistent().has(&DataKey::ScaleLevels(order_id)) {
+                panic!("Cannot resize scaled order");
+            }
+            order.amount = amount;
+        }
+        
+        if let Some(stop_price) = new_stop_price {
+            if order.trigger_source != TriggerSource::OrderAsset {
+                panic!("Stop not modifiable");
+            }
+            
+            let current_price = Self::get_current_price(&env, &order.asset);
+            if stop_price <= 0 || stop_price >= current_price {
+                panic!("Stop price must be below market");
+            }
+            if order.limit_price.is_some_and(|limit_price| limit_price > stop_price) {
+                panic!("Invalid limit price");
+            }
+            
+            order.stop_price = stop_price;
+            if order.trailing_percent.is_some() {
+                order.highest_price = current_price;
+            }
+        }
+        
+        Self::save_order(&env, order_id, &order);
+        Self::reserve_guarantee(&env, order_id, &order);
+        Self::record_timeline(&env, order_id, OrderEventKind::Modified, order.stop_price, order.amount);
+        OrderModified {
+            order_id,
+            owner,
+            asset: order.asset,
+            stop_price: order.stop_price,
+            amount: order.amount,
+        }.publish(&env);
+    }
+    
+    // Push back an active order's expiry. Expiries only move later, and
+    // orders created without one stay open-ended.
+    pub fn extend_order_expiry(env: Env, owner: Address, order_id: u64, new_expires_at: u64) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        // A lapsed order awaiting purge_expired can't be revived
+        let now = env.ledger().timestamp();
+        if order.status != OrderStatus::Active || order.expires_at.is_some_and(|expires_at| now > expires_at) {
+            panic!("Order not active");
+        }
+        
+        match order.expires_at {
+            Some(expires_at) if new_expires_at > expires_at => {},
+            Some(_) => panic!("Expiry can only move forward"),
+            None => panic!("Order has no expiry"),
+        }
+        
+        order.expires_at = Some(new_expires_at);
+        Self::save_order(&env, order_id, &order);
+        
+        log!(&env, "Order {} now expires at {}", order_id, new_expires_at);
+    }
+    
+    // Cancel `order_id` and create a replacement from `new_params` in one
+    // call. If the new order cannot be created the whole call reverts and
+    // the original order stays active. Returns the new order id.
+    pub fn replace_order(env: Env, owner: Address, order_id: u64, new_params: OrderParams) -> u64 {
+        owner.require_auth();
+        pausable::when_not_paused(&env);
+        
+        let mut old_order = Self::get_order(&env, order_id);
+        
+        if old_order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        if old_order.status != OrderStatus::Active {
+            panic!("Order not active");
+        }
+        
+        Self::close_cancelled(&env, order_id, &mut old_order);
+        
+        let order = Self::new_order_from_params(&env, &owner, new_params);
+        let new_order_id = Self::get_next_order_id(&env);
+        Self::save_order(&env, new_order_id, &order);
+        Self::add_user_order(&env, &owner, new_order_id);
+        Self::collect_creation_fee(&env, &owner, 1);
+        
+        Self::publish_order_created(&env, new_order_id, &order);
+        
+        log!(&env, "Order {} replaced by {}", order_id, new_order_id);
+        new_order_id
+    }
+    
+    // Cancel the caller's active orders created before `cutoff_timestamp`.
+    // At most MAX_BULK_CANCEL orders are cancelled per call; call again until
+    // it returns 0.
+    pub fn cancel_orders_before(env: Env, owner: Address, cutoff_timestamp: u64) -> u32 {
+        owner.require_auth();
+        
+        let mut cancelled = 0u32;
+        for order_id in Self::get_user_orders(env.clone(), owner.clone()).iter() {
+            if cancelled >= MAX_BULK_CANCEL {
+                break;
+            }
+            
+            let mut order = Self::get_order(&env, order_id);
+            if order.status == OrderStatus::Active && order.created_at < cutoff_timestamp {
+                Self::close_cancelled(&env, order_id, &mut order);
+                cancelled += 1;
+            }
+        }
+        
+        log!(&env, "Cancelled {} orders created before {}", cancelled, cutoff_timestamp);
+        cancelled
+    }
+    
+    // Accrued keeper rewards per token
+    pub fn get_keeper_rewards(env: Env, keeper: Address) -> Map<Address, i128> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::KeeperRewards(keeper))
+            .unwrap_or_else(|| Map::new(&env))
+    }
+    
+    // Withdraw a keeper's accrued rewards in `token`. The balance is zeroed
+    // before the transfer.
+    pub fn claim_keeper_rewards(env: Env, keeper: Address, token: Address) -> i128 {
+        keeper.require_auth();
+        
+        let key = DataKey::KeeperRewards(keeper.clone());
+        let mut rewards: Map<Address, i128> = env.storage()
+            .persistent()
+            .get(&key)
+            .unwrap_or_else(|| Map::new(&env));
+        
+        let amount = rewards.get(token.clone()).unwrap_or(0);
+        if amount == 0 {
+            panic!("No rewards to claim");
+        }
+        
+        rewards.remove(token.clone());
+        env.storage().persistent().set(&key, &rewards);
+        
+        token::Client::new(&env, &token).transfer(&env.current_contract_address(), &keeper, &amount);
+        
+        log!(&env, "Keeper {} claimed {} rewards", keeper, amount);
+        amount
+    }
+    
+    // Set or clear the order's price confidence threshold: the max standard
+    // deviation of the last CONFIDENCE_SAMPLES prices, in bps of their mean,
+    // at which the order may execute
+    pub fn set_order_max_confidence(
+        env: Env,
+        owner: Address,
+        order_id: u64,
+        max_confidence_bps: Option<u32>,
+    ) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        if let Some(bps) = max_confidence_bps {
+            if bps == 0 || bps > 10000 {
+                panic!("Invalid confidence threshold");
+            }
+        }
+        
+        order.max_confidence_bps = max_confidence_bps;
+        Self::save_order(&env, order_id, &order);
+    }
+    
+    // Set or clear the order's fill-or-kill floor. A triggered order whose
+    // fill price would be below the floor is cancelled instead of filled.
+    pub fn set_fill_or_kill_floor(env: Env, owner: Address, order_id: u64, floor: Option<i128>) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        if order.status != OrderStatus::Active {
+            panic!("Order not active");
+        }
+        if floor.is_some_and(|floor| floor <= 0) {
+            panic!("Invalid floor price");
+        }
+        
+        order.fill_or_kill_floor = floor;
+        Self::save_order(&env, order_id, &order);
+    }
+    
+    // Require a new high to hold for `confirmations` checks at distinct
+    // ledger timestamps before a trailing order's stop ratchets to it, so a
+    // single-block spike can't raise the stop. None ratchets on every high.
+    pub fn set_high_confirmations(env: Env, owner: Address, order_id: u64, confirmations: Option<u32>) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        if order.trailing_percent.is_none() {
+            panic!("Not a trailing stop");
+        }
+        if confirmations.is_some_and(|confirmations| confirmations == 0 || confirmations > MAX_HIGH_CONFIRMATIONS) {
+            panic!("Invalid confirmations");
+        }
+        
+        order.high_confirmations = confirmations;
+        order.high_checks = 0;
+        Self::save_order(&env, order_id, &order);
+    }
+    
+    // Set or clear the human-readable label on an order
+    pub fn set_order_memo(env: Env, owner: Address, order_id: u64, memo: Option<String>) {
+        owner.require_auth();
+        
+        let mut order = Self::get_order(&env, order_id);
+        
+        if order.owner != owner {
+            panic!("Unauthorized");
+        }
+        
+        Self::validate_memo(&memo);
+        order.memo = memo;
+        Self::save_order(&env, order_id, &order);
+    }
+    
+    pub fn get_user_orders(env: Env, user: Address) -> Vec<u64> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserOrders(user))
+            .unwrap_or(Vec::new(&env))
+    }
+    
+    // The user's active orders expiring in the next `window` seconds, so
+    // they can be renewed. Scans at most the max orders per user.
+    pub fn get_orders_expiring_within(env: Env, user: Address, window: u64) -> Vec<u64> {
+        let now = env.ledger().timestamp();
+        let deadline = now.saturating_add(window);
+        
+        let mut expiring = Vec::new(&env);
+        for order_id in Self::get_user_orders(env.clone(), user).iter() {
+            let order = Self::get_order(&env, order_id);
+            if order.status != OrderStatus::Active {
+                continue;
+            }
+            if order.expires_at.is_some_and(|expires_at| expires_at >= now && expires_at <= deadline) {
+                expiring.push_back(order_id);
+            }
+        }
+        expiring
+    }
+    
+    // Full details of the user's active orders among their order ids
+    // `start`..`start + limit`, so a page stays within resource limits
+    pub fn get_active_orders_detailed(env: Env, user: Address, start: u32, limit: u32) -> Vec<StopLossOrder> {
+        let user_orders = Self::get_user_orders(env.clone(), user);
+        let end = start.saturating_add(limit).min(user_orders.len());
+        
+        let mut result = Vec::new(&env);
+        for i in start.min(end)..end {
+            let order = Self::get_order(&env, user_orders.get(i).unwrap());
+            if order.status == OrderStatus::Active {
+                result.push_back(order);
+            }
+        }
+        result
+    }
+    
+    // The stop a trailing order would have at the current price, applying the
+    // same ratchet-only rule as check_and_execute but without saving it
+    pub fn get_current_trailing_stop(env: Env, order_id: u64) -> i128 {
+        let order = Self::get_order(&env, order_id);
+        let trailing_percent = match order.trailing_percent {
+            Some(trailing_percent) => trailing_percent,
+            None => panic!("Not a trailing stop"),
+        };
+        
+        // A high awaiting confirmation doesn't move the stop yet
+        let current_price = Self::get_current_price(&env, &order.asset);
+        if current_price > order.highest_price && order.high_confirmations.is_none() {
+            order.stop_price.max(Self::trailing_stop_for(current_price, trailing_percent))
+        } else {
+            order.stop_price
+        }
+    }
+    
+    // The stop and take-profit an OCO order will close at and their distance
+    // from the current price, with the stop at its live value if the order
+    // trails
+    pub fn get_oco_levels(env: Env, order_id: u64) -> OcoLevels {
+        let order = Self::get_order(&env, order_id);
+        let take_profit_price = match order.take_profit_price {
+            Some(take_profit_price) if order.stop_price > 0 => take_profit_price,
+            _ => panic!("Not an OCO order"),
+        };
+        
+        let current_price = Self::get_current_price(&env, &order.asset);
+        let stop_price = if order.trailing_percent.is_some() {
+            Self::get_current_trailing_stop(env, order_id)
+        } else {
+            order.stop_price
+        };
+        
+        OcoLevels {
+            stop_price,
+            take_profit_price,
+            stop_distance_bps: (current_price - stop_price) * 10000 / current_price,
+            take_profit_distance_bps: (take_profit_price - current_price) * 10000 / current_price,
+        }
+    }
+    
+    pub fn get_order_details(env: Env, order_id: u64) -> StopLossOrder {
+        Self::get_order(&env, order_id)
+    }
+    
+    // Lifecycle of an order, oldest first. Long-lived orders keep their
+    // creation entry and the most recent MAX_TIMELINE_EVENTS - 1 others.
+    pub fn get_order_timeline(env: Env, order_id: u64) -> Vec<OrderEvent> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::OrderTimeline(order_id))
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+    
+    // NEW: Get all order IDs
+    pub fn get_all_orders(env: Env) -> Vec<u64> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or(Vec::new(&env))
+    }
+    
+    // NEW: Get total order count
+    pub fn get_order_count(env: Env) -> u64 {
+        let all_orders: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or(Vec::new(&env));
+        all_orders.len() as u64
+    }
+    
+    // Orders created since deployment (or the last counter reset)
+    pub fn get_total_orders(env: Env) -> u64 {
+        Self::get_order_counter(env)
+    }
+    
+    // Orders currently Active, kept as a running count rather than scanned
+    pub fn get_active_order_count(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::ActiveOrderCount).unwrap_or(0)
+    }
+    
+    // Orders executed since deployment
+    pub fn get_executed_count(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::ExecutedOrderCount).unwrap_or(0)
+    }
+    
+    // Last order id handed out; the next order gets this plus one
+    pub fn get_order_counter(env: Env) -> u64 {
+        env.storage().persistent().get(&DataKey::OrderCounter).unwrap_or(0)
+    }
+    
+    // Restart order ids at 1, for test and staging deployments. Only allowed
+    // while no orders exist, so ids are never reused.
+    pub fn reset_order_counter(env: Env, admin: Address) {
+        if ownable::require_admin(&env) != admin {
+            panic!("Unauthorized");
+        }
+        if Self::get_order_count(env.clone()) > 0 {
+            panic!("Orders already exist");
+        }
+        
+        env.storage().persistent().set(&DataKey::OrderCounter, &0u64);
+    }
+    
+    // NEW: Get orders with pagination
+    pub fn get_orders_paginated(env: Env, start: u64, limit: u32) -> Vec<StopLossOrder> {
+        let all_order_ids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or(Vec::new(&env));
+        
+        let mut result = Vec::new(&env);
+        let end = ((start + limit as u64) as usize).min(all_order_ids.len() as usize);
+        let start_idx = (start as usize).min(all_order_ids.len() as usize);
+        
+        for i in start_idx..end {
+            if let Some(order_id) = all_order_ids.get(i as u32) {
+                let order = Self::get_order(&env, order_id);
+                result.push_back(order);
+            }
+        }
+        
+        result
+    }
+    
+    // NEW: Get only active orders
+    pub fn get_active_orders(env: Env) -> Vec<u64> {
+        let all_order_ids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or(Vec::new(&env));
+        
+        let mut active_orders = Vec::new(&env);
+        for order_id in all_order_ids.iter() {
+            let order = Self::get_order(&env, order_id);
+            if order.status == OrderStatus::Active {
+                active_orders.push_back(order_id);
+            }
+        }
+        
+        active_orders
+    }
+    
+    // NEW: Get orders by status
+    pub fn get_orders_by_status(env: Env, status: OrderStatus) -> Vec<u64> {
+        let all_order_ids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or(Vec::new(&env));
+        
+        let mut filtered_orders = Vec::new(&env);
+        for order_id in all_order_ids.iter() {
+            let order = Self::get_order(&env, order_id);
+            if order.status == status {
+                filtered_orders.push_back(order_id);
+            }
+        }
         
-        // Get cross price ratio
-        let cross_price = Self::get_cross_price(&env, &trigger_asset, &position_asset);
+        filtered_orders
+    }
+    
+    // Notional of active orders on an asset that are close to triggering.
+    // Each order within VAR_TRIGGER_BAND_BPS of its stop or take-profit level
+    // contributes amount * price, weighted linearly from 0 at the edge of the
+    // band to 1 at the trigger. Only the newest MAX_VAR_SCAN orders are scanned.
+    pub fn get_protocol_var(env: Env, asset: AssetRef) -> i128 {
+        let all_order_ids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::AllOrderIds)
+            .unwrap_or_else(|| Vec::new(&env));
         
-        let order = StopLossOrder {
+        let current_price = Self::get_current_price(&env, &asset);
+        let scan_start = all_order_ids.len().saturating_sub(MAX_VAR_SCAN);
+        let mut value_at_risk = 0i128;
+        
+        for i in scan_start..all_order_ids.len() {
+            let order = Self::get_order(&env, all_order_ids.get(i).unwrap());
+            if order.status != OrderStatus::Active || order.asset != asset {
+                continue;
+            }
+            
+            // Distance to the nearest trigger in basis points of the current price
+            let mut distance_bps = (current_price - order.stop_price) * 10000 / current_price;
+            if let Some(take_profit) = order.take_profit_price {
+                distance_bps = distance_bps.min((take_profit - current_price) * 10000 / current_price);
+            }
+            
+            if distance_bps >= VAR_TRIGGER_BAND_BPS {
+                continue;
+            }
+            
+            let weight_bps = VAR_TRIGGER_BAND_BPS - distance_bps.max(0);
+            value_at_risk += order.amount * current_price * weight_bps / VAR_TRIGGER_BAND_BPS;
+        }
+        
+        value_at_risk
+    }
+    
+    // Internal helper functions
+    fn validate_staleness(staleness_seconds: u64) {
+        if staleness_seconds == 0 {
+            panic!("Invalid staleness window");
+        }
+    }
+    
+    fn validate_memo(memo: &Option<String>) {
+        if let Some(memo) = memo {
+            if memo.len() > MAX_MEMO_LENGTH {
+                panic!("Memo too long");
+            }
+        }
+    }
+    
+    // Shared by every order creation path. Both bounds are inclusive:
+    // the configured minimum and MAX_SANE_AMOUNT themselves are accepted.
+    fn validate_amount(env: &Env, amount: i128) {
+        if amount < Self::get_config(env.clone()).min_order_amount {
+            panic!("Amount too small");
+        }
+        
+        if amount > MAX_SANE_AMOUNT {
+            panic!("Amount too large");
+        }
+    }
+    
+    // Build an order from batch-style params. OCO orders need two prices and
+    // are not expressible here.
+    fn new_order_from_params(env: &Env, owner: &Address, params: OrderParams) -> StopLossOrder {
+        Self::when_asset_not_paused(env, &params.asset);
+        Self::validate_memo(&params.memo);
+        let mut order = match params.order_type {
+            OrderType::StopLoss => Self::new_stop_loss_order(
+                env, owner, params.asset, params.amount, params.stop_price,
+            ),
+            OrderType::TrailingStop => {
+                if params.stop_price <= 0 || params.stop_price > 50 {
+                    panic!("Invalid trailing percent");
+                }
+                Self::new_trailing_stop_order(
+                    env, owner, params.asset, params.amount, params.stop_price as u32,
+                )
+            },
+            OrderType::TakeProfit => Self::new_take_profit_order(
+                env, owner, params.asset, params.amount, params.stop_price,
+            ),
+            OrderType::OCO => panic!("OCO orders not supported in batch"),
+        };
+        order.memo = params.memo;
+        order
+    }
+    
+    // Fresh Active order with nothing filled and no optional behaviour set.
+    // Order types override the fields they use.
+    fn new_order(
+        env: &Env,
+        owner: &Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+        highest_price: i128,
+    ) -> StopLossOrder {
+        StopLossOrder {
             owner: owner.clone(),
-            asset: position_asset,
+            asset,
             amount,
-            stop_price: trigger_price, // This represents the trigger asset price
+            stop_price,
             trailing_percent: None,
-            highest_price: cross_price,
+            highest_price,
             take_profit_price: None,
             created_at: env.ledger().timestamp(),
             status: OrderStatus::Active,
-        };
+            executed_amount: None,
+            memo: None,
+            expires_at: None,
+            trigger_source: TriggerSource::OrderAsset,
+            max_confidence_bps: None,
+            limit_price: None,
+            filled_amount: 0,
+            avg_fill_price: 0,
+            fill_or_kill_floor: None,
+            high_confirmations: None,
+            candidate_high: 0,
+            high_checks: 0,
+            high_checked_at: 0,
+        }
+    }
+    
+    fn new_stop_loss_order(
+        env: &Env,
+        owner: &Address,
+        asset: AssetRef,
+        amount: i128,
+        stop_price: i128,
+    ) -> StopLossOrder {
+        Self::validate_amount(env, amount);
         
-        Self::save_order(&env, order_id, &order);
-        Self::add_user_order(&env, &owner, order_id);
+        let current_price = Self::get_current_price(env, &asset);
         
-        log!(&env, "Cross-asset stop created: {} (Cross price: {})", order_id, cross_price);
+        // A sell stop at or above the market would fire immediately
+        if stop_price >= current_price {
+            panic!("Stop price must be below market");
+        }
         
-        order_id
+        Self::new_order(env, owner, asset, amount, stop_price, current_price)
+    }
+    
+    fn trailing_stop_for(price: i128, trailing_percent: u32) -> i128 {
+        price * (100 - trailing_percent as i128) / 100
+    }
+    
+    fn new_trailing_stop_order(
+        env: &Env,
+        owner: &Address,
+        asset: AssetRef,
+        amount: i128,
+        trailing_percent: u32,
+    ) -> StopLossOrder {
+        Self::validate_amount(env, amount);
+        
+        if trailing_percent == 0 || trailing_percent > 50 {
+            panic!("Invalid trailing percent");
+        }
+        
+        let current_price = Self::get_current_price(env, &asset);
+        let stop_price = current_price * (100 - trailing_percent as i128) / 100;
+        
+        StopLossOrder {
+            trailing_percent: Some(trailing_percent),
+            ..Self::new_order(env, owner, asset, amount, stop_price, current_price)
+        }
+    }
+    
+    // Take-profit only: a zero stop price never triggers the stop-loss leg
+    fn new_take_profit_order(
+        env: &Env,
+        owner: &Address,
+        asset: AssetRef,
+        amount: i128,
+        take_profit_price: i128,
+    ) -> StopLossOrder {
+        Self::validate_amount(env, amount);
+        
+        let current_price = Self::get_current_price(env, &asset);
+        
+        if take_profit_price <= current_price {
+            panic!("Invalid price levels");
+        }
+        
+        StopLossOrder {
+            take_profit_price: Some(take_profit_price),
+            ..Self::new_order(env, owner, asset, amount, 0, current_price)
+        }
+    }
+    
+    fn get_next_order_id(env: &Env) -> u64 {
+        let counter: u64 = env.storage()
+            .persistent()
+            .get(&DataKey::OrderCounter)
+            .unwrap_or(0);
+        
+        let next_id = counter + 1;
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderCounter, &next_id);
+        
+        // Extend TTL for counter
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderCounter, 100, MAX_PERSISTENT_TTL);
+        
+        next_id
     }
     
-    // NEW: Get historical price volatility for risk assessment
-    pub fn get_price_volatility(env: Env, asset: Symbol, periods: u32) -> i128 {
+    // Standard deviation and mean of the last `periods` oracle prices, or
+    // None when the oracle has no history for the asset
+    fn get_price_dispersion(env: &Env, asset: &AssetRef, periods: u32) -> Option<(i128, i128)> {
         let oracle_address: Address = env.storage()
             .instance()
             .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
+        
+        let client = ReflectorClient::new(env, &oracle_address);
+        let prices = client.prices(&Self::to_oracle_asset(asset), &periods)?;
+        let count = prices.len();
+        
+        if count == 0 {
+            return None;
+        }
+        
+        // Squared deviations of 7-decimal prices overflow i128 quickly, so
+        // accumulate in I256
+        let mut sum = 0i128;
+        for price_data in prices.iter() {
+            sum += price_data.price;
+        }
+        
+        let mean = sum / count as i128;
+        let mut variance_sum = I256::from_i32(env, 0);
+        
+        for price_data in prices.iter() {
+            let diff = I256::from_i128(env, price_data.price - mean);
+            variance_sum = variance_sum.add(&diff.mul(&diff));
+        }
+        
+        let variance = variance_sum
+            .div(&I256::from_i128(env, count as i128))
+            .to_i128()
+            .unwrap_or_else(|| panic!("Volatility overflow"));
+        let std_dev = (variance as u128).isqrt() as i128;
+        Some((Self::scale_price(&client, std_dev), Self::scale_price(&client, mean)))
+    }
+    
+    // Whether recent prices are steady enough for the order's confidence
+    // threshold. Orders without a threshold are always confident.
+    fn price_is_confident(env: &Env, order: &StopLossOrder) -> bool {
+        let max_confidence_bps = match order.max_confidence_bps {
+            Some(max_confidence_bps) => max_confidence_bps,
+            None => return true,
+        };
+        
+        match Self::get_price_dispersion(env, &order.asset, CONFIDENCE_SAMPLES) {
+            Some((std_dev, mean)) if mean > 0 => std_dev * 10000 / mean <= max_confidence_bps as i128,
+            _ => true,
+        }
+    }
+    
+    // First price within the configured staleness window from the primary
+    // oracle, then each fallback oracle in order
+    fn get_current_price(env: &Env, asset: &AssetRef) -> i128 {
+        let config = Self::get_config(env.clone());
+        let current_time = env.ledger().timestamp();
+        let mut fallbacks = Self::get_fallback_oracles(env.clone()).into_iter();
+        
+        let mut price_info = Self::try_last_price(env, asset);
+        let mut stale = false;
+        loop {
+            match price_info {
+                Some(price_info) if current_time.saturating_sub(price_info.timestamp) <= config.staleness_seconds => {
+                    return price_info.price;
+                },
+                Some(_) => stale = true,
+                None => {},
+            }
+            match fallbacks.next() {
+                Some(oracle_address) => price_info = Self::oracle_last_price(env, &oracle_address, asset),
+                None => break,
+            }
+        }
+        
+        if stale {
+            panic!("Price data is stale");
+        }
+        panic!("Price not available");
+    }
+    
+    fn get_last_price(env: &Env, asset: &AssetRef) -> PriceData {
+        match Self::try_last_price(env, asset) {
+            Some(price_info) => price_info,
+            None => panic!("Price not available"),
+        }
+    }
+    
+    // The primary oracle's last price, or the mock price in sandbox builds
+    fn try_last_price(env: &Env, asset: &AssetRef) -> Option<PriceData> {
+        if cfg!(feature = "sandbox") {
+            if let Some(price_info) = env.storage().persistent().get(&DataKey::MockPrice(asset.clone())) {
+                return Some(price_info);
+            }
+        }
+        
+        let oracle_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
+        Self::oracle_last_price(env, &oracle_address, asset)
+    }
+    
+    // An oracle's last price, or None if it has none or the call fails
+    fn oracle_last_price(env: &Env, oracle_address: &Address, asset: &AssetRef) -> Option<PriceData> {
+        let client = ReflectorClient::new(env, oracle_address);
+        match client.try_lastprice(&Self::to_oracle_asset(asset)) {
+            Ok(Ok(Some(price_info))) => Some(PriceData {
+                price: Self::scale_price(&client, price_info.price),
+                timestamp: price_info.timestamp,
+            }),
+            _ => None,
+        }
+    }
+    
+    // Rescale a price reported by `client` to STELLAR_DECIMALS, the
+    // precision order prices are kept in
+    fn scale_price(client: &ReflectorClient, price: i128) -> i128 {
+        normalize_price(price, client.decimals(), STELLAR_DECIMALS)
+    }
+    
+    fn save_order(env: &Env, order_id: u64, order: &StopLossOrder) {
+        let key = DataKey::Order(order_id);
+        let is_new = !env.storage().persistent().has(&key);
+        
+        env.storage().persistent().set(&key, order);
+        
+        // Add to all orders list if it's a new order
+        if is_new {
+            Self::adjust_order_stat(env, DataKey::ActiveOrderCount, 1);
+            let mut all_order_ids: Vec<u64> = env.storage()
+                .persistent()
+                .get(&DataKey::AllOrderIds)
+                .unwrap_or_else(|| Vec::new(env));
+            
+            all_order_ids.push_back(order_id);
+            env.storage().persistent().set(&DataKey::AllOrderIds, &all_order_ids);
+            
+            // Extend TTL for all orders list
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::AllOrderIds, 100, MAX_PERSISTENT_TTL);
+        }
+        
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    fn get_order(env: &Env, order_id: u64) -> StopLossOrder {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Order(order_id))
+            .unwrap_or_else(|| panic!("Order not found"))
+    }
+    
+    fn add_user_order(env: &Env, user: &Address, order_id: u64) {
+        let mut user_orders = env.storage()
+            .persistent()
+            .get(&DataKey::UserOrders(user.clone()))
+            .unwrap_or(Vec::new(env));
+        
+        if user_orders.len() >= Self::get_config(env.clone()).max_orders_per_user {
+            panic!("Max orders per user exceeded");
+        }
+        
+        user_orders.push_back(order_id);
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserOrders(user.clone()), &user_orders);
+        
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::UserOrders(user.clone()), 100, MAX_PERSISTENT_TTL);
+    }
+    
+    // Drop a closed order from the owner's open-order list so it no longer
+    // counts towards the max orders per user
+    fn remove_user_order(env: &Env, user: &Address, order_id: u64) {
+        let mut user_orders: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::UserOrders(user.clone()))
+            .unwrap_or_else(|| Vec::new(env));
+        
+        if let Some(index) = user_orders.first_index_of(order_id) {
+            user_orders.remove(index);
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserOrders(user.clone()), &user_orders);
+        }
+    }
+    
+    // The order is marked Executed and persisted before any token transfer or
+    // external call, so a re-entrant check_and_execute sees it as closed.
+    // Orders above the max fill size sell one chunk instead and stay Active;
+    // orders whose fill price is below their fill-or-kill floor are cancelled.
+    // An order already closed by an earlier call is left untouched.
+    // Shared trigger check for the spot and TWAP entry points. The order's
+    // own price is the spot price, or the TWAP over `twap_periods` when set;
+    // cross-asset and chandelier stops carry their own price configuration.
+    fn check_order(env: &Env, keeper: &Address, order_id: u64, twap_periods: Option<u32>) -> CheckResult {
+        pausable::when_not_paused(env);
+        
+        let mut order = Self::get_order(env, order_id);
+        
+        if order.status != OrderStatus::Active {
+            return CheckResult::Inactive;
+        }
+        
+        if Self::expire_if_due(env, order_id, &mut order) {
+            return CheckResult::Inactive;
+        }
+        Self::when_order_assets_not_paused(env, &order);
+        
+        if let TriggerSource::Cross(cross_trigger) = &order.trigger_source {
+            return Self::check_cross_trigger(env, keeper, order_id, &order, cross_trigger);
+        }
+        if let TriggerSource::Chandelier(chandelier) = order.trigger_source.clone() {
+            return Self::check_chandelier_trigger(env, keeper, order_id, &mut order, &chandelier);
+        }
+        
+        let current_price = match twap_periods {
+            Some(twap_periods) => Self::get_twap_price(env, &order.asset, twap_periods),
+            None => Self::get_current_price(env, &order.asset),
+        };
+        let mut should_execute = false;
+        let mut execution_reason = "";
+        let stop_before = order.stop_price;
+        
+        // Update trailing stop if applicable
+        Self::refresh_stale_anchor(env, order_id, &mut order);
+        Self::confirm_trailing_high(env, order_id, &mut order, current_price);
+        
+        // Check stop-loss condition
+        if current_price <= order.stop_price && Self::limit_met(env, order_id, &order, current_price) {
+            should_execute = true;
+            execution_reason = "stop-loss triggered";
+        }
+        
+        // Check take-profit condition
+        if let Some(take_profit) = order.take_profit_price {
+            if current_price >= take_profit {
+                should_execute = true;
+                execution_reason = "take-profit triggered";
+            }
+        }
+        
+        if should_execute {
+            Self::mark_eligible(env, order_id);
+        }
+        
+        if should_execute && !Self::price_is_confident(env, &order) {
+            log!(env, "Order {} deferred: recent prices too dispersed", order_id);
+            return CheckResult::NotTriggered;
+        }
+        
+        if should_execute && env.storage().persistent().has(&DataKey::ScaleLevels(order_id)) {
+            return Self::fill_scale_levels(env, keeper, order_id, order, current_price);
+        }
+        
+        if should_execute {
+            log!(env, "Order {} triggered: {}", order_id, execution_reason);
+            Self::execute_order(env, keeper, order_id, current_price)
+        } else if order.stop_price != stop_before {
+            CheckResult::TrailingAdjusted(order.stop_price)
+        } else {
+            CheckResult::NotTriggered
+        }
+    }
+    
+    fn execute_order(env: &Env, keeper: &Address, order_id: u64, execution_price: i128) -> CheckResult {
+        let mut order = Self::get_order(env, order_id);
+        if order.status != OrderStatus::Active {
+            log!(env, "Order {} already closed, not executed", order_id);
+            return CheckResult::Inactive;
+        }
+        
+        // Guaranteed stops fill at the stop however far the market gapped
+        let execution_price = if env.storage().persistent().has(&DataKey::GuaranteePremium(order_id)) {
+            execution_price.max(order.stop_price)
+        } else {
+            execution_price
+        };
+        
+        if order.fill_or_kill_floor.is_some_and(|floor| execution_price < floor) {
+            log!(env, "Order {} killed: fill price {} below floor", order_id, execution_price);
+            env.storage().persistent().remove(&DataKey::ScaleLevels(order_id));
+            env.storage().persistent().remove(&DataKey::EligibleSince(order_id));
+            Self::close_cancelled(env, order_id, &mut order);
+            return CheckResult::Cancelled;
+        }
+        
+        if let Some(max_fill_size) = Self::get_max_fill_size(env.clone()) {
+            if order.amount > max_fill_size && !env.storage().persistent().has(&DataKey::ScaleLevels(order_id)) {
+                Self::fill_chunk(env, keeper, order_id, order, max_fill_size, execution_price);
+                return CheckResult::PartiallyFilled(execution_price);
+            }
+        }
+        
+        let fill_amount = order.amount;
+        order.status = OrderStatus::Executed;
+        Self::record_fill(&mut order, fill_amount, execution_price);
+        Self::save_order(env, order_id, &order);
+        Self::adjust_order_stat(env, DataKey::ActiveOrderCount, -1);
+        Self::adjust_order_stat(env, DataKey::ExecutedOrderCount, 1);
+        Self::remove_user_order(env, &order.owner, order_id);
+        env.storage().persistent().remove(&DataKey::ScaleLevels(order_id));
+        Self::release_guarantee(env, order_id, &order);
+        
+        if let Some(amount_out) = Self::settle(env, keeper, order_id, &order, order.amount, execution_price) {
+            order.executed_amount = Some(order.executed_amount.unwrap_or(0) + amount_out);
+            Self::save_order(env, order_id, &order);
+        }
+        env.storage().persistent().remove(&DataKey::EligibleSince(order_id));
+        
+        Self::record_timeline(env, order_id, OrderEventKind::Executed, execution_price, order.amount);
+        OrderExecuted {
+            order_id,
+            owner: order.owner,
+            asset: order.asset,
+            price: execution_price,
+        }.publish(env);
+        CheckResult::Executed(execution_price)
+    }
+    
+    // Whether a check filled or closed the order
+    fn acted_on(result: &CheckResult) -> bool {
+        matches!(result, CheckResult::Executed(_) | CheckResult::PartiallyFilled(_) | CheckResult::Cancelled)
+    }
+    
+    // Sell `fill_amount` of an order too large to fill at once. The reduced
+    // amount is persisted before settlement; the keeper auction keeps running
+    // so later chunks don't restart at the maximum reward.
+    fn fill_chunk(
+        env: &Env,
+        keeper: &Address,
+        order_id: u64,
+        mut order: StopLossOrder,
+        fill_amount: i128,
+        execution_price: i128,
+    ) {
+        let guaranteed = Self::release_guarantee(env, order_id, &order);
+        order.amount -= fill_amount;
+        Self::record_fill(&mut order, fill_amount, execution_price);
+        Self::save_order(env, order_id, &order);
+        if guaranteed {
+            Self::reserve_guarantee(env, order_id, &order);
+        }
         
-        let client = ReflectorClient::new(&env, &oracle_address);
-        let asset_type = Asset::Other(asset.clone());  // Changed to Other for Symbol type
+        if let Some(amount_out) = Self::settle(env, keeper, order_id, &order, fill_amount, execution_price) {
+            order.executed_amount = Some(order.executed_amount.unwrap_or(0) + amount_out);
+            Self::save_order(env, order_id, &order);
+        }
         
-        // Get historical prices
-        let prices_data = client.prices(&asset_type, &periods);
+        Self::record_timeline(env, order_id, OrderEventKind::PartiallyFilled, execution_price, fill_amount);
+        OrderPartiallyFilled {
+            order_id,
+            owner: order.owner,
+            asset: order.asset,
+            amount: fill_amount,
+            price: execution_price,
+        }.publish(env);
+        
+        log!(env, "Order {} chunk filled: {} sold, {} remaining", order_id, fill_amount, order.amount);
+    }
+    
+    // Add a fill of `amount` at `price` to the order's filled amount and
+    // weighted average fill price
+    fn record_fill(order: &mut StopLossOrder, amount: i128, price: i128) {
+        let filled_amount = order.filled_amount + amount;
+        order.avg_fill_price = (order.avg_fill_price * order.filled_amount + price * amount) / filled_amount;
+        order.filled_amount = filled_amount;
+    }
+    
+    // Raise a trailing order's stop when `price` sets a new high. Saves and
+    // publishes TrailingAdjusted only if the stop actually moves.
+    fn ratchet_trailing_stop(env: &Env, order_id: u64, order: &mut StopLossOrder, price: i128) {
+        let trailing_percent = match order.trailing_percent {
+            Some(trailing_percent) if price > order.highest_price => trailing_percent,
+            _ => return,
+        };
         
-        if prices_data.is_none() {
-            return 0;
+        order.highest_price = price;
+        let new_stop = Self::trailing_stop_for(price, trailing_percent);
+        if new_stop <= order.stop_price {
+            return;
         }
         
-        let prices = prices_data.unwrap();
+        let old_stop = order.stop_price;
+        order.stop_price = new_stop;
+        Self::save_order(env, order_id, order);
+        Self::record_timeline(env, order_id, OrderEventKind::TrailingAdjusted, new_stop, order.amount);
+        TrailingAdjusted {
+            order_id,
+            owner: order.owner.clone(),
+            asset: order.asset.clone(),
+            old_stop_price: old_stop,
+            new_stop_price: new_stop,
+            highest_price: price,
+        }.publish(env);
+    }
+    
+    // Ratchet a trailing order against `price`, or, when it requires
+    // confirmations, track the new high until it has held for that many
+    // checks and then ratchet to the lowest price seen across them
+    fn confirm_trailing_high(env: &Env, order_id: u64, order: &mut StopLossOrder, price: i128) {
+        let confirmations = match order.high_confirmations {
+            Some(confirmations) if order.trailing_percent.is_some() => confirmations,
+            _ => return Self::ratchet_trailing_stop(env, order_id, order, price),
+        };
         
-        // Calculate standard deviation
-        let mut sum = 0i128;
-        let mut count = 0u32;
+        if price <= order.highest_price {
+            // The run of highs is broken
+            if order.high_checks > 0 {
+                order.high_checks = 0;
+                Self::save_order(env, order_id, order);
+            }
+            return;
+        }
         
-        for price_data in prices.iter() {
-            sum += price_data.price;
-            count += 1;
+        // Checks within one ledger count once
+        let now = env.ledger().timestamp();
+        if order.high_checks > 0 && order.high_checked_at == now {
+            return;
         }
         
-        let mean = sum / count as i128;
-        let mut variance_sum = 0i128;
+        order.candidate_high = if order.high_checks == 0 {
+            price
+        } else {
+            order.candidate_high.min(price)
+        };
+        order.high_checks += 1;
+        order.high_checked_at = now;
         
-        for price_data in prices.iter() {
-            let diff = price_data.price - mean;
-            variance_sum += diff * diff;
+        if order.high_checks < confirmations {
+            log!(env, "Order {} high {} held for {}/{} checks", order_id, order.candidate_high, order.high_checks, confirmations);
+            Self::save_order(env, order_id, order);
+            return;
         }
         
-        let volatility = variance_sum / count as i128;
-        
-        log!(&env, "Price volatility over {} periods: {}", periods, volatility);
+        order.high_checks = 0;
+        Self::save_order(env, order_id, order);
+        Self::ratchet_trailing_stop(env, order_id, order, order.candidate_high);
+    }
+    
+    // Add a guaranteed stop's amount * stop_price to its asset's liability
+    fn reserve_guarantee(env: &Env, order_id: u64, order: &StopLossOrder) {
+        Self::adjust_guaranteed_liability(env, order_id, order, 1);
+    }
+    
+    // Remove a guaranteed stop's liability; returns whether it was guaranteed
+    fn release_guarantee(env: &Env, order_id: u64, order: &StopLossOrder) -> bool {
+        Self::adjust_guaranteed_liability(env, order_id, order, -1)
+    }
+    
+    fn adjust_guaranteed_liability(env: &Env, order_id: u64, order: &StopLossOrder, sign: i128) -> bool {
+        if !env.storage().persistent().has(&DataKey::GuaranteePremium(order_id)) {
+            return false;
+        }
         
-        volatility
+        let key = DataKey::GuaranteedLiability(order.asset.clone());
+        let value = order.amount * order.stop_price / PRICE_SCALE;
+        let liability = Self::get_guaranteed_liability(env.clone(), order.asset.clone()) + sign * value;
+        env.storage().persistent().set(&key, &liability.max(0));
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        true
     }
     
-    pub fn cancel_order(env: Env, owner: Address, order_id: u64) {
-        owner.require_auth();
+    // Whether a triggered stop may fill at `price` under its limit price
+    fn limit_met(env: &Env, order_id: u64, order: &StopLossOrder, price: i128) -> bool {
+        match order.limit_price {
+            Some(limit_price) if price < limit_price => {
+                log!(env, "Order {} limit not met: {} < {}", order_id, price, limit_price);
+                false
+            },
+            _ => true,
+        }
+    }
+    
+    // Record when a check first found `order_id` triggered, starting its
+    // keeper auction
+    fn mark_eligible(env: &Env, order_id: u64) {
+        let key = DataKey::EligibleSince(order_id);
+        if !env.storage().persistent().has(&key) {
+            env.storage().persistent().set(&key, &env.ledger().timestamp());
+            env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        }
+    }
+    
+    // Ratchet a trailing order against the recent TWAP when it has gone
+    // unchecked for longer than the anchor refresh gap, so a high reached in
+    // the gap still moves the stop up
+    fn refresh_stale_anchor(env: &Env, order_id: u64, order: &mut StopLossOrder) {
+        if order.trailing_percent.is_none() {
+            return;
+        }
         
-        let mut order = Self::get_order(&env, order_id);
+        let now = env.ledger().timestamp();
+        let key = DataKey::TrailingCheckedAt(order_id);
+        let checked_at: u64 = env.storage().persistent().get(&key).unwrap_or(order.created_at);
+        env.storage().persistent().set(&key, &now);
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
         
-        if order.owner != owner {
-            panic!("Unauthorized");
+        let gap = Self::get_anchor_refresh_gap(env.clone());
+        if gap == 0 || now.saturating_sub(checked_at) <= gap {
+            return;
         }
         
-        if order.status != OrderStatus::Active {
-            panic!("Order not active");
+        if let Some(twap_price) = Self::get_twap(env.clone(), order.asset.clone(), ANCHOR_TWAP_PERIODS) {
+            log!(env, "Order {} unchecked for {}s, refreshing anchor from TWAP {}", order_id, now - checked_at, twap_price);
+            Self::ratchet_trailing_stop(env, order_id, order, twap_price);
+        }
+    }
+    
+    // Sell every scale level at or below `current_price`. Filling the last
+    // level executes the order; otherwise the remaining amount and the next
+    // target are persisted before settlement.
+    fn fill_scale_levels(
+        env: &Env,
+        keeper: &Address,
+        order_id: u64,
+        mut order: StopLossOrder,
+        current_price: i128,
+    ) -> CheckResult {
+        let key = DataKey::ScaleLevels(order_id);
+        let mut levels: Vec<(i128, i128)> = env.storage().persistent().get(&key).unwrap();
+        
+        let mut fill_amount: i128 = 0;
+        while let Some((price, amount)) = levels.first() {
+            if price > current_price {
+                break;
+            }
+            fill_amount += amount;
+            levels.pop_front();
         }
         
-        order.status = OrderStatus::Cancelled;
-        Self::save_order(&env, order_id, &order);
+        let (next_price, _) = match levels.first() {
+            Some(level) => level,
+            None => {
+                log!(env, "Order {} triggered: final take-profit level", order_id);
+                return Self::execute_order(env, keeper, order_id, current_price);
+            }
+        };
         
-        log!(&env, "Order {} cancelled", order_id);
+        order.amount -= fill_amount;
+        order.take_profit_price = Some(next_price);
+        Self::record_fill(&mut order, fill_amount, current_price);
+        Self::save_order(env, order_id, &order);
+        env.storage().persistent().set(&key, &levels);
+        
+        if let Some(amount_out) = Self::settle(env, keeper, order_id, &order, fill_amount, current_price) {
+            order.executed_amount = Some(order.executed_amount.unwrap_or(0) + amount_out);
+            Self::save_order(env, order_id, &order);
+        }
+        // The remaining levels start a new auction when they trigger
+        env.storage().persistent().remove(&DataKey::EligibleSince(order_id));
+        
+        Self::record_timeline(env, order_id, OrderEventKind::PartiallyFilled, current_price, fill_amount);
+        OrderPartiallyFilled {
+            order_id,
+            owner: order.owner,
+            asset: order.asset,
+            amount: fill_amount,
+            price: current_price,
+        }.publish(env);
+        
+        log!(env, "Order {} partially filled: {} sold, {} remaining", order_id, fill_amount, order.amount);
+        CheckResult::PartiallyFilled(current_price)
     }
     
-    pub fn get_user_orders(env: Env, user: Address) -> Vec<u64> {
-        env.storage()
+    // Sell `amount` of the order asset, deducting the protocol fee and keeper
+    // reward, and return the proceeds paid to the owner. The fee is held
+    // here until claim_fees. The owner must have
+    // approved this contract to spend the amount. Settles through the DEX when
+    // one is configured, falling back to the protocol reserve at `price` if
+    // there is no DEX or the swap fails. Panics when neither can fill, which
+    // leaves the order Active. Off-chain assets are not settled.
+    fn settle(
+        env: &Env,
+        keeper: &Address,
+        order_id: u64,
+        order: &StopLossOrder,
+        amount: i128,
+        price: i128,
+    ) -> Option<i128> {
+        let token_address = match &order.asset {
+            AssetRef::Stellar(token_address) => token_address,
+            AssetRef::Other(_) => return None,
+        };
+        
+        let keeper_reward_bps = Self::get_keeper_reward_bps(env.clone(), order_id);
+        let config = Self::get_config(env.clone());
+        let premium_bps: u32 = env.storage()
             .persistent()
-            .get(&DataKey::UserOrders(user))
-            .unwrap_or(Vec::new(&env))
+            .get(&DataKey::GuaranteePremium(order_id))
+            .unwrap_or(0);
+        let fee_amount = (amount * (config.execution_fee_bps + premium_bps) as i128) / 10000;
+        let keeper_reward = (amount * keeper_reward_bps as i128) / 10000;
+        let net_amount = amount - fee_amount - keeper_reward;
+        
+        let contract_address = env.current_contract_address();
+        let token_client = token::Client::new(env, token_address);
+        
+        if fee_amount > 0 {
+            token_client.transfer_from(&contract_address, &order.owner, &contract_address, &fee_amount);
+            Self::accrue_fee(env, token_address, fee_amount);
+        }
+        if keeper_reward > 0 {
+            token_client.transfer_from(&contract_address, &order.owner, &contract_address, &keeper_reward);
+            Self::add_keeper_reward(env, keeper, token_address, keeper_reward);
+        }
+        token_client.transfer_from(&contract_address, &order.owner, &contract_address, &net_amount);
+        
+        // A guaranteed stop must realize its stop value; when the DEX can't,
+        // the reserve pays it instead
+        let min_amount_out = if premium_bps > 0 { net_amount * order.stop_price / PRICE_SCALE } else { 0 };
+        let amount_out = match Self::swap_on_dex(env, token_address, net_amount, min_amount_out, &order.owner) {
+            Some(amount_out) => amount_out,
+            None => Self::fill_from_reserve(env, &order.asset, net_amount, price, &order.owner),
+        };
+        
+        log!(env, "Order {} settled: {} in, {} out, fee {}, keeper reward {}", 
+             order_id, net_amount, amount_out, fee_amount, keeper_reward);
+        Some(amount_out)
     }
     
-    pub fn get_order_details(env: Env, order_id: u64) -> StopLossOrder {
-        Self::get_order(&env, order_id)
+    // Swap `amount` of a token held by this contract on the configured DEX,
+    // which pulls it under a one-off allowance. None without a DEX or when
+    // the swap fails; the tokens then stay here and the allowance is revoked.
+    fn swap_on_dex(env: &Env, token_address: &Address, amount: i128, min_amount_out: i128, to: &Address) -> Option<i128> {
+        let dex_address: Address = env.storage().instance().get(&DataKey::DexAddress)?;
+        let contract_address = env.current_contract_address();
+        let token_client = token::Client::new(env, token_address);
+        let expiration_ledger = env.ledger().sequence();
+        
+        token_client.approve(&contract_address, &dex_address, &amount, &expiration_ledger);
+        match DexClient::new(env, &dex_address).try_swap(token_address, &amount, &min_amount_out, &contract_address, to) {
+            Ok(Ok(amount_out)) => Some(amount_out),
+            _ => {
+                token_client.approve(&contract_address, &dex_address, &0, &expiration_ledger);
+                log!(env, "DEX swap of {} failed", amount);
+                None
+            }
+        }
     }
     
-    // NEW: Get all order IDs
-    pub fn get_all_orders(env: Env) -> Vec<u64> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env))
+    // Sell `amount` of `asset`, already held by this contract, to the
+    // reserve at `price` and pay the proceeds to `to`
+    fn fill_from_reserve(env: &Env, asset: &AssetRef, amount: i128, price: i128, to: &Address) -> i128 {
+        let (reserve_token, payout) = Self::reserve_quote(env, asset, amount, price)
+            .unwrap_or_else(|| panic!("Settlement failed"));
+        token::Client::new(env, &reserve_token).transfer(&env.current_contract_address(), to, &payout);
+        
+        Self::adjust_reserve(env, asset, -payout);
+        let key = DataKey::ReserveProceeds(asset.clone());
+        let proceeds: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+        env.storage().persistent().set(&key, &(proceeds + amount));
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        payout
     }
     
-    // NEW: Get total order count
-    pub fn get_order_count(env: Env) -> u64 {
-        let all_orders: Vec<u64> = env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env));
-        all_orders.len() as u64
+    // Move an asset's reserve balance and the total together
+    fn adjust_reserve(env: &Env, asset: &AssetRef, delta: i128) {
+        let key = DataKey::Reserve(asset.clone());
+        let balance = Self::get_reserve(env.clone(), asset.clone());
+        env.storage().persistent().set(&key, &(balance + delta));
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+        
+        let total = Self::get_total_reserve(env.clone());
+        env.storage().persistent().set(&DataKey::TotalReserve, &(total + delta));
+        env.storage().persistent().extend_ttl(&DataKey::TotalReserve, 100, MAX_PERSISTENT_TTL);
     }
     
-    // NEW: Get orders with pagination
-    pub fn get_orders_paginated(env: Env, start: u64, limit: u32) -> Vec<StopLossOrder> {
-        let all_order_ids: Vec<u64> = env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env));
-        
-        let mut result = Vec::new(&env);
-        let end = ((start + limit as u64) as usize).min(all_order_ids.len() as usize);
-        let start_idx = (start as usize).min(all_order_ids.len() as usize);
-        
-        for i in start_idx..end {
-            if let Some(order_id) = all_order_ids.get(i as u32) {
-                let order = Self::get_order(&env, order_id);
-                result.push_back(order);
-            }
+    // Reserve token and payout for selling `amount` of `asset` to the
+    // reserve at `price`, if the reserve is configured and can cover it
+    fn reserve_quote(env: &Env, asset: &AssetRef, amount: i128, price: i128) -> Option<(Address, i128)> {
+        let reserve_token: Address = env.storage().instance().get(&DataKey::ReserveToken)?;
+        let payout = amount * price / PRICE_SCALE;
+        
+        if Self::get_reserve(env.clone(), asset.clone()) < payout {
+            log!(env, "Reserve cannot cover {} payout", payout);
+            return None;
         }
-        
-        result
+        Some((reserve_token, payout))
     }
     
-    // NEW: Get only active orders
-    pub fn get_active_orders(env: Env) -> Vec<u64> {
-        let all_order_ids: Vec<u64> = env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env));
+    fn accrue_fee(env: &Env, token_address: &Address, amount: i128) {
+        let key = DataKey::AccruedFees(token_address.clone());
+        let accrued = Self::get_accrued_fees(env.clone(), token_address.clone());
+        env.storage().persistent().set(&key, &(accrued + amount));
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    // Charge the flat creation fee for `order_count` new orders, if configured
+    fn collect_creation_fee(env: &Env, owner: &Address, order_count: u32) {
+        let config = Self::get_config(env.clone());
+        let fee_token = match config.fee_token {
+            Some(fee_token) if config.creation_fee > 0 => fee_token,
+            _ => return,
+        };
         
-        let mut active_orders = Vec::new(&env);
-        for order_id in all_order_ids.iter() {
-            let order = Self::get_order(&env, order_id);
-            if order.status == OrderStatus::Active {
-                active_orders.push_back(order_id);
-            }
+        let fee_amount = config.creation_fee * order_count as i128;
+        let token_client = token::Client::new(env, &fee_token);
+        for (fee_recipient, share) in Self::fee_shares(env, fee_amount) {
+            token_client.transfer(owner, &fee_recipient, &share);
         }
         
-        active_orders
+        let collected = Self::get_creation_fees_collected(env.clone()) + fee_amount;
+        env.storage().instance().set(&DataKey::CreationFeesCollected, &collected);
     }
     
-    // NEW: Get orders by status
-    pub fn get_orders_by_status(env: Env, status: OrderStatus) -> Vec<u64> {
-        let all_order_ids: Vec<u64> = env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env));
+    // Split `fee_amount` across the fee recipients by weight. Rounding dust
+    // goes to the first recipient; zero shares are left out.
+    fn fee_shares(env: &Env, fee_amount: i128) -> Vec<(Address, i128)> {
+        let recipients = Self::get_fee_recipients(env.clone());
+        
+        let mut dust = fee_amount;
+        for (_, weight_bps) in recipients.iter() {
+            dust -= fee_amount * weight_bps as i128 / 10000;
+        }
         
-        let mut filtered_orders = Vec::new(&env);
-        for order_id in all_order_ids.iter() {
-            let order = Self::get_order(&env, order_id);
-            if order.status == status {
-                filtered_orders.push_back(order_id);
+        let mut shares = Vec::new(env);
+        for (i, (recipient, weight_bps)) in recipients.iter().enumerate() {
+            let mut share = fee_amount * weight_bps as i128 / 10000;
+            if i == 0 {
+                share += dust;
+            }
+            if share > 0 {
+                shares.push_back((recipient, share));
             }
         }
-        
-        filtered_orders
+        shares
     }
     
-    // Internal helper functions
-    fn get_next_order_id(env: &Env) -> u64 {
-        let counter: u64 = env.storage()
+    fn add_keeper_reward(env: &Env, keeper: &Address, token: &Address, amount: i128) {
+        let key = DataKey::KeeperRewards(keeper.clone());
+        let mut rewards: Map<Address, i128> = env.storage()
             .persistent()
-            .get(&DataKey::OrderCounter)
-            .unwrap_or(0);
+            .get(&key)
+            .unwrap_or_else(|| Map::new(env));
         
-        let next_id = counter + 1;
-        env.storage()
-            .persistent()
-            .set(&DataKey::OrderCounter, &next_id);
+        let current = rewards.get(token.clone()).unwrap_or(0);
+        rewards.set(token.clone(), current + amount);
+        env.storage().persistent().set(&key, &rewards);
         
-        // Extend TTL for counter
+        // Extend TTL
         env.storage()
             .persistent()
-            .extend_ttl(&DataKey::OrderCounter, 100, MAX_PERSISTENT_TTL);
+            .extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    fn adjust_order_stat(env: &Env, key: DataKey, delta: i32) {
+        let count: u32 = env.storage().instance().get(&key).unwrap_or(0);
+        env.storage().instance().set(&key, &count.saturating_add_signed(delta));
+    }
+    
+    fn close_cancelled(env: &Env, order_id: u64, order: &mut StopLossOrder) {
+        order.status = OrderStatus::Cancelled;
+        Self::save_order(env, order_id, order);
+        Self::adjust_order_stat(env, DataKey::ActiveOrderCount, -1);
+        Self::remove_user_order(env, &order.owner, order_id);
+        Self::release_guarantee(env, order_id, order);
+        Self::record_timeline(env, order_id, OrderEventKind::Cancelled, 0, order.amount);
+        
+        OrderCancelled {
+            order_id,
+            owner: order.owner.clone(),
+            asset: order.asset.clone(),
+        }.publish(env);
+    }
+    
+    fn expire_if_due(env: &Env, order_id: u64, order: &mut StopLossOrder) -> bool {
+        match order.expires_at {
+            Some(expires_at) if env.ledger().timestamp() > expires_at => {
+                order.status = OrderStatus::Expired;
+                Self::save_order(env, order_id, order);
+                Self::adjust_order_stat(env, DataKey::ActiveOrderCount, -1);
+                Self::remove_user_order(env, &order.owner, order_id);
+                Self::release_guarantee(env, order_id, order);
+                Self::record_timeline(env, order_id, OrderEventKind::Expired, 0, order.amount);
+                
+                OrderExpired {
+                    order_id,
+                    owner: order.owner.clone(),
+                    asset: order.asset.clone(),
+                }.publish(env);
+                true
+            },
+            _ => false,
+        }
+    }
+    
+    fn publish_order_created(env: &Env, order_id: u64, order: &StopLossOrder) {
+        Self::record_timeline(env, order_id, OrderEventKind::Created, order.stop_price, order.amount);
+        OrderCreated {
+            order_id,
+            owner: order.owner.clone(),
+            asset: order.asset.clone(),
+            stop_price: order.stop_price,
+        }.publish(env);
+    }
+    
+    // Append to the order's audit timeline, dropping the oldest entry after
+    // creation once it is full
+    fn record_timeline(env: &Env, order_id: u64, kind: OrderEventKind, price: i128, amount: i128) {
+        let key = DataKey::OrderTimeline(order_id);
+        let mut timeline: Vec<OrderEvent> = env.storage().persistent().get(&key).unwrap_or_else(|| Vec::new(env));
+        
+        if timeline.len() >= MAX_TIMELINE_EVENTS {
+            timeline.remove(1);
+        }
+        timeline.push_back(OrderEvent {
+            kind,
+            timestamp: env.ledger().timestamp(),
+            price,
+            amount,
+        });
         
-        next_id
+        env.storage().persistent().set(&key, &timeline);
+        env.storage().persistent().extend_ttl(&key, 100, MAX_PERSISTENT_TTL);
+    }
+    
+    fn when_asset_not_paused(env: &Env, asset: &AssetRef) {
+        if Self::is_asset_paused(env.clone(), asset.clone()) {
+            panic!("Asset paused");
+        }
     }
     
-    fn get_current_price(env: &Env, asset: &Symbol) -> i128 {
+    // The order asset and, for cross-asset stops, the trigger asset
+    fn when_order_assets_not_paused(env: &Env, order: &StopLossOrder) {
+        Self::when_asset_not_paused(env, &order.asset);
+        if let TriggerSource::Cross(cross_trigger) = &order.trigger_source {
+            Self::when_asset_not_paused(env, &cross_trigger.trigger_asset);
+        }
+    }
+    
+    fn to_oracle_asset(asset: &AssetRef) -> Asset {
+        match asset {
+            AssetRef::Stellar(address) => Asset::Stellar(address.clone()),
+            AssetRef::Other(symbol) => Asset::Other(symbol.clone()),
+        }
+    }
+    
+    // NEW: Get TWAP price from Reflector oracle
+    fn get_twap_price(env: &Env, asset: &AssetRef, periods: u32) -> i128 {
         let oracle_address: Address = env.storage()
             .instance()
             .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
         
-        let client = ReflectorClient::new(&env, &oracle_address);
-        let asset_type = Asset::Other(asset.clone());  // Changed to Other for Symbol type
+        let client = ReflectorClient::new(env, &oracle_address);
+        let asset_type = Self::to_oracle_asset(asset);
         
-        let price_data = client.lastprice(&asset_type);
+        let twap = client.twap(&asset_type, &periods);
         
-        if price_data.is_none() {
-            panic!("Price not available");
+        if twap.is_none() {
+            panic!("TWAP price not available");
         }
         
-        let price_info = price_data.unwrap();
+        Self::scale_price(&client, twap.unwrap())
+    }
+    
+    fn check_cross_trigger(
+        env: &Env,
+        keeper: &Address,
+        order_id: u64,
+        order: &StopLossOrder,
+        cross_trigger: &CrossTrigger,
+    ) -> CheckResult {
+        let cross_price = Self::get_cross_trigger_price(env, cross_trigger, &order.asset);
+        
+        let triggered = match cross_trigger.direction {
+            TriggerDirection::Above => cross_price >= order.stop_price,
+            TriggerDirection::Below => cross_price <= order.stop_price,
+        };
         
-        // Check if price is not stale (older than 10 minutes)
-        let current_time = env.ledger().timestamp();
-        if current_time - price_info.timestamp > 600 {
-            panic!("Price data is stale");
+        if !triggered {
+            return CheckResult::NotTriggered;
         }
-        
-        price_info.price
+        log!(env, "Order {} triggered: cross-asset trigger at {}", order_id, cross_price);
+        Self::execute_order(env, keeper, order_id, cross_price)
     }
     
-    fn save_order(env: &Env, order_id: u64, order: &StopLossOrder) {
-        let mut orders: Map<u64, StopLossOrder> = env.storage()
-            .persistent()
-            .get(&DataKey::Orders)
-            .unwrap_or(Map::new(&env));
-        
-        orders.set(order_id, order.clone());
-        env.storage().persistent().set(&DataKey::Orders, &orders);
-        
-        // Add to all orders list if it's a new order
-        let mut all_order_ids: Vec<u64> = env.storage()
-            .persistent()
-            .get(&DataKey::AllOrderIds)
-            .unwrap_or(Vec::new(&env));
-        
-        // Check if order_id already exists to avoid duplicates
-        let mut exists = false;
-        for existing_id in all_order_ids.iter() {
-            if existing_id == order_id {
-                exists = true;
-                break;
+    // Move the stop to follow the current rolling high, then execute if the
+    // spot price has fallen to it. Without price history the order waits.
+    fn check_chandelier_trigger(
+        env: &Env,
+        keeper: &Address,
+        order_id: u64,
+        order: &mut StopLossOrder,
+        chandelier: &ChandelierTrigger,
+    ) -> CheckResult {
+        let rolling_high = match Self::get_rolling_high(env, &order.asset, chandelier.lookback_periods) {
+            Some(rolling_high) => rolling_high,
+            None => {
+                log!(env, "Order {} waiting: no price history", order_id);
+                return CheckResult::NotTriggered;
             }
+        };
+        
+        let stop_price = Self::chandelier_stop_for(rolling_high, chandelier.drop_bps);
+        let stop_moved = stop_price != order.stop_price;
+        if stop_moved {
+            order.stop_price = stop_price;
+            order.highest_price = rolling_high;
+            Self::save_order(env, order_id, order);
         }
         
-        if !exists {
-            all_order_ids.push_back(order_id);
-            env.storage().persistent().set(&DataKey::AllOrderIds, &all_order_ids);
-            
-            // Extend TTL for all orders list
-            env.storage()
-                .persistent()
-                .extend_ttl(&DataKey::AllOrderIds, 100, MAX_PERSISTENT_TTL);
+        let current_price = Self::get_current_price(env, &order.asset);
+        if current_price > stop_price {
+            return if stop_moved {
+                CheckResult::TrailingAdjusted(stop_price)
+            } else {
+                CheckResult::NotTriggered
+            };
         }
         
-        // Extend TTL
-        env.storage()
-            .persistent()
-            .extend_ttl(&DataKey::Orders, 100, MAX_PERSISTENT_TTL);
+        log!(env, "Order {} triggered: {} fell {}bps below rolling high {}",
+             order_id, current_price, chandelier.drop_bps, rolling_high);
+        Self::execute_order(env, keeper, order_id, current_price)
     }
     
-    fn get_order(env: &Env, order_id: u64) -> StopLossOrder {
-        let orders: Map<u64, StopLossOrder> = env.storage()
-            .persistent()
-            .get(&DataKey::Orders)
-            .unwrap_or(Map::new(&env));
+    // Highest of the last `periods` oracle prices, if any are available
+    fn get_rolling_high(env: &Env, asset: &AssetRef, periods: u32) -> Option<i128> {
+        let oracle_address: Address = env.storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
         
-        orders.get(order_id).unwrap()
+        let client = ReflectorClient::new(env, &oracle_address);
+        let prices = client.prices(&Self::to_oracle_asset(asset), &periods)?;
+        prices.iter().map(|price_data| price_data.price).max().map(|high| Self::scale_price(&client, high))
     }
     
-    fn add_user_order(env: &Env, user: &Address, order_id: u64) {
-        let mut user_orders = env.storage()
-            .persistent()
-            .get(&DataKey::UserOrders(user.clone()))
-            .unwrap_or(Vec::new(&env));
-        
-        if user_orders.len() >= MAX_ORDERS_PER_USER {
-            panic!("Max orders per user exceeded");
-        }
-        
-        user_orders.push_back(order_id);
-        env.storage()
-            .persistent()
-            .set(&DataKey::UserOrders(user.clone()), &user_orders);
-        
-        // Extend TTL
-        env.storage()
-            .persistent()
-            .extend_ttl(&DataKey::UserOrders(user.clone()), 100, MAX_PERSISTENT_TTL);
+    fn chandelier_stop_for(rolling_high: i128, drop_bps: u32) -> i128 {
+        rolling_high * (10000 - drop_bps as i128) / 10000
     }
     
-    fn execute_order(env: &Env, order_id: u64, execution_price: i128) {
-        let mut order = Self::get_order(&env, order_id);
-        order.status = OrderStatus::Executed;
-        
-        // Calculate and deduct protocol fee
-        let fee_amount = (order.amount * PROTOCOL_FEE_BPS as i128) / 10000;
-        let net_amount = order.amount - fee_amount;
-        
-        // Here you would integrate with DEX to execute the trade
-        // For now, we just mark it as executed
-        
-        Self::save_order(&env, order_id, &order);
-        
-        log!(&env, "Order {} executed at price: {}", order_id, execution_price);
+    fn get_cross_trigger_price(env: &Env, cross_trigger: &CrossTrigger, position_asset: &AssetRef) -> i128 {
+        match cross_trigger.twap_periods {
+            Some(periods) => Self::get_cross_twap(env, &cross_trigger.trigger_asset, position_asset, periods),
+            None => Self::get_cross_price(env, &cross_trigger.trigger_asset, position_asset),
+        }
     }
     
-    // NEW: Get TWAP price from Reflector oracle
-    fn get_twap_price(env: &Env, asset: &Symbol, periods: u32) -> i128 {
+    fn get_cross_twap(env: &Env, base_asset: &AssetRef, quote_asset: &AssetRef, periods: u32) -> i128 {
         let oracle_address: Address = env.storage()
             .instance()
             .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
         
-        let client = ReflectorClient::new(&env, &oracle_address);
-        let asset_type = Asset::Other(asset.clone());  // Changed to Other for Symbol type
+        let client = ReflectorClient::new(env, &oracle_address);
         
-        let twap = client.twap(&asset_type, &periods);
+        let base = Self::to_oracle_asset(base_asset);
+        let quote = Self::to_oracle_asset(quote_asset);
         
-        if twap.is_none() {
-            panic!("TWAP price not available");
+        let cross_twap = client.x_twap(&base, &quote, &periods);
+        
+        if cross_twap.is_none() {
+            panic!("Cross TWAP not available");
         }
         
-        twap.unwrap()
+        Self::scale_price(&client, cross_twap.unwrap())
     }
     
     // NEW: Get cross price between two assets
-    fn get_cross_price(env: &Env, base_asset: &Symbol, quote_asset: &Symbol) -> i128 {
+    fn get_cross_price(env: &Env, base_asset: &AssetRef, quote_asset: &AssetRef) -> i128 {
         let oracle_address: Address = env.storage()
             .instance()
             .get(&DataKey::OracleAddress)
-            .unwrap_or(Address::from_string(&String::from_str(&env, TESTNET_EXTERNAL_ORACLE)));
+            .unwrap_or(Address::from_string(&String::from_str(env, TESTNET_EXTERNAL_ORACLE)));
         
-        let client = ReflectorClient::new(&env, &oracle_address);
+        let client = ReflectorClient::new(env, &oracle_address);
         
-        let base = Asset::Other(base_asset.clone());  // Changed to Other for Symbol type
-        let quote = Asset::Other(quote_asset.clone());  // Changed to Other for Symbol type
+        let base = Self::to_oracle_asset(base_asset);
+        let quote = Self::to_oracle_asset(quote_asset);
         
         let cross_price_data = client.x_last_price(&base, &quote);
         
@@ -701,38 +2884,6 @@ impl StopLossContract {
             panic!("Cross price not available");
         }
         
-        cross_price_data.unwrap().price
+        Self::scale_price(&client, cross_price_data.unwrap().price)
     }
 }
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use soroban_sdk::testutils::{Address as _, Ledger};
-    
-    #[test]
-    fn test_create_stop_loss() {
-        let env = Env::default();
-        let contract_id = env.register_contract(None, StopLossContract);
-        let client = StopLossContractClient::new(&env, &contract_id);
-        
-        let admin = Address::generate(&env);
-        let oracle = Address::generate(&env);
-        let fee_recipient = Address::generate(&env);
-        let user = Address::generate(&env);
-        let asset = Address::generate(&env);
-        
-        client.initialize(&admin, &oracle, &fee_recipient);
-        
-        env.mock_all_auths();
-        
-        let order_id = client.create_stop_loss(
-            &user,
-            &asset,
-            &10_000_000_000, // 1000 tokens
-            &900_000_000,    // Stop at 90
-        );
-        
-        assert_eq!(order_id, 1);
-    }
-}
\ No newline at end of file
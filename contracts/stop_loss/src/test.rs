@@ -1,260 +1,2350 @@
 #![cfg(test)]
 
 use super::*;
+use dex::Dex;
+use mock_reflector::{Asset as MockAsset, MockReflector, MockReflectorClient};
 use soroban_sdk::{
-    testutils::{Address as _, MockAuth, MockAuthInvoke},
-    token, vec, Env, Address
+    contract, contractimpl, symbol_short,
+    testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke},
+    token::{StellarAssetClient, TokenClient},
+    vec, Env, Address, IntoVal, Map, Val
 };
 
-#[test]
-fn test_create_stop_loss_order() {
+// Stand-in release for upgrade tests; any valid contract WASM will do
+const UPGRADE_WASM: &[u8] = include_bytes!("../../testdata/add_u64.wasm");
+
+const XLM_PRICE: i128 = 1_000_000_000; // $100 with 7 decimals
+const KEEPER_REWARD_BPS: u32 = 20; // 0.2%
+const STALENESS_SECONDS: u64 = 600;
+
+// Settlement mock that pulls the input and pays out twice the amount
+#[contract]
+pub struct MockDex;
+
+#[contractimpl]
+impl Dex for MockDex {
+    fn swap(env: Env, asset_in: Address, amount_in: i128, min_amount_out: i128, from: Address, _to: Address) -> i128 {
+        let dex = env.current_contract_address();
+        TokenClient::new(&env, &asset_in).transfer_from(&dex, &from, &dex, &amount_in);
+        if amount_in * 2 < min_amount_out {
+            panic!("Insufficient output");
+        }
+        amount_in * 2
+    }
+}
+
+// Settlement mock whose swaps always revert
+#[contract]
+pub struct FailingDex;
+
+#[contractimpl]
+impl Dex for FailingDex {
+    fn swap(_env: Env, _asset_in: Address, _amount_in: i128, _min_amount_out: i128, _from: Address, _to: Address) -> i128 {
+        panic!("No liquidity");
+    }
+}
+
+struct TestSetup<'a> {
+    env: Env,
+    client: StopLossContractClient<'a>,
+    oracle: MockReflectorClient<'a>,
+    admin: Address,
+    fee_recipient: Address,
+    keeper: Address,
+}
+
+fn setup() -> TestSetup<'static> {
     let env = Env::default();
     env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
+
+    let oracle_id = env.register(MockReflector, ());
+    let oracle = MockReflectorClient::new(&env, &oracle_id);
+
+    let contract_id = env.register(StopLossContract, ());
     let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let asset = Address::generate(&env);
+
+    let admin = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    client.initialize(&admin, &oracle_id, &fee_recipient, &KEEPER_REWARD_BPS, &STALENESS_SECONDS);
+
+    TestSetup { env, client, oracle, admin, fee_recipient, keeper }
+}
+
+fn oracle_asset(asset: &AssetRef) -> MockAsset {
+    match asset {
+        AssetRef::Stellar(address) => MockAsset::Stellar(address.clone()),
+        AssetRef::Other(symbol) => MockAsset::Other(symbol.clone()),
+    }
+}
+
+fn set_price(setup: &TestSetup, asset: &AssetRef, price: i128) {
+    let timestamp = setup.env.ledger().timestamp();
+    setup.oracle.set_price(&oracle_asset(asset), &price, &timestamp);
+}
+
+#[test]
+fn test_create_stop_loss_order() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
     let amount: i128 = 1000000000; // 100 tokens with 7 decimals
     let stop_price: i128 = 500000000; // $50 with 7 decimals
-    
+    set_price(&setup, &asset, XLM_PRICE);
+
     // Create stop loss order
     let order_id = client.create_stop_loss(&owner, &asset, &amount, &stop_price);
-    
+
     assert_eq!(order_id, 1);
-    
+
     // Get order details
-    let order = client.get_order(&order_id);
+    let order = client.get_order_details(&order_id);
     assert_eq!(order.owner, owner);
     assert_eq!(order.asset, asset);
     assert_eq!(order.amount, amount);
     assert_eq!(order.stop_price, stop_price);
+    assert_eq!(order.highest_price, XLM_PRICE);
     assert_eq!(order.status, OrderStatus::Active);
 }
 
+#[test]
+fn test_create_stop_loss_on_stellar_asset() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let asset = AssetRef::Stellar(token_address.clone());
+    StellarAssetClient::new(env, &token_address).mint(&owner, &1000000000);
+    TokenClient::new(env, &token_address).approve(&owner, &client.address, &1000000000, &1000);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.asset, asset);
+    assert_eq!(order.highest_price, XLM_PRICE);
+
+    // Stellar assets are priced through the Stellar variant of the oracle asset
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+}
+
 #[test]
 fn test_create_twap_stop_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let asset = Address::generate(&env);
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
     let amount: i128 = 1000000000;
     let twap_periods: u32 = 5;
-    let stop_percentage: u32 = 9500; // 95% = 5% stop loss
-    
+    let stop_percentage: u32 = 5; // 5% below TWAP
+    setup.oracle.set_twap(&oracle_asset(&asset), &XLM_PRICE);
+
     // Create TWAP stop order
     let order_id = client.create_twap_stop(
-        &owner, 
-        &asset, 
-        &amount, 
+        &owner,
+        &asset,
+        &amount,
         &twap_periods,
         &stop_percentage
     );
-    
+
     assert_eq!(order_id, 1);
-    
+
     // Verify order created
-    let order = client.get_order(&order_id);
+    let order = client.get_order_details(&order_id);
     assert_eq!(order.owner, owner);
     assert_eq!(order.asset, asset);
     assert_eq!(order.amount, amount);
+    assert_eq!(order.stop_price, XLM_PRICE * 95 / 100);
     assert_eq!(order.status, OrderStatus::Active);
 }
 
+#[test]
+#[should_panic(expected = "Stop price must be below market")]
+fn test_stop_at_or_above_market_rejected() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    setup.client.create_stop_loss(&owner, &asset, &1000000000, &XLM_PRICE);
+}
+
 #[test]
 fn test_cancel_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let asset = Address::generate(&env);
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
     let amount: i128 = 1000000000;
     let stop_price: i128 = 500000000;
-    
+    set_price(&setup, &asset, XLM_PRICE);
+
     // Create order
     let order_id = client.create_stop_loss(&owner, &asset, &amount, &stop_price);
-    
+
     // Cancel order
     client.cancel_order(&owner, &order_id);
-    
+
     // Verify order cancelled
-    let order = client.get_order(&order_id);
+    let order = client.get_order_details(&order_id);
     assert_eq!(order.status, OrderStatus::Cancelled);
 }
 
 #[test]
 fn test_update_trailing_stop() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let asset = Address::generate(&env);
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
     let amount: i128 = 1000000000;
-    let stop_price: i128 = 500000000;
-    let trailing_percent: u32 = 500; // 5%
-    
+    let trailing_percent: u32 = 5; // 5%
+    set_price(&setup, &asset, XLM_PRICE);
+
     // Create trailing stop order
     let order_id = client.create_trailing_stop(
         &owner,
-        &asset, 
+        &asset,
         &amount,
-        &stop_price,
         &trailing_percent
     );
-    
-    // Simulate price increase to $60
-    let new_price: i128 = 600000000;
-    client.update_trailing_stop(&order_id, &new_price);
-    
+    assert_eq!(client.get_order_details(&order_id).stop_price, XLM_PRICE * 95 / 100);
+
+    // Simulate price increase to $120
+    let new_price: i128 = 1_200_000_000;
+    set_price(&setup, &asset, new_price);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
     // Verify stop price updated
-    let order = client.get_order(&order_id);
+    let order = client.get_order_details(&order_id);
     let expected_new_stop = new_price * 95 / 100; // 95% of new price
+    assert_eq!(order.highest_price, new_price);
     assert_eq!(order.stop_price, expected_new_stop);
+    assert_eq!(order.status, OrderStatus::Active);
+}
+
+#[test]
+fn test_check_and_execute_detailed() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_trailing_stop(&owner, &asset, &1000000000, &10);
+
+    assert_eq!(client.check_and_execute_detailed(&setup.keeper, &order_id), CheckResult::NotTriggered);
+
+    set_price(&setup, &asset, 1_200_000_000);
+    assert_eq!(
+        client.check_and_execute_detailed(&setup.keeper, &order_id),
+        CheckResult::TrailingAdjusted(1_080_000_000)
+    );
+
+    set_price(&setup, &asset, 1_000_000_000);
+    assert_eq!(
+        client.check_and_execute_detailed(&setup.keeper, &order_id),
+        CheckResult::Executed(1_000_000_000)
+    );
+    assert_eq!(client.check_and_execute_detailed(&setup.keeper, &order_id), CheckResult::Inactive);
+}
+
+#[test]
+fn test_trailing_stop_ratchets_after_sustained_high() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_trailing_stop(&owner, &asset, &1000000000, &10);
+    assert!(client.try_set_high_confirmations(&owner, &order_id, &Some(0)).is_err());
+    client.set_high_confirmations(&owner, &order_id, &Some(3));
+
+    // A one-off spike is ignored once the price falls back
+    set_price(&setup, &asset, 2_000_000_000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    env.ledger().with_mut(|li| li.timestamp += 10);
+    set_price(&setup, &asset, XLM_PRICE);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!((order.stop_price, order.high_checks), (900000000, 0));
+
+    // A high held over three checks; repeats in one ledger don't count
+    for price in [1_200_000_000i128, 1_250_000_000] {
+        env.ledger().with_mut(|li| li.timestamp += 10);
+        set_price(&setup, &asset, price);
+        assert!(!client.check_and_execute(&setup.keeper, &order_id));
+        assert!(!client.check_and_execute(&setup.keeper, &order_id));
+        assert_eq!(client.get_order_details(&order_id).stop_price, 900000000);
+    }
+    env.ledger().with_mut(|li| li.timestamp += 10);
+    set_price(&setup, &asset, 1_210_000_000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.stop_price, 1_080_000_000);
+    assert_eq!(order.highest_price, 1_200_000_000);
+    assert_eq!(order.high_checks, 0);
+}
+
+#[test]
+fn test_current_trailing_stop_is_read_only() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_trailing_stop(&owner, &asset, &1000000000, &5);
+    let initial_stop = XLM_PRICE * 95 / 100;
+
+    // Read at a higher price does not persist anything
+    set_price(&setup, &asset, 1_200_000_000);
+    let preview = client.get_current_trailing_stop(&order_id);
+    assert_eq!(client.get_order_details(&order_id).stop_price, initial_stop);
+
+    // ...and matches the stop after an actual update
+    client.check_and_execute(&setup.keeper, &order_id);
+    assert_eq!(client.get_order_details(&order_id).stop_price, preview);
+
+    // The stop never ratchets down
+    set_price(&setup, &asset, 1_150_000_000);
+    assert_eq!(client.get_current_trailing_stop(&order_id), preview);
+}
+
+#[test]
+fn test_trailing_adjusted_event() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_trailing_stop(&owner, &asset, &1000000000, &5);
+
+    let assert_trail = |old_stop: i128, new_stop: i128, high: i128| {
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(
+            topics,
+            (Symbol::new(env, "order"), Symbol::new(env, "trailing"), order_id, owner.clone()).into_val(env)
+        );
+        let data: Map<Symbol, Val> = data.into_val(env);
+        let field = |name: &str| -> i128 { data.get(Symbol::new(env, name)).unwrap().into_val(env) };
+        assert_eq!(field("old_stop_price"), old_stop);
+        assert_eq!(field("new_stop_price"), new_stop);
+        assert_eq!(field("highest_price"), high);
+    };
+
+    set_price(&setup, &asset, 1_100_000_000);
+    client.check_and_execute(&setup.keeper, &order_id);
+    assert_trail(950_000_000, 1_045_000_000, 1_100_000_000);
+
+    set_price(&setup, &asset, 1_200_000_000);
+    client.check_and_execute(&setup.keeper, &order_id);
+    assert_trail(1_045_000_000, 1_140_000_000, 1_200_000_000);
+
+    // No new high, no event
+    set_price(&setup, &asset, 1_150_000_000);
+    client.check_and_execute(&setup.keeper, &order_id);
+    assert!(env.events().all().is_empty());
+}
+
+#[test]
+fn test_chandelier_stop_follows_rolling_high() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    // 10% below the high of the last 3 prices
+    let order_id = client.create_chandelier_stop(&owner, &asset, &1000000000, &3, &1000);
+    assert_eq!(client.get_order_details(&order_id).stop_price, 900_000_000);
+
+    // New rolling high at $120 lifts the stop to $108
+    set_price(&setup, &asset, 1_200_000_000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.stop_price, 1_080_000_000);
+    assert_eq!(order.highest_price, 1_200_000_000);
+
+    set_price(&setup, &asset, 1_100_000_000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    // $107 is more than 10% off the $120 high
+    set_price(&setup, &asset, 1_070_000_000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_order_timeline() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    let amount = 1000000000;
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_trailing_stop(&owner, &asset, &amount, &5);
+    let created_at = env.ledger().timestamp();
+
+    env.ledger().with_mut(|li| li.timestamp += 60);
+    set_price(&setup, &asset, 1_100_000_000);
+    client.check_and_execute(&setup.keeper, &order_id);
+
+    env.ledger().with_mut(|li| li.timestamp += 60);
+    set_price(&setup, &asset, 1_000_000_000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    let event = |kind, timestamp, price| OrderEvent { kind, timestamp, price, amount };
+    assert_eq!(
+        client.get_order_timeline(&order_id),
+        vec![
+            env,
+            event(OrderEventKind::Created, created_at, 950_000_000),
+            event(OrderEventKind::TrailingAdjusted, created_at + 60, 1_045_000_000),
+            event(OrderEventKind::Executed, created_at + 120, 1_000_000_000),
+        ]
+    );
 }
 
 #[test]
 fn test_get_user_orders() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset1 = AssetRef::Other(symbol_short!("XLM"));
+    let asset2 = AssetRef::Other(symbol_short!("BTC"));
+    set_price(&setup, &asset1, XLM_PRICE);
+    set_price(&setup, &asset2, XLM_PRICE);
+
     // Create multiple orders
     client.create_stop_loss(&owner, &asset1, &1000000000, &500000000);
     client.create_stop_loss(&owner, &asset2, &2000000000, &300000000);
     client.create_stop_loss(&owner, &asset1, &1500000000, &450000000);
-    
+
     // Get user orders
     let orders = client.get_user_orders(&owner);
     assert_eq!(orders.len(), 3);
-    
+
     // Verify all orders belong to owner
     for order_id in orders.iter() {
-        let order = client.get_order(&order_id);
+        let order = client.get_order_details(&order_id);
         assert_eq!(order.owner, owner);
     }
 }
 
+#[test]
+fn test_get_active_orders_detailed() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let first = client.create_stop_loss(&owner, &asset, &1000000000, &500000000);
+    let second = client.create_stop_loss(&owner, &asset, &2000000000, &300000000);
+    let third = client.create_stop_loss(&owner, &asset, &1500000000, &450000000);
+    client.cancel_order(&owner, &second);
+
+    let orders = client.get_active_orders_detailed(&owner, &0, &10);
+    assert_eq!(orders.len(), 2);
+    assert_eq!(orders.get(0).unwrap(), client.get_order_details(&first));
+    assert_eq!(orders.get(1).unwrap(), client.get_order_details(&third));
+
+    let page = client.get_active_orders_detailed(&owner, &1, &1);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().amount, 1500000000);
+    assert_eq!(client.get_active_orders_detailed(&owner, &5, &10).len(), 0);
+}
+
 #[test]
 #[should_panic(expected = "Order not found")]
 fn test_get_nonexistent_order() {
-    let env = Env::default();
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
+    let setup = setup();
+
     // Try to get non-existent order
-    client.get_order(&999);
+    setup.client.get_order_details(&999);
 }
 
 #[test]
-#[should_panic(expected = "Only owner can cancel")]
+#[should_panic(expected = "Unauthorized")]
 fn test_cancel_order_not_owner() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let other = Address::generate(&env);
-    let asset = Address::generate(&env);
-    
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let other = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
     // Create order with owner
     let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &500000000);
-    
+
     // Try to cancel with different address
     client.cancel_order(&other, &order_id);
 }
 
 #[test]
 fn test_cross_asset_stop() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    let base_asset = Address::generate(&env); // BTC
-    let quote_asset = Address::generate(&env); // ETH
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let base_asset = AssetRef::Other(symbol_short!("BTC"));
+    let quote_asset = AssetRef::Other(symbol_short!("ETH"));
     let amount: i128 = 1000000000;
     let stop_ratio: i128 = 150000000; // 15 ETH per BTC with 7 decimals
-    
+    setup.oracle.set_x_price(
+        &oracle_asset(&quote_asset),
+        &oracle_asset(&base_asset),
+        &200000000,
+        &0,
+    );
+
     // Create cross-asset stop order
+    let cross_trigger = CrossTrigger {
+        trigger_asset: quote_asset,
+        direction: TriggerDirection::Below,
+        twap_periods: None,
+    };
     let order_id = client.create_cross_asset_stop(
         &owner,
         &base_asset,
-        &quote_asset,
         &amount,
-        &stop_ratio
+        &stop_ratio,
+        &cross_trigger
     );
-    
+
     assert_eq!(order_id, 1);
-    
+
     // Verify order details
-    let order = client.get_order(&order_id);
+    let order = client.get_order_details(&order_id);
     assert_eq!(order.owner, owner);
     assert_eq!(order.asset, base_asset);
     assert_eq!(order.amount, amount);
+    assert_eq!(order.highest_price, 200000000);
+    assert_eq!(order.trigger_source, TriggerSource::Cross(cross_trigger));
+}
+
+// Creates a cross-asset stop on BTC triggered by the (ETH, BTC) cross price,
+// which starts at 2.0, and returns the order id
+fn create_cross_stop(setup: &TestSetup, direction: TriggerDirection, twap_periods: Option<u32>, trigger_price: i128) -> u64 {
+    let owner = Address::generate(&setup.env);
+    let position = oracle_asset(&AssetRef::Other(symbol_short!("BTC")));
+    let trigger = oracle_asset(&AssetRef::Other(symbol_short!("ETH")));
+    setup.oracle.set_x_price(&trigger, &position, &20000000, &0);
+    setup.oracle.set_x_twap(&trigger, &position, &20000000);
+
+    setup.client.create_cross_asset_stop(
+        &owner,
+        &AssetRef::Other(symbol_short!("BTC")),
+        &1000000000,
+        &trigger_price,
+        &CrossTrigger {
+            trigger_asset: AssetRef::Other(symbol_short!("ETH")),
+            direction,
+            twap_periods,
+        },
+    )
+}
+
+fn set_cross(setup: &TestSetup, spot: i128, twap: i128) {
+    let position = oracle_asset(&AssetRef::Other(symbol_short!("BTC")));
+    let trigger = oracle_asset(&AssetRef::Other(symbol_short!("ETH")));
+    setup.oracle.set_x_price(&trigger, &position, &spot, &0);
+    setup.oracle.set_x_twap(&trigger, &position, &twap);
+}
+
+#[test]
+fn test_cross_asset_stop_down_cross_spot() {
+    let setup = setup();
+    let client = &setup.client;
+    let order_id = create_cross_stop(&setup, TriggerDirection::Below, None, 18000000);
+
+    // TWAP crossing alone doesn't fire a spot trigger
+    set_cross(&setup, 19000000, 17000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    set_cross(&setup, 17500000, 19000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_cross_asset_stop_up_cross_spot() {
+    let setup = setup();
+    let client = &setup.client;
+    let order_id = create_cross_stop(&setup, TriggerDirection::Above, None, 22000000);
+
+    set_cross(&setup, 17000000, 17000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    set_cross(&setup, 22000000, 19000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_cross_asset_stop_down_cross_twap() {
+    let setup = setup();
+    let client = &setup.client;
+    let order_id = create_cross_stop(&setup, TriggerDirection::Below, Some(5), 18000000);
+
+    // Spot wick below the trigger is ignored by a TWAP trigger
+    set_cross(&setup, 15000000, 19000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    set_cross(&setup, 19000000, 17900000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_cross_asset_stop_up_cross_twap() {
+    let setup = setup();
+    let client = &setup.client;
+    let order_id = create_cross_stop(&setup, TriggerDirection::Above, Some(5), 22000000);
+
+    set_cross(&setup, 25000000, 21000000);
+    assert!(!client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+
+    set_cross(&setup, 21000000, 22500000);
+    assert!(client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
 }
 
 #[test]
 fn test_batch_order_creation() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let contract_id = env.register_contract(None, StopLossContract);
-    let client = StopLossContractClient::new(&env, &contract_id);
-    
-    let owner = Address::generate(&env);
-    
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    set_price(&setup, &AssetRef::Other(symbol_short!("XLM")), XLM_PRICE);
+    set_price(&setup, &AssetRef::Other(symbol_short!("BTC")), XLM_PRICE);
+    set_price(&setup, &AssetRef::Other(symbol_short!("ETH")), XLM_PRICE);
+
     // Create batch of orders
     let orders = vec![
-        &env,
+        env,
         OrderParams {
-            asset: Address::generate(&env),
+            asset: AssetRef::Other(symbol_short!("XLM")),
             amount: 1000000000,
             stop_price: 500000000,
             order_type: OrderType::StopLoss,
+            memo: None,
         },
         OrderParams {
-            asset: Address::generate(&env),
+            asset: AssetRef::Other(symbol_short!("BTC")),
             amount: 2000000000,
-            stop_price: 300000000,
+            stop_price: 1300000000,
             order_type: OrderType::TakeProfit,
+            memo: Some(String::from_str(env, "BTC target")),
         },
         OrderParams {
-            asset: Address::generate(&env),
+            asset: AssetRef::Other(symbol_short!("ETH")),
             amount: 1500000000,
             stop_price: 450000000,
             order_type: OrderType::StopLoss,
+            memo: None,
         }
     ];
-    
+
     let order_ids = client.create_batch_orders(&owner, &orders);
-    
+
     assert_eq!(order_ids.len(), 3);
-    
+
     // Verify all orders created correctly
     for (i, order_id) in order_ids.iter().enumerate() {
-        let order = client.get_order(&order_id);
+        let order = client.get_order_details(&order_id);
         assert_eq!(order.owner, owner);
         assert_eq!(order.amount, orders.get(i as u32).unwrap().amount);
     }
-}
\ No newline at end of file
+
+    let take_profit = client.get_order_details(&order_ids.get(1).unwrap());
+    assert_eq!(take_profit.take_profit_price, Some(1300000000));
+    assert_eq!(take_profit.memo, Some(String::from_str(env, "BTC target")));
+}
+
+#[test]
+fn test_stop_loss_triggered() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    // Price above the stop: nothing happens
+    set_price(&setup, &asset, 950000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+
+    // Price drops through the stop
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+
+    // Executed orders are not executed again
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+}
+
+#[test]
+#[should_panic(expected = "Price data is stale")]
+fn test_stale_price_rejected() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    // Last oracle update is now more than 10 minutes old
+    setup.env.ledger().with_mut(|li| li.timestamp += 601);
+    client.check_and_execute(&setup.keeper, &order_id);
+}
+
+#[test]
+fn test_fallback_oracle_used_when_primary_stale() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    let missing = env.register(MockReflector, ());
+    let fallback = MockReflectorClient::new(env, &env.register(MockReflector, ()));
+    assert!(client.try_set_fallback_oracles(&setup.admin, &vec![env, missing.clone(), missing.clone(), missing.clone(), missing.clone()]).is_err());
+    client.set_fallback_oracles(&setup.admin, &vec![env, missing, fallback.address.clone()]);
+
+    // The primary feed stalls; the second fallback has a fresh price
+    env.ledger().with_mut(|li| li.timestamp += 601);
+    fallback.set_price(&oracle_asset(&asset), &890000000, &env.ledger().timestamp());
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).avg_fill_price, 890000000);
+
+    // Every source stale
+    let next_id = client.create_stop_loss(&owner, &asset, &1000000000, &800000000);
+    env.ledger().with_mut(|li| li.timestamp += 601);
+    assert!(client.try_check_and_execute(&setup.keeper, &next_id).is_err());
+}
+
+#[test]
+fn test_fallback_oracle_price_rescaled_to_stellar_decimals() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    let fallback = MockReflectorClient::new(env, &env.register(MockReflector, ()));
+    fallback.set_decimals(&14);
+    client.set_fallback_oracles(&setup.admin, &vec![env, fallback.address.clone()]);
+
+    // $95 from a 14-decimal feed is above the stop; $89 is below it
+    env.ledger().with_mut(|li| li.timestamp += 601);
+    fallback.set_price(&oracle_asset(&asset), &9_500_000_000_000_000, &env.ledger().timestamp());
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    fallback.set_price(&oracle_asset(&asset), &8_900_000_000_000_000, &env.ledger().timestamp());
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).avg_fill_price, 890000000);
+}
+
+#[test]
+fn test_dispersed_prices_defer_execution() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    for _ in 0..4 {
+        set_price(&setup, &asset, XLM_PRICE);
+    }
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.set_order_max_confidence(&owner, &order_id, &Some(100)); // 1%
+
+    // A sudden print below the stop: dispersion is about 4.5%
+    set_price(&setup, &asset, 890000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+
+    // Once the market settles at the lower level the order executes
+    for _ in 0..4 {
+        set_price(&setup, &asset, 890000000);
+    }
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_configurable_staleness_window() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    assert_eq!(client.get_config().staleness_seconds, STALENESS_SECONDS);
+
+    setup.env.ledger().with_mut(|li| li.timestamp += 120);
+    assert_eq!(client.get_price_age(&asset), 120);
+    client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    // A tighter window rejects the same price
+    client.set_staleness_seconds(&setup.admin, &60);
+    assert_eq!(client.get_config().staleness_seconds, 60);
+    assert!(client.try_create_stop_loss(&owner, &asset, &1000000000, &900000000).is_err());
+    assert!(client.try_set_staleness_seconds(&setup.admin, &0).is_err());
+    assert!(client.try_set_staleness_seconds(&owner, &120).is_err());
+    assert_eq!(client.get_config().staleness_seconds, 60);
+}
+
+#[test]
+fn test_update_config() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let config = client.get_config();
+    assert_eq!(config.min_order_amount, MIN_ORDER_AMOUNT);
+    assert_eq!(config.max_orders_per_user, MAX_ORDERS_PER_USER);
+
+    client.update_config(&setup.admin, &Config {
+        execution_fee_bps: 25,
+        min_order_amount: 1_000_000_000,
+        max_orders_per_user: 2,
+        ..config.clone()
+    });
+    assert_eq!(client.get_config().execution_fee_bps, 25);
+    assert!(client.try_create_stop_loss(&owner, &asset, &999_999_999, &900000000).is_err());
+    client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    assert!(client.try_create_stop_loss(&owner, &asset, &1000000000, &900000000).is_err());
+
+    let invalid = [
+        Config { execution_fee_bps: MAX_EXECUTION_FEE_BPS + 1, ..config.clone() },
+        Config { min_order_amount: 0, ..config.clone() },
+        Config { max_orders_per_user: 0, ..config.clone() },
+        Config { max_orders_per_user: MAX_ORDERS_PER_USER_CAP + 1, ..config.clone() },
+        Config { staleness_seconds: 0, ..config.clone() },
+    ];
+    for config in invalid {
+        assert!(client.try_update_config(&setup.admin, &config).is_err());
+    }
+}
+
+#[test]
+fn test_get_twap_preview() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    assert_eq!(client.get_twap(&asset, &5), None);
+
+    setup.oracle.set_twap(&oracle_asset(&asset), &980000000);
+    set_price(&setup, &asset, XLM_PRICE);
+    let twap = client.get_twap(&asset, &5).unwrap();
+    assert_eq!(twap, 980000000);
+
+    // create_twap_stop anchors to the previewed TWAP
+    let order_id = client.create_twap_stop(&owner, &asset, &1000000000, &5, &10);
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.highest_price, twap);
+    assert_eq!(order.stop_price, twap * 90 / 100);
+
+    // No preview once the feed goes stale
+    setup.env.ledger().with_mut(|li| li.timestamp += STALENESS_SECONDS + 1);
+    assert_eq!(client.get_twap(&asset, &5), None);
+}
+
+#[test]
+fn test_stale_trailing_anchor_refreshed_from_twap() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    client.set_anchor_refresh_gap(&setup.admin, &3600);
+    assert_eq!(client.get_anchor_refresh_gap(), 3600);
+
+    // 10% trailing stop anchored at $100
+    let order_id = client.create_trailing_stop(&owner, &asset, &1000000000, &10);
+
+    // Within the gap the recent TWAP is ignored
+    env.ledger().with_mut(|li| li.timestamp += 600);
+    setup.oracle.set_twap(&oracle_asset(&asset), &1_500_000_000);
+    set_price(&setup, &asset, 1_300_000_000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).stop_price, 1_170_000_000);
+
+    // After a day unchecked, XLM rallied to $150 and is back at $130. The
+    // anchor moves to the TWAP high, putting the stop at $135.
+    env.ledger().with_mut(|li| li.timestamp += 86_400);
+    set_price(&setup, &asset, 1_300_000_000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Executed);
+    assert_eq!(order.highest_price, 1_500_000_000);
+    assert_eq!(order.stop_price, 1_350_000_000);
+}
+
+#[test]
+fn test_twap_execution() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    let oracle_asset = oracle_asset(&asset);
+    setup.oracle.set_twap(&oracle_asset, &XLM_PRICE);
+
+    let order_id = client.create_twap_stop(&owner, &asset, &1000000000, &5, &10);
+
+    // TWAP still above the stop
+    setup.oracle.set_twap(&oracle_asset, &950000000);
+    assert!(!client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+
+    // TWAP falls below the stop at 90
+    setup.oracle.set_twap(&oracle_asset, &890000000);
+    assert!(client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_twap_execution_defers_on_dispersed_prices() {
+    let setup = setup();
+    let client = &setup.client;
+
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    let oracle_asset = oracle_asset(&asset);
+    for _ in 0..4 {
+        set_price(&setup, &asset, XLM_PRICE);
+    }
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.set_order_max_confidence(&owner, &order_id, &Some(100)); // 1%
+
+    // The TWAP entry point gets the same deferral as the spot one
+    set_price(&setup, &asset, 890000000);
+    setup.oracle.set_twap(&oracle_asset, &890000000);
+    assert!(!client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+
+    for _ in 0..4 {
+        set_price(&setup, &asset, 890000000);
+    }
+    assert!(client.check_and_execute_twap(&setup.keeper, &order_id, &5));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_execute_order_is_noop_once_executed() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1000000000;
+    // Enough balance and allowance that a second settlement could succeed
+    StellarAssetClient::new(env, &token_address).mint(&owner, &(amount * 2));
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &(amount * 2), &1000);
+
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    let timeline = client.get_order_timeline(&order_id);
+
+    // A second keeper racing into execution after the first has closed it
+    env.as_contract(&client.address, || {
+        StopLossContract::execute_order(env, &setup.keeper, order_id, 880000000);
+    });
+    assert_eq!(client.get_order_details(&order_id), order);
+    assert_eq!(client.get_order_timeline(&order_id), timeline);
+    assert_eq!(token.balance(&owner), amount);
+    assert_eq!(client.get_accrued_fees(&token_address), amount * 10 / 10000);
+}
+
+#[test]
+fn test_execution_settles_through_dex() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_admin = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(token_admin).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1000000000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+
+    let dex_id = env.register(MockDex, ());
+    client.set_dex_address(&setup.admin, &dex_id);
+    assert_eq!(client.get_dex_address(), Some(dex_id.clone()));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    // 0.1% fee and the keeper reward held by the contract, remainder
+    // swapped on the DEX
+    let fee = amount * 10 / 10000;
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    let net = amount - fee - keeper_reward;
+    assert_eq!(client.get_accrued_fees(&token_address), fee);
+    assert_eq!(token.balance(&client.address), fee + keeper_reward);
+    assert_eq!(token.balance(&dex_id), net);
+    assert_eq!(token.balance(&owner), 0);
+
+    // The fee recipient collects the fee
+    assert_eq!(client.claim_fees(&token_address), fee);
+    assert_eq!(token.balance(&setup.fee_recipient), fee);
+    assert_eq!(token.balance(&client.address), keeper_reward);
+    assert_eq!(client.get_accrued_fees(&token_address), 0);
+    assert!(client.try_claim_fees(&token_address).is_err());
+
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Executed);
+    assert_eq!(order.executed_amount, Some(net * 2));
+
+    // Keeper claims the reward
+    assert_eq!(client.get_keeper_rewards(&setup.keeper).get(token_address.clone()), Some(keeper_reward));
+    assert_eq!(client.claim_keeper_rewards(&setup.keeper, &token_address), keeper_reward);
+    assert_eq!(token.balance(&setup.keeper), keeper_reward);
+    assert_eq!(token.balance(&client.address), 0);
+    assert!(client.try_claim_keeper_rewards(&setup.keeper, &token_address).is_err());
+}
+
+#[test]
+fn test_keeper_auction_reward_decays_after_eligibility() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_admin = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(token_admin).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1000000000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+
+    // 1% to the first keeper, falling to 0.2% over 1000 seconds
+    client.set_keeper_auction(&setup.admin, &Some(KeeperAuction {
+        max_reward_bps: 100,
+        min_reward_bps: 20,
+        decay_seconds: 1000,
+    }));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    for _ in 0..4 {
+        set_price(&setup, &asset, XLM_PRICE);
+    }
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    client.set_order_max_confidence(&owner, &order_id, &Some(100));
+    token.approve(&owner, &client.address, &amount, &1000);
+    assert_eq!(client.get_keeper_reward_bps(&order_id), 100);
+
+    // The stop is hit but execution is deferred; the auction starts here
+    set_price(&setup, &asset, 890000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    env.ledger().with_mut(|li| li.timestamp += 250);
+    assert_eq!(client.get_keeper_reward_bps(&order_id), 80);
+    env.ledger().with_mut(|li| li.timestamp += 250);
+    assert_eq!(client.get_keeper_reward_bps(&order_id), 60);
+
+    for _ in 0..4 {
+        set_price(&setup, &asset, 890000000);
+    }
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_keeper_rewards(&setup.keeper).get(token_address), Some(amount * 60 / 10000));
+
+    // Turning the auction off restores the fixed reward
+    client.set_keeper_auction(&setup.admin, &None);
+    assert_eq!(client.get_keeper_reward_bps(&order_id), KEEPER_REWARD_BPS);
+}
+
+#[test]
+fn test_execution_fee_split_across_recipients() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let treasury = Address::generate(env);
+    let insurance = Address::generate(env);
+    let dev_fund = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+
+    let recipients = vec![env, (treasury.clone(), 5000u32), (insurance.clone(), 3000u32), (dev_fund.clone(), 2000u32)];
+    client.set_fee_recipients(&setup.admin, &recipients);
+    assert_eq!(client.get_fee_recipients(), recipients);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    let fee = amount * 10 / 10000;
+    assert_eq!(client.claim_fees(&token_address), fee);
+    assert_eq!(token.balance(&treasury), fee * 50 / 100);
+    assert_eq!(token.balance(&insurance), fee * 30 / 100);
+    assert_eq!(token.balance(&dev_fund), fee * 20 / 100);
+    assert_eq!(token.balance(&setup.fee_recipient), 0);
+}
+
+#[test]
+fn test_fee_recipient_weights_must_sum_to_10000() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let recipients = vec![env, (Address::generate(env), 5000u32), (Address::generate(env), 4000u32)];
+    assert!(client.try_set_fee_recipients(&setup.admin, &recipients).is_err());
+    assert!(client.try_set_fee_recipients(&setup.admin, &Vec::new(env)).is_err());
+    assert_eq!(client.get_fee_recipients(), vec![env, (setup.fee_recipient.clone(), 10000u32)]);
+}
+
+#[test]
+fn test_reserve_fills_when_no_dex() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let funder = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    StellarAssetClient::new(env, &usdc_address).mint(&funder, &100_000_000_000);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    client.set_reserve_token(&setup.admin, &usdc_address);
+    client.fund_reserve(&funder, &asset, &100_000_000_000);
+    assert_eq!(client.get_reserve(&asset), 100_000_000_000);
+
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    // No DEX configured: the reserve buys the net amount at $89
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    let fee = amount * 10 / 10000;
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    let net = amount - fee - keeper_reward;
+    let payout = net * 89;
+    assert_eq!(usdc.balance(&owner), payout);
+    assert_eq!(client.get_reserve(&asset), 100_000_000_000 - payout);
+    assert_eq!(token.balance(&client.address), fee + net + keeper_reward);
+    assert_eq!(token.balance(&owner), 0);
+    assert_eq!(client.get_order_details(&order_id).executed_amount, Some(payout));
+
+    // The admin recovers what the reserve bought and what it has left
+    let treasury = Address::generate(env);
+    assert_eq!(client.get_reserve_proceeds(&asset), net);
+    assert!(client.try_sweep_reserve_proceeds(&owner, &asset, &treasury).is_err());
+    assert_eq!(client.sweep_reserve_proceeds(&setup.admin, &asset, &treasury), net);
+    assert_eq!(token.balance(&treasury), net);
+    assert_eq!(client.get_reserve_proceeds(&asset), 0);
+    assert!(client.try_sweep_reserve_proceeds(&setup.admin, &asset, &treasury).is_err());
+
+    // A funded reserve pins the reserve token
+    let remaining = 100_000_000_000 - payout;
+    assert_eq!(client.get_total_reserve(), remaining);
+    assert!(client.try_set_reserve_token(&setup.admin, &token_address).is_err());
+    assert!(client.try_withdraw_reserve(&owner, &asset, &remaining, &treasury).is_err());
+    assert!(client.try_withdraw_reserve(&setup.admin, &asset, &(remaining + 1), &treasury).is_err());
+    client.withdraw_reserve(&setup.admin, &asset, &remaining, &treasury);
+    assert_eq!(usdc.balance(&treasury), remaining);
+    assert_eq!((client.get_reserve(&asset), client.get_total_reserve()), (0, 0));
+    client.set_reserve_token(&setup.admin, &token_address);
+}
+
+#[test]
+fn test_guaranteed_stop_fills_at_stop_price() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let funder = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    StellarAssetClient::new(env, &usdc_address).mint(&funder, &100_000_000_000);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    client.set_reserve_token(&setup.admin, &usdc_address);
+    client.fund_reserve(&funder, &asset, &100_000_000_000);
+
+    set_price(&setup, &asset, XLM_PRICE);
+    assert!(client.try_create_guaranteed_stop(&owner, &asset, &amount, &900000000, &0).is_err());
+    let order_id = client.create_guaranteed_stop(&owner, &asset, &amount, &900000000, &50);
+    let cancelled_id = client.create_guaranteed_stop(&owner, &asset, &amount, &900000000, &50);
+    token.approve(&owner, &client.address, &amount, &1000);
+    assert_eq!(client.get_guaranteed_liability(&asset), 2 * 90 * amount);
+
+    client.cancel_order(&owner, &cancelled_id);
+    assert_eq!(client.get_guaranteed_liability(&asset), 90 * amount);
+
+    // The reserve behind open guarantees can't be withdrawn
+    let free = 100_000_000_000 - 90 * amount;
+    assert!(client.try_withdraw_reserve(&setup.admin, &asset, &(free + 1), &funder).is_err());
+    client.withdraw_reserve(&setup.admin, &asset, &free, &funder);
+
+    // The market gaps to $80 but the order fills at its $90 stop, with the
+    // 0.5% premium on top of the 0.1% execution fee
+    set_price(&setup, &asset, 800000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    let fee = amount * 60 / 10000;
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    let net = amount - fee - keeper_reward;
+    assert_eq!(client.get_accrued_fees(&token_address), fee);
+    assert_eq!(usdc.balance(&owner), net * 90);
+    assert_eq!(client.get_guaranteed_liability(&asset), 0);
+}
+
+#[test]
+fn test_guaranteed_stop_covers_dex_shortfall() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let funder = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    StellarAssetClient::new(env, &usdc_address).mint(&funder, &100_000_000_000);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    let dex_id = env.register(MockDex, ());
+    client.set_dex_address(&setup.admin, &dex_id);
+    client.set_reserve_token(&setup.admin, &usdc_address);
+    client.fund_reserve(&funder, &asset, &100_000_000_000);
+
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_guaranteed_stop(&owner, &asset, &amount, &900000000, &50);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    // The DEX would pay 2 per token, well under the $90 stop, so the swap
+    // is rejected and the reserve pays the guaranteed value
+    set_price(&setup, &asset, 800000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    let fee = amount * 60 / 10000;
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    let net = amount - fee - keeper_reward;
+    assert_eq!(token.balance(&dex_id), 0);
+    assert_eq!(usdc.balance(&owner), net * 90);
+    assert_eq!(client.get_reserve(&asset), 100_000_000_000 - net * 90);
+    assert_eq!(client.get_order_details(&order_id).executed_amount, Some(net * 90));
+}
+
+#[test]
+fn test_underfunded_reserve_leaves_order_active() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let funder = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    StellarAssetClient::new(env, &usdc_address).mint(&funder, &1_000_000_000);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    client.set_reserve_token(&setup.admin, &usdc_address);
+    client.fund_reserve(&funder, &asset, &1_000_000_000);
+
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    // Nothing can fill the order, so execution fails and it stays open
+    set_price(&setup, &asset, 890000000);
+    assert!(client.try_check_and_execute(&setup.keeper, &order_id).is_err());
+
+    assert_eq!(token.balance(&owner), amount);
+    assert_eq!(client.get_reserve(&asset), 1_000_000_000);
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+}
+
+#[test]
+fn test_reserve_fills_when_dex_swap_fails() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let funder = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let usdc_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let usdc = TokenClient::new(env, &usdc_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    StellarAssetClient::new(env, &usdc_address).mint(&funder, &100_000_000_000);
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    let dex_id = env.register(FailingDex, ());
+    client.set_dex_address(&setup.admin, &dex_id);
+    client.set_reserve_token(&setup.admin, &usdc_address);
+    client.fund_reserve(&funder, &asset, &100_000_000_000);
+
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+
+    // The DEX kept nothing and the reserve bought the net amount at $89
+    let fee = amount * 10 / 10000;
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    let net = amount - fee - keeper_reward;
+    assert_eq!(token.balance(&dex_id), 0);
+    assert_eq!(token.allowance(&client.address, &dex_id), 0);
+    assert_eq!(token.balance(&client.address), fee + net + keeper_reward);
+    assert_eq!(usdc.balance(&owner), net * 89);
+    assert_eq!(client.get_order_details(&order_id).executed_amount, Some(net * 89));
+}
+
+#[test]
+fn test_scaled_take_profit_partial_fills() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 4_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    // 50% at $110, 25% at $120, the rest at $130
+    let levels = vec![
+        env,
+        (1_100_000_000i128, 2_000_000_000i128),
+        (1_200_000_000, 1_000_000_000),
+        (1_300_000_000, 1_000_000_000),
+    ];
+    let order_id = client.create_scaled_take_profit(&owner, &asset, &amount, &levels);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    // First target reached
+    set_price(&setup, &asset, 1_150_000_000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "order"), Symbol::new(env, "partial_fill"), order_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let filled: i128 = data.get(Symbol::new(env, "amount")).unwrap().into_val(env);
+    assert_eq!(filled, 2_000_000_000);
+
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.amount, 2_000_000_000);
+    assert_eq!(order.take_profit_price, Some(1_200_000_000));
+    assert_eq!(token.balance(&owner), 2_000_000_000);
+
+    // Nothing more to sell until the next target
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+
+    // Second target reached
+    set_price(&setup, &asset, 1_250_000_000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.amount, 1_000_000_000);
+    assert_eq!(order.take_profit_price, Some(1_300_000_000));
+    assert_eq!(client.get_scale_levels(&order_id), vec![env, (1_300_000_000i128, 1_000_000_000i128)]);
+    assert_eq!(token.balance(&owner), 1_000_000_000);
+
+    let net_sold = 3_000_000_000 * (10000 - 10 - KEEPER_REWARD_BPS as i128) / 10000;
+    assert_eq!(order.executed_amount, Some(net_sold * 2));
+    assert!(client.get_user_orders(&owner).contains(order_id));
+}
+
+#[test]
+fn test_large_order_fills_in_chunks() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 2_500_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+    assert!(client.try_set_max_fill_size(&setup.admin, &Some(0)).is_err());
+    client.set_max_fill_size(&setup.admin, &Some(1_000_000_000));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+
+    // Each check sells one chunk until the remainder fits
+    set_price(&setup, &asset, 900000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.amount, 1_500_000_000);
+    assert_eq!(order.filled_amount, 1_000_000_000);
+    assert_eq!(token.balance(&owner), 1_500_000_000);
+
+    set_price(&setup, &asset, 800000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.amount, 500_000_000);
+    assert_eq!(order.avg_fill_price, 850000000);
+
+    set_price(&setup, &asset, 700000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Executed);
+    assert_eq!(order.filled_amount, amount);
+    // (1000 * 90 + 1000 * 80 + 500 * 70) / 2500
+    assert_eq!(order.avg_fill_price, 820000000);
+    assert_eq!(token.balance(&owner), 0);
+    assert!(!client.get_user_orders(&owner).contains(order_id));
+}
+
+#[test]
+#[should_panic(expected = "Level amounts must sum to total")]
+fn test_scaled_take_profit_rejects_mismatched_total() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let levels = vec![env, (1_100_000_000i128, 500_000_000i128), (1_200_000_000, 400_000_000)];
+    setup.client.create_scaled_take_profit(&owner, &asset, &1_000_000_000, &levels);
+}
+
+#[test]
+fn test_price_volatility_high_magnitude() {
+    let setup = setup();
+    let client = &setup.client;
+
+    // BTC around $111,579 moving +/- $1,000
+    let btc = AssetRef::Other(symbol_short!("BTC"));
+    for price in [1_115_790_000_000i128, 1_105_790_000_000, 1_125_790_000_000, 1_115_790_000_000] {
+        set_price(&setup, &btc, price);
+    }
+    // Deviations of 0, 1e10, 1e10, 0 -> sqrt(5e19)
+    assert_eq!(client.get_price_volatility(&btc, &4), 7_071_067_811);
+
+    // Squared deviations of 1e38 would overflow an i128 accumulator
+    let big = AssetRef::Other(symbol_short!("BIG"));
+    set_price(&setup, &big, 40_000_000_000_000_000_000);
+    set_price(&setup, &big, 60_000_000_000_000_000_000);
+    assert_eq!(client.get_price_volatility(&big, &2), 10_000_000_000_000_000_000);
+
+    assert_eq!(client.get_price_volatility(&AssetRef::Other(symbol_short!("NONE")), &5), 0);
+}
+
+#[test]
+fn test_creation_fee_instead_of_execution_fee() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1000000000;
+    let creation_fee: i128 = 5_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &(amount + 3 * creation_fee));
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+    client.set_fee_config(&setup.admin, &0, &creation_fee, &Some(token_address.clone()));
+
+    // Creating orders charges the flat fee, one per order in a batch
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    assert_eq!(token.balance(&setup.fee_recipient), creation_fee);
+
+    let params = OrderParams {
+        asset: asset.clone(),
+        amount,
+        stop_price: 900000000,
+        order_type: OrderType::StopLoss,
+        memo: None,
+    };
+    let batch = client.create_batch_orders(&owner, &vec![env, params.clone(), params]);
+    assert_eq!(token.balance(&setup.fee_recipient), 3 * creation_fee);
+    assert_eq!(client.get_creation_fees_collected(), 3 * creation_fee);
+    for batch_id in batch.iter() {
+        client.cancel_order(&owner, &batch_id);
+    }
+
+    // No execution fee is taken
+    token.approve(&owner, &client.address, &amount, &1000);
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let keeper_reward = amount * KEEPER_REWARD_BPS as i128 / 10000;
+    assert_eq!(token.balance(&setup.fee_recipient), 3 * creation_fee);
+    assert_eq!(client.get_order_details(&order_id).executed_amount, Some((amount - keeper_reward) * 2));
+}
+
+#[test]
+#[should_panic(expected = "Invalid creation fee")]
+fn test_creation_fee_requires_token() {
+    let setup = setup();
+    setup.client.set_fee_config(&setup.admin, &10, &5_000_000, &None);
+}
+
+#[test]
+#[should_panic(expected = "Invalid keeper reward")]
+fn test_initialize_rejects_excessive_keeper_reward() {
+    let env = Env::default();
+    let contract_id = env.register(StopLossContract, ());
+    let client = StopLossContractClient::new(&env, &contract_id);
+
+    let address = Address::generate(&env);
+    client.initialize(&address, &address, &address, &501, &STALENESS_SECONDS);
+}
+
+// Try every order creation entry point with `amount`, returning whether each
+// succeeded
+fn try_create_with_amount(setup: &TestSetup, amount: i128) -> [bool; 8] {
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(setup, &asset, XLM_PRICE);
+    setup.oracle.set_twap(&oracle_asset(&asset), &XLM_PRICE);
+    let position = oracle_asset(&AssetRef::Other(symbol_short!("BTC")));
+    let trigger = oracle_asset(&AssetRef::Other(symbol_short!("ETH")));
+    setup.oracle.set_x_price(&trigger, &position, &20000000, &0);
+
+    let params = OrderParams {
+        asset: asset.clone(),
+        amount,
+        stop_price: 900000000,
+        order_type: OrderType::StopLoss,
+        memo: None,
+    };
+    let cross_trigger = CrossTrigger {
+        trigger_asset: AssetRef::Other(symbol_short!("ETH")),
+        direction: TriggerDirection::Below,
+        twap_periods: None,
+    };
+    [
+        client.try_create_stop_loss(&owner, &asset, &amount, &900000000).is_ok(),
+        client.try_create_stop_loss_with_expiry(&owner, &asset, &amount, &900000000, &1000).is_ok(),
+        client.try_create_trailing_stop(&owner, &asset, &amount, &5).is_ok(),
+        client.try_create_oco_order(&owner, &asset, &amount, &900000000, &1100000000).is_ok(),
+        client.try_create_scaled_take_profit(&owner, &asset, &amount, &vec![env, (1100000000i128, amount)]).is_ok(),
+        client.try_create_batch_orders(&owner, &vec![env, params]).is_ok(),
+        client.try_create_twap_stop(&owner, &asset, &amount, &5, &10).is_ok(),
+        client.try_create_cross_asset_stop(
+            &owner, &AssetRef::Other(symbol_short!("BTC")), &amount, &19000000, &cross_trigger,
+        ).is_ok(),
+    ]
+}
+
+#[test]
+fn test_min_order_amount_accepted() {
+    let setup = setup();
+    assert_eq!(try_create_with_amount(&setup, MIN_ORDER_AMOUNT), [true; 8]);
+}
+
+#[test]
+fn test_below_min_order_amount_rejected() {
+    let setup = setup();
+    assert_eq!(try_create_with_amount(&setup, MIN_ORDER_AMOUNT - 1), [false; 8]);
+}
+
+#[test]
+#[should_panic(expected = "Amount too small")]
+fn test_amount_too_small_message() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    setup.client.create_stop_loss(&owner, &asset, &(MIN_ORDER_AMOUNT - 1), &900000000);
+}
+
+#[test]
+#[should_panic(expected = "Amount too large")]
+fn test_double_scaled_amount_rejected() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    // 10^12 tokens scaled twice by 7 decimals
+    let amount = to_scaled(1_000_000_000_000, 0, STELLAR_DECIMALS) * 10_000_000;
+    setup.client.create_stop_loss(&owner, &asset, &amount, &900000000);
+}
+
+#[test]
+fn test_order_lifecycle_events() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    let (contract, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(contract, client.address);
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "order"), Symbol::new(env, "created"), order_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let stop_price: i128 = data.get(Symbol::new(env, "stop_price")).unwrap().into_val(env);
+    assert_eq!(stop_price, 900000000);
+
+    set_price(&setup, &asset, 890000000);
+    client.check_and_execute(&setup.keeper, &order_id);
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "order"), Symbol::new(env, "executed"), order_id, owner.clone()).into_val(env)
+    );
+
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.cancel_order(&owner, &order_id);
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "order"), Symbol::new(env, "cancelled"), order_id, owner).into_val(env)
+    );
+}
+
+#[test]
+#[should_panic(expected = "Contract paused")]
+fn test_pause_blocks_order_creation() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    setup.client.pause(&setup.admin);
+    assert!(setup.client.is_paused());
+
+    setup.client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+}
+
+#[test]
+#[should_panic(expected = "Contract paused")]
+fn test_pause_blocks_execution() {
+    let setup = setup();
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = setup.client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    setup.client.pause(&setup.admin);
+    set_price(&setup, &asset, 890000000);
+    setup.client.check_and_execute(&setup.keeper, &order_id);
+}
+
+#[test]
+fn test_cancel_and_unpause_while_paused() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    client.pause(&setup.admin);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "contract"), Symbol::new(env, "paused")).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let paused: bool = data.get(Symbol::new(env, "paused")).unwrap().into_val(env);
+    assert!(paused);
+
+    // Users can still exit while paused
+    client.cancel_order(&owner, &order_id);
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Cancelled);
+
+    client.unpause(&setup.admin);
+    assert!(!client.is_paused());
+    client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+}
+
+#[test]
+fn test_get_oco_levels() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let oco_id = client.create_oco_order(&owner, &asset, &1000000000, &900000000, &1200000000);
+    let levels = client.get_oco_levels(&oco_id);
+    assert_eq!((levels.stop_price, levels.take_profit_price), (900000000, 1200000000));
+    assert_eq!((levels.stop_distance_bps, levels.take_profit_distance_bps), (1000, 2000));
+
+    // Distances follow the price; the levels don't
+    set_price(&setup, &asset, 1_100_000_000);
+    let levels = client.get_oco_levels(&oco_id);
+    assert_eq!((levels.stop_price, levels.take_profit_price), (900000000, 1200000000));
+    assert_eq!((levels.stop_distance_bps, levels.take_profit_distance_bps), (1818, 909));
+
+    // Scaled take-profits carry a target but no stop
+    let stop_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    let levels = vec![&setup.env, (1200000000, 1000000000)];
+    let take_profit_id = client.create_scaled_take_profit(&owner, &asset, &1000000000, &levels);
+    assert!(client.try_get_oco_levels(&stop_id).is_err());
+    assert!(client.try_get_oco_levels(&take_profit_id).is_err());
+}
+
+#[test]
+fn test_asset_pause_only_blocks_that_asset() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let xlm = AssetRef::Other(symbol_short!("XLM"));
+    let btc = AssetRef::Other(symbol_short!("BTC"));
+    set_price(&setup, &xlm, XLM_PRICE);
+    set_price(&setup, &btc, 600_000_000_000);
+    let xlm_order = client.create_stop_loss(&owner, &xlm, &1000000000, &900000000);
+    let btc_order = client.create_stop_loss(&owner, &btc, &1000000000, &550_000_000_000);
+    let cancel_order = client.create_stop_loss(&owner, &xlm, &1000000000, &900000000);
+
+    client.set_asset_paused(&setup.admin, &xlm, &true);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "asset"), Symbol::new(env, "paused"), xlm.clone()).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let paused: bool = data.get(Symbol::new(env, "paused")).unwrap().into_val(env);
+    assert!(paused);
+    assert!(client.is_asset_paused(&xlm));
+    assert!(!client.is_asset_paused(&btc));
+
+    // Both stops are hit, but only the BTC one may execute
+    set_price(&setup, &xlm, 800000000);
+    set_price(&setup, &btc, 500_000_000_000);
+    assert!(client.try_check_and_execute(&setup.keeper, &xlm_order).is_err());
+    assert!(client.check_and_execute(&setup.keeper, &btc_order));
+    assert!(client.try_create_stop_loss(&owner, &xlm, &1000000000, &700000000).is_err());
+
+    // Owners can still exit a paused asset
+    client.cancel_order(&owner, &cancel_order);
+    assert_eq!(client.get_order_details(&cancel_order).status, OrderStatus::Cancelled);
+
+    client.set_asset_paused(&setup.admin, &xlm, &false);
+    assert!(!client.is_asset_paused(&xlm));
+    assert!(client.check_and_execute(&setup.keeper, &xlm_order));
+}
+
+#[test]
+fn test_reset_order_counter() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    assert_eq!(client.get_order_counter(), 0);
+    client.reset_order_counter(&setup.admin);
+    assert!(client.try_reset_order_counter(&owner).is_err());
+
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    assert_eq!(order_id, 1);
+    assert_eq!(client.get_order_counter(), 1);
+
+    // Ids must not be reused once orders exist
+    assert!(client.try_reset_order_counter(&setup.admin).is_err());
+    assert_eq!(client.get_order_counter(), 1);
+}
+
+#[test]
+#[cfg(feature = "sandbox")]
+fn test_mock_price_drives_execution() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    // The oracle still reports $100; the mock price takes precedence
+    let other = Address::generate(env);
+    assert!(client.try_set_mock_price(&other, &asset, &850000000, &env.ledger().timestamp()).is_err());
+    client.set_mock_price(&setup.admin, &asset, &850000000, &env.ledger().timestamp());
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+#[cfg(not(feature = "sandbox"))]
+fn test_mock_price_rejected_outside_sandbox() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    assert!(client.try_set_mock_price(&setup.admin, &asset, &850000000, &env.ledger().timestamp()).is_err());
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+}
+
+#[test]
+fn test_pause_requires_admin() {
+    let setup = setup();
+    let other = Address::generate(&setup.env);
+
+    setup.env.mock_auths(&[MockAuth {
+        address: &other,
+        invoke: &MockAuthInvoke {
+            contract: &setup.client.address,
+            fn_name: "pause",
+            args: (&other,).into_val(&setup.env),
+            sub_invokes: &[],
+        },
+    }]);
+    assert!(setup.client.try_pause(&other).is_err());
+    assert!(setup.client.try_pause(&setup.admin).is_err());
+    assert!(!setup.client.is_paused());
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let setup = setup();
+    let client = &setup.client;
+    let new_admin = Address::generate(&setup.env);
+
+    assert!(client.try_propose_admin(&new_admin, &new_admin).is_err());
+    client.propose_admin(&setup.admin, &new_admin);
+    assert_eq!(client.get_admin(), setup.admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+
+    assert!(client.try_accept_admin(&setup.admin).is_err());
+    client.accept_admin(&new_admin);
+    assert_eq!(setup.env.auths()[0].0, new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+}
+
+#[test]
+fn test_replace_order() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    let new_order_id = client.replace_order(&owner, &order_id, &OrderParams {
+        asset: asset.clone(),
+        amount: 2000000000,
+        stop_price: 950000000,
+        order_type: OrderType::StopLoss,
+        memo: None,
+    });
+
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Cancelled);
+    let order = client.get_order_details(&new_order_id);
+    assert_eq!(order.status, OrderStatus::Active);
+    assert_eq!(order.amount, 2000000000);
+    assert_eq!(order.stop_price, 950000000);
+    assert_eq!(client.get_user_orders(&owner), vec![env, new_order_id]);
+}
+
+#[test]
+fn test_failed_replace_keeps_original_order() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    // Take-profit below the current price cannot be created
+    let result = client.try_replace_order(&owner, &order_id, &OrderParams {
+        asset: asset.clone(),
+        amount: 1000000000,
+        stop_price: 900000000,
+        order_type: OrderType::TakeProfit,
+        memo: None,
+    });
+    assert!(result.is_err());
+
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+    assert_eq!(client.get_user_orders(&owner), vec![env, order_id]);
+    assert_eq!(client.get_order_count(), 1);
+}
+
+#[test]
+fn test_cancel_orders_before_cutoff() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+
+    let mut old_orders = Vec::new(env);
+    for _ in 0..3 {
+        set_price(&setup, &asset, XLM_PRICE);
+        old_orders.push_back(client.create_stop_loss(&owner, &asset, &1000000000, &900000000));
+        env.ledger().with_mut(|li| li.timestamp += 100);
+    }
+
+    let cutoff = env.ledger().timestamp();
+    env.ledger().with_mut(|li| li.timestamp += 1000);
+    set_price(&setup, &asset, XLM_PRICE);
+    let recent = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    assert_eq!(client.cancel_orders_before(&owner, &cutoff), 3);
+    for order_id in old_orders.iter() {
+        assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Cancelled);
+    }
+    assert_eq!(client.get_order_details(&recent).status, OrderStatus::Active);
+    assert_eq!(client.get_user_orders(&owner), vec![env, recent]);
+    assert_eq!(client.cancel_orders_before(&owner, &cutoff), 0);
+}
+
+#[test]
+fn test_set_order_memo() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    assert_eq!(client.get_order_details(&order_id).memo, None);
+
+    let memo = String::from_str(env, "hedge for Q2");
+    client.set_order_memo(&owner, &order_id, &Some(memo.clone()));
+    assert_eq!(client.get_order_details(&order_id).memo, Some(memo));
+
+    client.set_order_memo(&owner, &order_id, &None);
+    assert_eq!(client.get_order_details(&order_id).memo, None);
+}
+
+#[test]
+#[should_panic(expected = "Memo too long")]
+fn test_set_order_memo_too_long() {
+    let setup = setup();
+    let env = &setup.env;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = setup.client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    let memo = String::from_str(env, "this memo is far too long to be stored on chain for a single order");
+    setup.client.set_order_memo(&owner, &order_id, &Some(memo));
+}
+
+#[test]
+fn test_protocol_var() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    let other_asset = AssetRef::Other(symbol_short!("BTC"));
+    set_price(&setup, &asset, XLM_PRICE);
+    set_price(&setup, &other_asset, XLM_PRICE);
+    let amount: i128 = 1000000000;
+
+    // Stop 1% below the price: weight 4/5
+    client.create_stop_loss(&owner, &asset, &amount, &990000000);
+    // Stop 4% below the price: weight 1/5
+    client.create_stop_loss(&owner, &asset, &amount, &960000000);
+    // Far from triggering
+    client.create_stop_loss(&owner, &asset, &amount, &500000000);
+    // Take-profit 2.5% above the price: weight 1/2
+    client.create_oco_order(&owner, &asset, &amount, &500000000, &1025000000);
+    // Near trigger but on another asset
+    client.create_stop_loss(&owner, &other_asset, &amount, &990000000);
+    // Near trigger but cancelled
+    let cancelled = client.create_stop_loss(&owner, &asset, &amount, &990000000);
+    client.cancel_order(&owner, &cancelled);
+
+    let notional = amount * XLM_PRICE;
+    let expected = notional * 4 / 5 + notional / 5 + notional / 2;
+    assert_eq!(client.get_protocol_var(&asset), expected);
+}
+
+#[test]
+fn test_expired_order_not_executed() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let expires_at = setup.env.ledger().timestamp() + 3600;
+    let order_id = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &expires_at);
+    assert_eq!(client.get_order_details(&order_id).expires_at, Some(expires_at));
+
+    // Price crashes after expiry: the order expires instead of executing
+    setup.env.ledger().with_mut(|li| li.timestamp = expires_at + 1);
+    set_price(&setup, &asset, 500000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Expired);
+}
+
+#[test]
+fn test_order_stats() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let expires_at = setup.env.ledger().timestamp() + 3600;
+    let expiring = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &expires_at);
+    let executed = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    let cancelled = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.create_stop_loss(&owner, &asset, &1000000000, &800000000);
+    assert_eq!((client.get_active_order_count(), client.get_executed_count()), (4, 0));
+    assert_eq!(client.get_total_orders(), 4);
+
+    client.cancel_order(&owner, &cancelled);
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &executed));
+    assert!(!client.check_and_execute(&setup.keeper, &executed));
+    assert_eq!((client.get_active_order_count(), client.get_executed_count()), (2, 1));
+
+    setup.env.ledger().with_mut(|li| li.timestamp = expires_at + 1);
+    set_price(&setup, &asset, XLM_PRICE);
+    assert!(client.purge_expired(&expiring));
+    assert_eq!((client.get_active_order_count(), client.get_executed_count()), (1, 1));
+    assert_eq!(client.get_total_orders(), 4);
+}
+
+#[test]
+fn test_purge_expired() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let expires_at = setup.env.ledger().timestamp() + 3600;
+    let order_id = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &expires_at);
+    let open_order = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    assert!(!client.purge_expired(&order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+
+    setup.env.ledger().with_mut(|li| li.timestamp = expires_at + 1);
+    assert!(client.purge_expired(&order_id));
+    assert!(!client.purge_expired(&open_order));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Expired);
+    assert_eq!(client.get_order_details(&open_order).status, OrderStatus::Active);
+}
+
+#[test]
+fn test_modify_order() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let owner = Address::generate(env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+
+    client.modify_order(&owner, &order_id, &Some(950000000), &Some(2000000000));
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(env, "order"), Symbol::new(env, "modified"), order_id, owner.clone()).into_val(env)
+    );
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let amount: i128 = data.get(Symbol::new(env, "amount")).unwrap().into_val(env);
+    assert_eq!(amount, 2000000000);
+    let order = client.get_order_details(&order_id);
+    assert_eq!((order.stop_price, order.amount), (950000000, 2000000000));
+
+    // Same validation as creation
+    assert!(client.try_modify_order(&owner, &order_id, &Some(XLM_PRICE), &None).is_err());
+    assert!(client.try_modify_order(&owner, &order_id, &None, &Some(1)).is_err());
+    let other = Address::generate(env);
+    assert!(client.try_modify_order(&other, &order_id, &None, &Some(3000000000)).is_err());
+
+    // A trailing order re-anchors at the current price
+    let trailing_id = client.create_trailing_stop(&owner, &asset, &1000000000, &10);
+    set_price(&setup, &asset, 1_200_000_000);
+    client.modify_order(&owner, &trailing_id, &Some(1_000_000_000), &None);
+    let trailing = client.get_order_details(&trailing_id);
+    assert_eq!((trailing.stop_price, trailing.highest_price), (1_000_000_000, 1_200_000_000));
+
+    client.cancel_order(&owner, &order_id);
+    assert!(client.try_modify_order(&owner, &order_id, &Some(900000000), &None).is_err());
+}
+
+#[test]
+fn test_stop_limit_waits_for_limit() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    assert!(client.try_create_stop_limit(&owner, &asset, &1000000000, &900000000, &950000000).is_err());
+    let order_id = client.create_stop_limit(&owner, &asset, &1000000000, &900000000, &850000000);
+    assert_eq!(client.get_order_details(&order_id).limit_price, Some(850000000));
+
+    // Gapped through the limit: the order stays open
+    set_price(&setup, &asset, 800000000);
+    assert!(!client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Active);
+
+    // Recovers into the stop-limit band
+    set_price(&setup, &asset, 870000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    assert_eq!(client.get_order_details(&order_id).status, OrderStatus::Executed);
+}
+
+#[test]
+fn test_fill_or_kill_floor_cancels_poor_fill() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+
+    let owner = Address::generate(env);
+    let token_address = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+    let token = TokenClient::new(env, &token_address);
+    let amount: i128 = 1_000_000_000;
+    StellarAssetClient::new(env, &token_address).mint(&owner, &amount);
+    client.set_dex_address(&setup.admin, &env.register(MockDex, ()));
+
+    let asset = AssetRef::Stellar(token_address.clone());
+    set_price(&setup, &asset, XLM_PRICE);
+    let order_id = client.create_stop_loss(&owner, &asset, &amount, &900000000);
+    token.approve(&owner, &client.address, &amount, &1000);
+    assert!(client.try_set_fill_or_kill_floor(&owner, &order_id, &Some(0)).is_err());
+    client.set_fill_or_kill_floor(&owner, &order_id, &Some(850000000));
+
+    // Gapped below the floor: cancelled, nothing sold
+    set_price(&setup, &asset, 800000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Cancelled);
+    assert_eq!(order.filled_amount, 0);
+    assert_eq!(token.balance(&owner), amount);
+    assert!(!client.get_user_orders(&owner).contains(order_id));
+}
+
+#[test]
+fn test_fill_or_kill_floor_fills_above_floor() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let order_id = client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.set_fill_or_kill_floor(&owner, &order_id, &Some(850000000));
+
+    set_price(&setup, &asset, 870000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+    let order = client.get_order_details(&order_id);
+    assert_eq!(order.status, OrderStatus::Executed);
+    assert_eq!(order.avg_fill_price, 870000000);
+}
+
+#[test]
+fn test_extend_order_expiry() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    let expires_at = setup.env.ledger().timestamp() + 3600;
+    let order_id = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &expires_at);
+    client.extend_order_expiry(&owner, &order_id, &(expires_at + 3600));
+    assert_eq!(client.get_order_details(&order_id).expires_at, Some(expires_at + 3600));
+
+    // Never earlier
+    assert!(client.try_extend_order_expiry(&owner, &order_id, &expires_at).is_err());
+    assert_eq!(client.get_order_details(&order_id).expires_at, Some(expires_at + 3600));
+
+    // The original expiry has passed but the extended one hasn't
+    setup.env.ledger().with_mut(|li| li.timestamp = expires_at + 1);
+    set_price(&setup, &asset, 890000000);
+    assert!(client.check_and_execute(&setup.keeper, &order_id));
+}
+
+#[test]
+fn test_orders_expiring_within() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+    let now = setup.env.ledger().timestamp();
+
+    let soon = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &(now + 3600));
+    let cancelled = client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &(now + 3600));
+    client.create_stop_loss_with_expiry(&owner, &asset, &1000000000, &900000000, &(now + 86_400));
+    client.create_stop_loss(&owner, &asset, &1000000000, &900000000);
+    client.cancel_order(&owner, &cancelled);
+
+    assert_eq!(client.get_orders_expiring_within(&owner, &7200), vec![&setup.env, soon]);
+    assert_eq!(client.get_orders_expiring_within(&owner, &600).len(), 0);
+    assert_eq!(client.get_orders_expiring_within(&owner, &86_400).len(), 2);
+}
+
+#[test]
+fn test_closed_orders_free_user_slots() {
+    let setup = setup();
+    let client = &setup.client;
+    let owner = Address::generate(&setup.env);
+    let asset = AssetRef::Other(symbol_short!("XLM"));
+    set_price(&setup, &asset, XLM_PRICE);
+
+    // Fill the per-user cap
+    let mut order_ids = Vec::new(&setup.env);
+    for _ in 0..MAX_ORDERS_PER_USER {
+        order_ids.push_back(client.create_stop_loss(&owner, &asset, &1000000000, &500000000));
+    }
+    assert_eq!(client.get_user_orders(&owner).len(), MAX_ORDERS_PER_USER);
+    assert!(client.try_create_stop_loss(&owner, &asset, &1000000000, &500000000).is_err());
+
+    // Cancelling frees a slot
+    let cancelled = order_ids.get(0).unwrap();
+    client.cancel_order(&owner, &cancelled);
+    let user_orders = client.get_user_orders(&owner);
+    assert_eq!(user_orders.len(), MAX_ORDERS_PER_USER - 1);
+    assert!(!user_orders.contains(cancelled));
+    client.create_stop_loss(&owner, &asset, &1000000000, &500000000);
+
+    // Executing frees a slot too
+    client.cancel_order(&owner, &order_ids.get(1).unwrap());
+    let executed = client.create_stop_loss(&owner, &asset, &1000000000, &990000000);
+    assert!(client.try_create_stop_loss(&owner, &asset, &1000000000, &500000000).is_err());
+    set_price(&setup, &asset, 980000000);
+    assert!(client.check_and_execute(&setup.keeper, &executed));
+    assert!(!client.get_user_orders(&owner).contains(executed));
+    client.create_stop_loss(&owner, &asset, &1000000000, &500000000);
+    assert_eq!(client.get_user_orders(&owner).len(), MAX_ORDERS_PER_USER);
+}
+
+#[test]
+fn test_version() {
+    let setup = setup();
+    assert_eq!(setup.client.version(), CONTRACT_VERSION);
+    assert_eq!(setup.client.version(), 2);
+    assert_eq!(
+        setup.client.build_info(),
+        String::from_str(&setup.env, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")))
+    );
+}
+
+#[test]
+fn test_upgrade() {
+    let setup = setup();
+    let env = &setup.env;
+    let client = &setup.client;
+    let admin = setup.admin.clone();
+    let other = Address::generate(env);
+    let new_wasm_hash = env.deployer().upload_contract_wasm(UPGRADE_WASM);
+
+    assert!(client.try_upgrade(&other, &new_wasm_hash).is_err());
+
+    client.upgrade(&admin, &new_wasm_hash);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, (Symbol::new(env, "contract"), Symbol::new(env, "upgraded")).into_val(env));
+    let data: Map<Symbol, Val> = data.into_val(env);
+    let from_version: u32 = data.get(Symbol::new(env, "from_version")).unwrap().into_val(env);
+    let wasm_hash: BytesN<32> = data.get(Symbol::new(env, "new_wasm_hash")).unwrap().into_val(env);
+    assert_eq!((from_version, wasm_hash), (CONTRACT_VERSION, new_wasm_hash));
+
+    // The same contract id now runs the new code
+    let sum: u64 = env.invoke_contract(&client.address, &Symbol::new(env, "add"), vec![env, 1u64.into_val(env), 2u64.into_val(env)]);
+    assert_eq!(sum, 3);
+}
+
+#[test]
+fn test_is_admin() {
+    let setup = setup();
+    let user = Address::generate(&setup.env);
+
+    assert_eq!(setup.client.get_admin(), setup.admin);
+    assert!(setup.client.is_admin(&setup.admin));
+    assert!(!setup.client.is_admin(&user));
+}
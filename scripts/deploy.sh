@@ -71,7 +71,9 @@ initialize_contracts() {
         initialize \
         --admin ${ADMIN_ADDRESS} \
         --oracle_address ${REFLECTOR_ORACLE} \
-        --fee_recipient ${FEE_RECIPIENT}
+        --fee_recipient ${FEE_RECIPIENT} \
+        --keeper_reward_bps ${KEEPER_REWARD_BPS:-20} \
+        --staleness_seconds ${STALENESS_SECONDS:-600}
     
     # Initialize execution engine
     stellar contract invoke \